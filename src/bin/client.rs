@@ -1,11 +1,15 @@
-use std::fmt::Formatter;
+use std::path::PathBuf;
 use std::ops::RangeInclusive;
 use std::time::Duration;
+use bookish_eureka::api::bill::{BillItems, GetBillParams, GetBillResponse, OrderItem, PostBillItemsRequest};
+use bookish_eureka::api::export::{ExportFormat, ImportMenuParams};
+use bookish_eureka::api::item::Item;
+use bookish_eureka::api::payment::{PaymentMethod, PostPaymentRequest};
+use bookish_eureka::api::table::{PatchTableParams, Table};
+use bookish_eureka::api::CommonRequestParams;
+use bookish_eureka::sdk::{Client, Error, StatusCode};
 use clap::{Args, Parser, Subcommand};
-use derive_more::Display;
 use rand::{thread_rng, Rng};
-use reqwest::{Client, Method, StatusCode};
-use serde::{Deserialize};
 use tokio::{pin, select};
 
 /// Simple program to greet a person
@@ -23,6 +27,9 @@ enum Commands {
     /// table related ops
     #[command(arg_required_else_help = true)]
     Table(TableArgs),
+    /// menu related ops
+    #[command(arg_required_else_help = true)]
+    Menu(MenuArgs),
     /// integration test
     #[command(arg_required_else_help = true)]
     Test(TestArgs)
@@ -48,6 +55,24 @@ enum TableCmds {
     List
 }
 
+#[derive(Debug, Args)]
+pub(crate) struct MenuArgs {
+    #[command(subcommand)]
+    command: MenuCmds,
+}
+
+#[derive(Debug, Subcommand)]
+enum MenuCmds {
+    /// create or update menu items from a CSV file with a header line, or an NDJSON file
+    #[command(arg_required_else_help = true)]
+    Import {
+        #[arg(help = "File to import, treated as CSV when it ends with .csv", value_name = "FILE")]
+        file: PathBuf,
+        #[arg(long, help = "Validate and report without writing anything.")]
+        dry_run: bool,
+    },
+}
+
 #[derive(Debug, Args)]
 struct InitArgs {
     id: u8,
@@ -66,7 +91,7 @@ enum ItemCmds {
     #[command(arg_required_else_help = true)]
     Add {
         #[arg(long, help = "Array of menu items to add.", value_name = "MENU_ITEM_IDs", num_args = 1..)]
-        items: Vec<i64>,
+        items: Vec<i32>,
     },
     #[command(arg_required_else_help = true)]
     Remove {
@@ -78,81 +103,43 @@ enum ItemCmds {
 
 const HOST: &str = "http://localhost:8080";
 
-#[derive(Debug, Deserialize)]
-struct TableInitResponse {
-    pub bill_id: i64,
-}
-
-#[derive(Debug, Deserialize)]
-struct GetBillResponse {
-    pub bill: Option<Bill>,
-}
-
-#[derive(Debug, Deserialize)]
-pub(crate) struct GetTablesResponse {
-    pub tables: Tables,
-}
-
-#[derive(Debug, Deserialize)]
-struct Tables(Option<Vec<Table>>);
-
-#[derive(Debug, Deserialize)]
-pub(crate) struct Table {
-    pub id: u8,
-    pub bill_id: Option<i64>,
-}
-
-#[derive(Debug, Deserialize)]
-pub(crate) struct Bill {
-    pub id: i64,
-    pub items: Items,
-}
-
-#[derive(Debug, Deserialize, Default)]
-struct Items(Vec<Item>);
-
-#[derive(Debug, Deserialize)]
-pub(crate) struct Item {
-    pub id: i64,
-    pub name: String,
-    pub time_to_deliver: i32,
-    pub state: String,
+/// Items listed in detail, the aggregated view is not asked for
+fn items_of(response: GetBillResponse) -> Vec<Item> {
+    match response.bill.map(|bill| bill.items) {
+        Some(BillItems::Detailed(items)) => items,
+        _ => vec![],
+    }
 }
 
-impl Display for Items {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        if self.0.is_empty() {
-            return write!(f, "[]");
-        }
-        for (i, item) in self.0.iter().enumerate() {
-            write!(f, "\n")?;
-            let maybe_comma = if i == self.0.len() - 1 { "" } else { ", " };  
-            write!(f, "{{ id: {}, name: {}, time_to_deliver: {} mins, state: {} }}{}", item.id, item.name, item.time_to_deliver, item.state, maybe_comma)?;
-        }
-        Ok(())
+fn format_items(items: &[Item]) -> String {
+    if items.is_empty() {
+        return "[]".to_string();
     }
+    items.iter()
+        .map(|item| format!("\n{{ id: {}, name: {}, time_to_deliver: {} mins, state: {} }}", item.id, item.name, item.time_to_deliver, item.state))
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
-impl Display for Tables {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        if self.0.is_none() || self.0.as_ref().unwrap().is_empty() {
-            return write!(f, "[]");
-        }
-        let tables = self.0.as_ref().unwrap();
-        for (i, table) in tables.iter().enumerate() {
-            write!(f, "\n")?;
-            let maybe_comma = if i == tables.len() - 1 { "" } else { ", " };
-            write!(f, "{{ id: {}, bill_id: {} }}{}", table.id, table.bill_id.map_or_else(|| "x".to_string(), |v| v.to_string()), maybe_comma)?;
-        }
-        Ok(())
-    }
+fn format_tables(tables: &Option<Vec<Table>>) -> String {
+    let Some(tables) = tables.as_ref().filter(|tables| !tables.is_empty()) else {
+        return "[]".to_string();
+    };
+    tables.iter()
+        .map(|table| format!("\n{{ id: {}, bill_id: {} }}", table.id, table.bill_id.map_or_else(|| "x".to_string(), |v| v.to_string())))
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
-const TABLE_ID_RANGE: RangeInclusive<i32> = 1..=10; // match V1__init.sql
+const TABLE_ID_RANGE: RangeInclusive<i16> = 1..=10; // match V1__init.sql
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
     let args = Cli::parse();
+    let client = match std::env::var("AUTH_TOKEN") {
+        Ok(token) => Client::new(HOST).with_auth_token(token),
+        Err(_) => Client::new(HOST),
+    };
 
     match args.command {
         Commands::Table(table) => {
@@ -160,21 +147,17 @@ async fn main() -> Result<(), anyhow::Error> {
                 TableCmds::Init(args) => {
                     let id = args.id;
                     println!("initializing table={} for customers", id);
-                    let res = Client::new()
-                        .patch(format!("{}/{}", HOST, "v1/table/") + &id.to_string())
-                        .send()
-                        .await?;
-                    match res.status() {
-                        StatusCode::OK => {
-                            let res = res.json::<TableInitResponse>().await.expect("failed to get response, aborting");
+                    match client.patch_table(id.into(), &PatchTableParams::default()).await {
+                        Ok(res) => {
                             println!("table {} initialized successfully, bound to bill id = {}", id, res.bill_id);
                         },
-                        StatusCode::BAD_REQUEST => {
+                        Err(Error::Status { status: StatusCode::BAD_REQUEST, .. }) => {
                             println!("table {} is already taken, please guide the customers to other tables", id);
                         }
-                        unexpected => {
+                        Err(Error::Status { status: unexpected, .. }) => {
                             println!("got unexpected status code, {}", unexpected);
                         },
+                        Err(e) => return Err(e.into()),
                     }
                 },
                 TableCmds::Item(args) => {
@@ -182,234 +165,248 @@ async fn main() -> Result<(), anyhow::Error> {
                     match args.command {
                         ItemCmds::Add { items: menu_ids } => {
                             println!("adding items to bill={}", bill_id);
-                            let res = Client::new()
-                                .post(format!("{}/{}", HOST, format!("v1/bill/{}/items", bill_id)))
-                                .json(&serde_json::json!({
-                                    "items": menu_ids,
-                                }))
-                                .send()
-                                .await?;
-                            match res.status() {
-                                StatusCode::OK => {
+                            let request = PostBillItemsRequest { items: menu_ids.into_iter().map(OrderItem::from).collect() };
+                            match client.post_bill_items(bill_id, &request).await {
+                                Ok(_) => {
                                     println!("Successfully added items to bill id = {}", bill_id);
                                 },
-                                StatusCode::BAD_REQUEST => {
+                                Err(Error::Status { status: StatusCode::BAD_REQUEST, .. }) => {
                                     println!("Failed to add items to bill id = {}", bill_id);
                                 }
-                                unexpected => {
+                                Err(Error::Status { status: unexpected, .. }) => {
                                     println!("got unexpected status code, {}", unexpected);
                                 },
+                                Err(e) => return Err(e.into()),
                             }
                         },
                         ItemCmds::Remove { id } => {
                             println!("removing items from bill={}", bill_id);
-                            let res = Client::new()
-                                .delete(format!("{}/{}", HOST, format!("v1/bill/{}/item/{}", bill_id, id)))
-                                .send()
-                                .await?;
-                            match res.status() {
-                                StatusCode::OK => {
+                            match client.delete_bill_items(bill_id, id).await {
+                                Ok(()) => {
                                     println!("Successfully removed items from bill id = {}", bill_id);
                                 },
-                                StatusCode::BAD_REQUEST => {
+                                Err(Error::Status { status: StatusCode::BAD_REQUEST, .. }) => {
                                     println!("Bad request");
                                 },
-                                StatusCode::NOT_FOUND => {
+                                Err(Error::Status { status: StatusCode::NOT_FOUND, .. }) => {
                                     println!("Resource not found");
                                 },
-                                unexpected => {
+                                Err(Error::Status { status: unexpected, .. }) => {
                                     println!("got unexpected status code, {}", unexpected);
                                 },
+                                Err(e) => return Err(e.into()),
                             }
                         },
                         ItemCmds::List => {
                             println!("listing items from bill={}", bill_id);
-                            let res = Client::new()
-                                .get(format!("{}/{}", HOST, format!("v1/bill/{}", bill_id)))
-                                .send()
-                                .await?;
-                            match res.status() {
-                                StatusCode::OK => {
-                                    let res = res.json::<GetBillResponse>().await?;
-                                    println!("items for bill = [{}] => {}", bill_id, res.bill.map(|b|b.items).unwrap_or(Items::default()));
+                            match client.get_bill(bill_id, &CommonRequestParams::default(), &GetBillParams::default()).await {
+                                Ok(res) => {
+                                    println!("items for bill = [{}] => {}", bill_id, format_items(&items_of(res)));
                                 },
-                                StatusCode::BAD_REQUEST => {
+                                Err(Error::Status { status: StatusCode::BAD_REQUEST, .. }) => {
                                     println!("Bad request");
                                 },
-                                StatusCode::NOT_FOUND => {
+                                Err(Error::Status { status: StatusCode::NOT_FOUND, .. }) => {
                                     println!("Resource not found");
                                 },
-                                unexpected => {
+                                Err(Error::Status { status: unexpected, .. }) => {
                                     println!("got unexpected status code, {}", unexpected);
                                 },
+                                Err(e) => return Err(e.into()),
                             }
                         },
                     }
                 },
                 TableCmds::List => {
                     println!("Listing tables =>");
-                    let res = Client::new()
-                        .get(format!("{}/{}", HOST, format!("v1/tables")))
-                        .send()
-                        .await?;
-                    match res.status() {
-                        StatusCode::OK => {
-                            let tables = res.json::<GetTablesResponse>().await?.tables;
-                            println!("{}", tables);
+                    match client.get_tables().await {
+                        Ok(res) => {
+                            println!("{}", format_tables(&res.tables));
                         },
-                        StatusCode::BAD_REQUEST => {
+                        Err(Error::Status { status: StatusCode::BAD_REQUEST, .. }) => {
                             println!("Bad request");
                         },
-                        StatusCode::NOT_FOUND => {
+                        Err(Error::Status { status: StatusCode::NOT_FOUND, .. }) => {
                             println!("Resource not found");
                         },
-                        unexpected => {
+                        Err(Error::Status { status: unexpected, .. }) => {
                             println!("got unexpected status code, {}", unexpected);
                         },
+                        Err(e) => return Err(e.into()),
                     }
                 }
             }
         },
+        Commands::Menu(menu) => {
+            match menu.command {
+                MenuCmds::Import { file, dry_run } => {
+                    println!("importing menu items from {}{}", file.display(), if dry_run { " (dry run)" } else { "" });
+                    let format = match file.extension().and_then(|ext| ext.to_str()) {
+                        Some("csv") => ExportFormat::Csv,
+                        _ => ExportFormat::Ndjson,
+                    };
+                    match client.post_import_menu(std::fs::read(&file)?, format, &ImportMenuParams { dry_run }).await {
+                        Ok(res) if res.errors.is_empty() => {
+                            let verb = if res.dry_run { "would be" } else { "were" };
+                            println!("{} menu items {} created, {} {} updated", res.created, verb, res.updated, verb);
+                        },
+                        Ok(res) => {
+                            println!("nothing imported, {} errors:", res.errors.len());
+                            for issue in res.errors {
+                                println!("line {}: {}", issue.line, issue.message);
+                            }
+                        },
+                        Err(Error::Status { status: unexpected, .. }) => {
+                            println!("got unexpected status code, {}", unexpected);
+                        },
+                        Err(e) => return Err(e.into()),
+                    }
+                },
+            }
+        },
         Commands::Test(TestArgs{ concurrency}) => {
             let intval = tokio::time::interval(Duration::new(0, 500_000_000)); // emit a batch of request every 0.5 second
             pin!(intval);
             loop {
                 for _ in 0..concurrency as usize {
-                    tokio::spawn(async {
+                    let client = client.clone();
+                    tokio::spawn(async move {
                         // INIT TABLE
                         let table_id = thread_rng().gen_range(TABLE_ID_RANGE);
                         let id = table_id;
                         println!("initializing table={} for customers", id);
-                        let res = Client::new()
-                            .patch(format!("{}/{}", HOST, "v1/table/") + &id.to_string())
-                            .send()
-                            .await.expect("failed to init table");
-                        let bill_id = match res.status() {
-                            StatusCode::OK => {
-                                let res = res.json::<TableInitResponse>().await.expect("failed to get response, aborting");
+                        let bill_id = match client.patch_table(id, &PatchTableParams::default()).await {
+                            Ok(res) => {
                                 println!("table {} initialized successfully, bound to bill id = {}", id, res.bill_id);
                                 res.bill_id
                             },
-                            StatusCode::BAD_REQUEST => {
+                            Err(Error::Status { status: StatusCode::BAD_REQUEST, .. }) => {
                                 println!("table {} is already taken, please guide the customers to other tables", id);
                                 return;
                             }
-                            unexpected => {
+                            Err(Error::Status { status: unexpected, .. }) => {
                                 println!("got unexpected status code, {}", unexpected);
                                 panic!("force abort as server is not healthy when initing tables");
                             },
+                            Err(e) => panic!("failed to init table, {}", e),
                         };
 
                         // ADD ITEMS
                         const MENU_SIZE: usize = 5;
-                        const MENU_ITEM_ID_RANGE: RangeInclusive<i64> = (1..=5); // there are only 5 menu items, update upper bound to create some BAD_REQUEST possibilities.
-                        let menu_ids = (0..MENU_SIZE).into_iter().fold(vec![], |mut acc, _| {
-                            acc.push(thread_rng().gen_range(MENU_ITEM_ID_RANGE));
-                            acc
-                        });
-                        let res = Client::new()
-                            .post(format!("{}/{}", HOST, format!("v1/bill/{}/items", bill_id)))
-                            .json(&serde_json::json!({
-                                "items": menu_ids,
-                            }))
-                            .send()
-                            .await.expect("failed to add items");
-                        match res.status() {
-                            StatusCode::OK => {
+                        const MENU_ITEM_ID_RANGE: RangeInclusive<i32> = 1..=5; // there are only 5 menu items, update upper bound to create some BAD_REQUEST possibilities.
+                        let menu_ids = (0..MENU_SIZE).map(|_| OrderItem::from(thread_rng().gen_range(MENU_ITEM_ID_RANGE))).collect();
+                        match client.post_bill_items(bill_id, &PostBillItemsRequest { items: menu_ids }).await {
+                            Ok(_) => {
                                 println!("Successfully added items to bill id = {}", bill_id);
                             },
-                            StatusCode::BAD_REQUEST => {
+                            Err(Error::Status { status: StatusCode::BAD_REQUEST, .. }) => {
                                 println!("Bad request for bill id = {}, either bill or menu item does not exist", bill_id);
                             }
-                            unexpected => {
+                            Err(Error::Status { status: unexpected, .. }) => {
                                 println!("got unexpected status code, {}", unexpected);
                                 panic!("force abort as server is not healthy when adding items");
                             },
+                            Err(e) => panic!("failed to add items, {}", e),
                         }
                         // LIST ITEMS FOR THIS TABLE
-                        let res = Client::new()
-                            .get(format!("{}/{}", HOST, format!("v1/bill/{}", bill_id)))
-                            .send()
-                            .await.expect("failed to list items for bill");
-                        let items = match res.status() {
-                            StatusCode::OK => {
-                                let res = res.json::<GetBillResponse>().await.expect("failed to get response for bill items");
-                                let res_items = res.bill.map(|b| b.items).take().unwrap();
-                                let items = res_items.0.into_iter().map(|item| item.id).collect::<Vec<_>>();
+                        let items = match client.get_bill(bill_id, &CommonRequestParams::default(), &GetBillParams::default()).await {
+                            Ok(res) => {
+                                let items = items_of(res).into_iter().map(|item| item.id).collect::<Vec<_>>();
                                 println!("items for bill = [{}] => {:?}", bill_id, &items);
                                 items
                             },
-                            StatusCode::BAD_REQUEST => {
+                            Err(Error::Status { status: StatusCode::BAD_REQUEST, .. }) => {
                                 println!("Bad request");
                                 return;
                             },
-                            StatusCode::NOT_FOUND => {
+                            Err(Error::Status { status: StatusCode::NOT_FOUND, .. }) => {
                                 println!("Resource not found");
                                 return;
                             },
-                            unexpected => {
+                            Err(Error::Status { status: unexpected, .. }) => {
                                 println!("got unexpected status code, {}", unexpected);
                                 panic!("abort due to fail to list items for table");
                             },
+                            Err(e) => panic!("failed to list items for bill, {}", e),
                         };
                         // DELETE ITEM
                         let item_id = thread_rng().gen_range(0..items.len()); // if picked id idx = items.len(), we get NOT_FOUND.
-                        let res = Client::new()
-                            .delete(format!("{}/{}", HOST, format!("v1/bill/{}/item/{}", bill_id, items[item_id])))
-                            .send()
-                            .await.expect("failed to delete an item");
-                        match res.status() {
-                            StatusCode::OK => {
+                        match client.delete_bill_items(bill_id, items[item_id]).await {
+                            Ok(()) => {
                                 println!("Successfully removed an item from bill id = {}", bill_id);
                             },
-                            StatusCode::BAD_REQUEST => {
+                            Err(Error::Status { status: StatusCode::BAD_REQUEST, .. }) => {
                                 println!("Bad request");
                             },
-                            StatusCode::NOT_FOUND => {
+                            Err(Error::Status { status: StatusCode::NOT_FOUND, .. }) => {
                                 println!("Resource not found");
                                 panic!("abort due to the item {} to delete from the bill {} does not exist", items[item_id], bill_id);
                             },
-                            unexpected => {
+                            Err(Error::Status { status: unexpected, .. }) => {
                                 println!("got unexpected status code, {}", unexpected);
                                 panic!("abort due to fail to delete item {} from the bill {}", items[item_id], bill_id);
                             },
+                            Err(e) => panic!("failed to delete an item, {}", e),
+                        }
+                        // PAY
+                        let outstanding = match client.get_bill_payments(bill_id).await {
+                            Ok(res) => res.balance.outstanding,
+                            Err(Error::Status { status: unexpected, .. }) => {
+                                println!("got unexpected status code, {}", unexpected);
+                                panic!("abort due to fail to get balance of bill {}", bill_id);
+                            },
+                            Err(e) => panic!("failed to get balance of bill, {}", e),
+                        };
+                        if outstanding > 0 {
+                            let payment = PostPaymentRequest::Payment {
+                                provider: PaymentMethod::Cash,
+                                amount: outstanding,
+                                tip: 0,
+                                split_id: None,
+                            };
+                            match client.post_bill_payments(bill_id, &payment).await {
+                                Ok(_) => {
+                                    println!("Paid {} for bill id = {}", outstanding, bill_id);
+                                },
+                                Err(Error::Status { status: StatusCode::BAD_REQUEST, .. }) => {
+                                    println!("Bad request");
+                                },
+                                Err(Error::Status { status: unexpected, .. }) => {
+                                    println!("got unexpected status code, {}", unexpected);
+                                    panic!("abort due to fail to pay bill {}", bill_id);
+                                },
+                                Err(e) => panic!("failed to pay bill, {}", e),
+                            }
                         }
                         // CHECKOUT
-                        let res = Client::new()
-                            .post(format!("{}/{}", HOST, format!("v1/table/{}", id)))
-                            .send()
-                            .await.expect(format!("failed to checkout table {}", id).as_str());
-                        match res.status() {
-                            StatusCode::OK => {
+                        match client.post_table(id).await {
+                            Ok(_) => {
                                 println!("Checkout table {} successfully", id);
                             },
-                            StatusCode::BAD_REQUEST => {
+                            Err(Error::Status { status: StatusCode::BAD_REQUEST, .. }) => {
                                 println!("Bad request");
                             },
-                            unexpected => {
+                            Err(Error::Status { status: unexpected, .. }) => {
                                 println!("got unexpected status code, {}", unexpected);
                                 panic!("abort due to fail to checkout")
                             },
+                            Err(e) => panic!("failed to checkout table {}, {}", id, e),
                         };
                         // LIST ALL TABLES
-                        let res = Client::new()
-                            .get(format!("{}/{}", HOST, format!("v1/tables")))
-                            .send()
-                            .await.expect("failed to list tables");
-                        match res.status() {
-                            StatusCode::OK => {
-                                let tables = res.json::<GetTablesResponse>().await.expect("failed to get table response").tables;
-                                println!("Got tables: {}", tables);
+                        match client.get_tables().await {
+                            Ok(res) => {
+                                println!("Got tables: {}", format_tables(&res.tables));
                             },
-                            StatusCode::BAD_REQUEST => {
+                            Err(Error::Status { status: StatusCode::BAD_REQUEST, .. }) => {
                                 println!("Bad request");
                             },
-                            StatusCode::NOT_FOUND => {
+                            Err(Error::Status { status: StatusCode::NOT_FOUND, .. }) => {
                                 println!("Resource not found");
                             },
-                            unexpected => {
+                            Err(Error::Status { status: unexpected, .. }) => {
                                 println!("got unexpected status code, {}", unexpected);
                             },
+                            Err(e) => panic!("failed to list tables, {}", e),
                         }
                     });
                 }
@@ -0,0 +1,18 @@
+//! bookish-eureka, a restaurant ordering server, the models of its API and a client SDK
+
+mod server;
+
+/// Request and response models of the HTTP API, shared by the server and its clients
+pub mod api {
+    pub use crate::server::model::{admin, alert, bill, event, export, item, menu, payment, pricing, receipt, report, reservation, split, table, webhook};
+    pub use crate::server::model::CommonRequestParams;
+}
+
+pub mod sdk;
+
+/// What the server and migration binaries start from, the rest of the server stays internal
+pub mod app {
+    pub use crate::server::run;
+    pub use crate::server::model::config::{ConfigError, ServerConfig};
+    pub use crate::server::tls::postgres_connector;
+}
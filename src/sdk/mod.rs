@@ -0,0 +1,394 @@
+//! Typed async client of the HTTP API, with one method per endpoint named after its handler
+
+use derive_more::Display;
+use reqwest::{Method, RequestBuilder, Response};
+use serde::de::DeserializeOwned;
+use crate::api::admin::{GetCachesResponse, GetJobsParams, GetJobsResponse, GetLeasesResponse, GetRetentionResponse, JobRun};
+use crate::api::event::Event;
+use crate::api::alert::GetAlertsResponse;
+use crate::api::bill::{GetBillParams, GetBillResponse, PostBillItemsRequest, PostBillItemsResponse};
+use crate::api::export::{ExportFormat, ExportKind, ExportParams, ImportMenuParams, ImportMenuResponse};
+use crate::api::menu::GetMenuResponse;
+use crate::api::payment::{GetPaymentsResponse, PostPaymentRequest, PostPaymentResponse};
+use crate::api::pricing::{DiscountRule, GetDiscountRulesResponse, PostCompRequest, PricedBill};
+use crate::api::receipt::{GetReceiptParams, GetTicketParams};
+use crate::api::report::{CategorySalesRow, DeliveryRow, ItemSalesRow, ReportParams, ReportResponse, RevenueRow, TurnoverRow, VoidRow};
+use crate::api::reservation::{GetReservationsParams, GetReservationsResponse, GetWaitlistResponse, PostReservationRequest, PostWaitlistRequest, Reservation, WaitlistEntry};
+use crate::api::split::{GetBillSplitsResponse, PostBillSplitsRequest};
+use crate::api::table::{GetTablesResponse, PatchTableParams, PatchTablesResponse, PostTablesResponse};
+use crate::api::webhook::{GetWebhookDeliveriesParams, GetWebhookDeliveriesResponse, GetWebhooksResponse, PostWebhookRequest, PostWebhookResponse};
+use crate::api::CommonRequestParams;
+
+pub use reqwest::StatusCode;
+
+#[derive(Debug, Display, derive_more::Error)]
+pub enum Error {
+    /// the request is not sent or the response is not read
+    #[display("request failed, {_0}")]
+    Request(reqwest::Error),
+    /// the server responds with an unsuccessful status
+    #[display("unexpected status {status}, {message}")]
+    Status { status: StatusCode, message: String },
+    /// the server sends an event this client does not understand
+    #[display("malformed event, {_0}")]
+    Event(serde_json::Error),
+    /// the client reads events too slowly and missed some, the stream goes on after them
+    #[display("missed {missed} events")]
+    Lagged { missed: u64 },
+}
+
+impl Error {
+    /// Status responded by the server, none when the request failed before that
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Error::Request(e) => e.status(),
+            Error::Status { status, .. } => Some(*status),
+            Error::Event(_) | Error::Lagged { .. } => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Request(e)
+    }
+}
+
+/// Client of a server, cheap to clone as clones share connections
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    auth_token: Option<String>,
+}
+
+impl Client {
+    /// Create a client of the server at the base url, e.g. `http://localhost:8080`
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self::with_http_client(base_url, reqwest::Client::new())
+    }
+
+    /// Create a client sending requests with a configured HTTP client, for timeouts, proxies and so on
+    pub fn with_http_client(base_url: impl Into<String>, http: reqwest::Client) -> Self {
+        let base_url = base_url.into().trim_end_matches('/').to_string();
+        Self { http, base_url, auth_token: None }
+    }
+
+    /// Send the bearer token required by servers with auth configured
+    pub fn with_auth_token(self, auth_token: impl Into<String>) -> Self {
+        Self { auth_token: Some(auth_token.into()), ..self }
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let request = self.http.request(method, format!("{}{}", self.base_url, path));
+        match &self.auth_token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    async fn send(request: RequestBuilder) -> Result<Response, Error> {
+        Self::check(request.send().await?).await
+    }
+
+    /// Turn an unsuccessful response into an error, the server explains errors in plain text
+    async fn check(response: Response) -> Result<Response, Error> {
+        let status = response.status();
+        if !status.is_success() {
+            let message = response.text().await.unwrap_or_default();
+            return Err(Error::Status { status, message });
+        }
+        Ok(response)
+    }
+
+    async fn json<T: DeserializeOwned>(request: RequestBuilder) -> Result<T, Error> {
+        Ok(Self::send(request).await?.json().await?)
+    }
+
+    async fn empty(request: RequestBuilder) -> Result<(), Error> {
+        Self::send(request).await.map(|_| ())
+    }
+
+    async fn report<T: DeserializeOwned>(&self, path: &str, params: &ReportParams) -> Result<Vec<T>, Error> {
+        // rows are always fetched as JSON, CSV is for spreadsheets rather than programs
+        let params = ReportParams { format: None, ..params.clone() };
+        let response: ReportResponse<T> = Self::json(self.request(Method::GET, path).query(&params)).await?;
+        Ok(response.rows)
+    }
+
+    /// List tables with their availability
+    pub async fn get_tables(&self) -> Result<GetTablesResponse, Error> {
+        Self::json(self.request(Method::GET, "/v1/tables")).await
+    }
+
+    /// Occupy a table with a new bill, seating a reservation when given
+    pub async fn patch_table(&self, id: i16, params: &PatchTableParams) -> Result<PatchTablesResponse, Error> {
+        Self::json(self.request(Method::PATCH, &format!("/v1/table/{}", id)).query(params)).await
+    }
+
+    /// Check out a table
+    pub async fn post_table(&self, id: i16) -> Result<PostTablesResponse, Error> {
+        Self::json(self.request(Method::POST, &format!("/v1/table/{}", id))).await
+    }
+
+    /// Get a bill with a page of its items and its totals
+    pub async fn get_bill(&self, id: i64, page: &CommonRequestParams, params: &GetBillParams) -> Result<GetBillResponse, Error> {
+        Self::json(self.request(Method::GET, &format!("/v1/bill/{}", id)).query(page).query(params)).await
+    }
+
+    /// Order items, returns the created bill items in the order of the request
+    pub async fn post_bill_items(&self, id: i64, body: &PostBillItemsRequest) -> Result<PostBillItemsResponse, Error> {
+        Self::json(self.request(Method::POST, &format!("/v1/bill/{}/items", id)).json(body)).await
+    }
+
+    /// Remove a bill item that is not delivered yet
+    pub async fn delete_bill_items(&self, id: i64, item_id: i64) -> Result<(), Error> {
+        Self::empty(self.request(Method::DELETE, &format!("/v1/bill/{}/item/{}", id, item_id))).await
+    }
+
+    /// Split a bill by items or into equal shares
+    pub async fn post_bill_splits(&self, id: i64, body: &PostBillSplitsRequest) -> Result<GetBillSplitsResponse, Error> {
+        Self::json(self.request(Method::POST, &format!("/v1/bill/{}/splits", id)).json(body)).await
+    }
+
+    /// List splits of a bill
+    pub async fn get_bill_splits(&self, id: i64) -> Result<GetBillSplitsResponse, Error> {
+        Self::json(self.request(Method::GET, &format!("/v1/bill/{}/splits", id))).await
+    }
+
+    /// Remove splits of a bill when none is settled
+    pub async fn delete_bill_splits(&self, id: i64) -> Result<(), Error> {
+        Self::empty(self.request(Method::DELETE, &format!("/v1/bill/{}/splits", id))).await
+    }
+
+    /// Record a payment or a refund of a bill
+    pub async fn post_bill_payments(&self, id: i64, body: &PostPaymentRequest) -> Result<PostPaymentResponse, Error> {
+        Self::json(self.request(Method::POST, &format!("/v1/bill/{}/payments", id)).json(body)).await
+    }
+
+    /// List payments and refunds of a bill with its balance
+    pub async fn get_bill_payments(&self, id: i64) -> Result<GetPaymentsResponse, Error> {
+        Self::json(self.request(Method::GET, &format!("/v1/bill/{}/payments", id))).await
+    }
+
+    /// Create a discount rule, returns it with its id
+    pub async fn post_discount(&self, body: &DiscountRule) -> Result<DiscountRule, Error> {
+        Self::json(self.request(Method::POST, "/v1/discounts").json(body)).await
+    }
+
+    /// List active discount rules
+    pub async fn get_discounts(&self) -> Result<GetDiscountRulesResponse, Error> {
+        Self::json(self.request(Method::GET, "/v1/discounts")).await
+    }
+
+    /// Deactivate a discount rule
+    pub async fn delete_discount(&self, id: i32) -> Result<(), Error> {
+        Self::empty(self.request(Method::DELETE, &format!("/v1/discount/{}", id))).await
+    }
+
+    /// Comp a bill item or the whole bill, returns the bill totals after it
+    pub async fn post_bill_comps(&self, id: i64, body: &PostCompRequest) -> Result<PricedBill, Error> {
+        Self::json(self.request(Method::POST, &format!("/v1/bill/{}/comps", id)).json(body)).await
+    }
+
+    /// Render the receipt of a bill as text or HTML
+    pub async fn get_bill_receipt(&self, id: i64, params: &GetReceiptParams) -> Result<String, Error> {
+        Ok(Self::send(self.request(Method::GET, &format!("/v1/bill/{}/receipt", id)).query(params)).await?.text().await?)
+    }
+
+    /// Render the kitchen ticket of a bill
+    pub async fn get_bill_ticket(&self, id: i64, params: &GetTicketParams) -> Result<String, Error> {
+        Ok(Self::send(self.request(Method::GET, &format!("/v1/bill/{}/ticket", id)).query(params)).await?.text().await?)
+    }
+
+    /// List menu items with their modifier groups
+    pub async fn get_menu(&self) -> Result<GetMenuResponse, Error> {
+        Self::json(self.request(Method::GET, "/v1/menu")).await
+    }
+
+    /// Book a table for a party
+    pub async fn post_reservation(&self, body: &PostReservationRequest) -> Result<Reservation, Error> {
+        Self::json(self.request(Method::POST, "/v1/reservations").json(body)).await
+    }
+
+    /// List booked and seated reservations in a range
+    pub async fn get_reservations(&self, params: &GetReservationsParams) -> Result<GetReservationsResponse, Error> {
+        Self::json(self.request(Method::GET, "/v1/reservations").query(params)).await
+    }
+
+    /// Cancel a booked reservation
+    pub async fn delete_reservation(&self, id: i64) -> Result<(), Error> {
+        Self::empty(self.request(Method::DELETE, &format!("/v1/reservation/{}", id))).await
+    }
+
+    /// Add a walk-in party to the waitlist
+    pub async fn post_waitlist(&self, body: &PostWaitlistRequest) -> Result<WaitlistEntry, Error> {
+        Self::json(self.request(Method::POST, "/v1/waitlist").json(body)).await
+    }
+
+    /// List waiting parties with estimated waits
+    pub async fn get_waitlist(&self) -> Result<GetWaitlistResponse, Error> {
+        Self::json(self.request(Method::GET, "/v1/waitlist")).await
+    }
+
+    /// Remove a party from the waitlist
+    pub async fn delete_waitlist(&self, id: i64) -> Result<(), Error> {
+        Self::empty(self.request(Method::DELETE, &format!("/v1/waitlist/{}", id))).await
+    }
+
+    /// Revenue per period, the format of the params is ignored
+    pub async fn get_revenue_report(&self, params: &ReportParams) -> Result<Vec<RevenueRow>, Error> {
+        self.report("/v1/reports/revenue", params).await
+    }
+
+    /// Top selling menu items, the format of the params is ignored
+    pub async fn get_item_sales_report(&self, params: &ReportParams) -> Result<Vec<ItemSalesRow>, Error> {
+        self.report("/v1/reports/items", params).await
+    }
+
+    /// Top selling categories, the format of the params is ignored
+    pub async fn get_category_sales_report(&self, params: &ReportParams) -> Result<Vec<CategorySalesRow>, Error> {
+        self.report("/v1/reports/categories", params).await
+    }
+
+    /// Table turnover, the format of the params is ignored
+    pub async fn get_turnover_report(&self, params: &ReportParams) -> Result<Vec<TurnoverRow>, Error> {
+        self.report("/v1/reports/turnover", params).await
+    }
+
+    /// Estimated vs. actual delivery time, the format of the params is ignored
+    pub async fn get_delivery_report(&self, params: &ReportParams) -> Result<Vec<DeliveryRow>, Error> {
+        self.report("/v1/reports/delivery", params).await
+    }
+
+    /// Voided items, the format of the params is ignored
+    pub async fn get_void_report(&self, params: &ReportParams) -> Result<Vec<VoidRow>, Error> {
+        self.report("/v1/reports/voids", params).await
+    }
+
+    /// Export records created in a range as CSV or NDJSON, the whole export is read into memory
+    pub async fn get_export(&self, kind: ExportKind, params: &ExportParams) -> Result<Vec<u8>, Error> {
+        let kind = match kind {
+            ExportKind::Bills => "bills",
+            ExportKind::Items => "items",
+            ExportKind::Payments => "payments",
+        };
+        Ok(Self::send(self.request(Method::GET, &format!("/v1/export/{}", kind)).query(params)).await?.bytes().await?.to_vec())
+    }
+
+    /// Import menu items from CSV with a header line or NDJSON, a rejected import is returned with its errors rather than as an error
+    pub async fn post_import_menu(&self, body: Vec<u8>, format: ExportFormat, params: &ImportMenuParams) -> Result<ImportMenuResponse, Error> {
+        let content_type = match format {
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Ndjson => "application/x-ndjson",
+        };
+        let response = self.request(Method::POST, "/v1/import/menu")
+            .query(params)
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .body(body)
+            .send()
+            .await?;
+        match response.status() {
+            StatusCode::BAD_REQUEST => Ok(response.json().await?),
+            _ => Ok(Self::check(response).await?.json().await?),
+        }
+    }
+
+    /// List which instance holds the lease of each scheduled job
+    pub async fn get_leases(&self) -> Result<GetLeasesResponse, Error> {
+        Self::json(self.request(Method::GET, "/v1/admin/leases")).await
+    }
+
+    /// List scheduled jobs, their next run and recent history
+    pub async fn get_jobs(&self, params: &GetJobsParams) -> Result<GetJobsResponse, Error> {
+        Self::json(self.request(Method::GET, "/v1/admin/jobs").query(params)).await
+    }
+
+    /// Run a job now and get the finished run, the server waits for it to finish
+    pub async fn run_job(&self, name: &str) -> Result<JobRun, Error> {
+        Self::json(self.request(Method::POST, &format!("/v1/admin/jobs/{}/run", name))).await
+    }
+
+    /// Report what the retention job would archive and purge now
+    pub async fn get_retention(&self) -> Result<GetRetentionResponse, Error> {
+        Self::json(self.request(Method::GET, "/v1/admin/retention")).await
+    }
+
+    /// Report hits, misses and invalidations of the caches of the instance answering
+    pub async fn get_caches(&self) -> Result<GetCachesResponse, Error> {
+        Self::json(self.request(Method::GET, "/v1/admin/caches")).await
+    }
+
+    /// Subscribe a webhook to integration events, keep the secret to verify signatures with
+    pub async fn post_webhook(&self, body: &PostWebhookRequest) -> Result<PostWebhookResponse, Error> {
+        Self::json(self.request(Method::POST, "/v1/admin/webhooks").json(body)).await
+    }
+
+    /// List webhook subscriptions
+    pub async fn get_webhooks(&self) -> Result<GetWebhooksResponse, Error> {
+        Self::json(self.request(Method::GET, "/v1/admin/webhooks")).await
+    }
+
+    /// Unsubscribe a webhook
+    pub async fn delete_webhook(&self, id: i64) -> Result<(), Error> {
+        Self::empty(self.request(Method::DELETE, &format!("/v1/admin/webhooks/{}", id))).await
+    }
+
+    /// Inspect the delivery log of a webhook
+    pub async fn get_webhook_deliveries(&self, id: i64, params: &GetWebhookDeliveriesParams) -> Result<GetWebhookDeliveriesResponse, Error> {
+        Self::json(self.request(Method::GET, &format!("/v1/admin/webhooks/{}/deliveries", id)).query(params)).await
+    }
+
+    /// List bills flagged by the stale bill detector
+    pub async fn get_alerts(&self) -> Result<GetAlertsResponse, Error> {
+        Self::json(self.request(Method::GET, "/v1/alerts")).await
+    }
+
+    /// Resolve an alert once the bill is looked after
+    pub async fn delete_alert(&self, id: i64) -> Result<(), Error> {
+        Self::empty(self.request(Method::DELETE, &format!("/v1/alert/{}", id))).await
+    }
+
+    /// Subscribe to events as they happen
+    pub async fn get_events(&self) -> Result<Events, Error> {
+        let response = Self::send(self.request(Method::GET, "/v1/events")).await?;
+        Ok(Events { response, buffer: vec![] })
+    }
+
+    /// Get the OpenAPI document of the server
+    pub async fn get_openapi(&self) -> Result<serde_json::Value, Error> {
+        Self::json(self.request(Method::GET, "/openapi.json")).await
+    }
+}
+
+/// Stream of server-sent events
+#[derive(Debug)]
+pub struct Events {
+    response: Response,
+    buffer: Vec<u8>,
+}
+
+impl Events {
+    /// Wait for the next event, none once the server ends the stream, e.g. on shutdown
+    pub async fn next(&mut self) -> Result<Option<Event>, Error> {
+        loop {
+            // messages end with a blank line, comments only keep the connection alive
+            if let Some(end) = self.buffer.windows(2).position(|window| window == b"\n\n") {
+                let message = String::from_utf8_lossy(&self.buffer.drain(..end + 2).collect::<Vec<_>>()).into_owned();
+                let data = message.lines().filter_map(|line| line.strip_prefix("data:")).map(str::trim_start).collect::<Vec<_>>().join("\n");
+                if data.is_empty() {
+                    continue;
+                }
+                if message.lines().any(|line| line == "event: lagged") {
+                    let missed = serde_json::from_str::<serde_json::Value>(&data).map_err(Error::Event)?["missed"].as_u64().unwrap_or_default();
+                    return Err(Error::Lagged { missed });
+                }
+                return serde_json::from_str(&data).map(Some).map_err(Error::Event);
+            }
+            match self.response.chunk().await? {
+                Some(chunk) => self.buffer.extend_from_slice(&chunk),
+                None => return Ok(None),
+            }
+        }
+    }
+}
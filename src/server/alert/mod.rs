@@ -0,0 +1,276 @@
+//! Detection of stale bills, left open on a table long after the party is gone
+
+#[cfg(test)]
+use crate::server::database::pool::{DbClient, GenericRow, GenericTransaction};
+use std::str::FromStr;
+use anyhow::{anyhow, Error};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use tokio_postgres::types::ToSql;
+use crate::server::model::alert::{Alert, AlertReason};
+use crate::server::model::bill::{BILL_STATE_CLOSED, BILL_STATE_NEEDS_REVIEW, BILL_STATE_OPEN};
+use crate::server::model::config::AlertConfig;
+use crate::server::model::event::Event;
+use crate::server::state::AppState;
+
+/// Open bills on a table that are stale now, by bill id, reason and the time the bill has been stale since.
+/// A bill is stale when it is open too long, or when every item is delivered and nothing happened since for a while.
+/// Takes the bill state as $1, and the open and idle minutes as $2 and $3.
+const STALE_BILLS: &str = r#"
+    SELECT b.id AS bill_id, b.table_id, 'open_too_long' AS reason, b.created_at AS since
+    FROM bill b
+    JOIN "table" t ON t.bill_id = b.id
+    WHERE b.state = $1 AND b.created_at <= CURRENT_TIMESTAMP - make_interval(mins => $2)
+    UNION ALL
+    SELECT b.id AS bill_id, b.table_id, 'idle' AS reason, activity.last_at AS since
+    FROM bill b
+    JOIN "table" t ON t.bill_id = b.id
+    CROSS JOIN LATERAL (
+        SELECT
+            GREATEST(b.created_at, MAX(i.created_at), MAX(i.delivered_at), (SELECT MAX(p.created_at) FROM payment p WHERE p.bill_id = b.id)) AS last_at,
+            COUNT(*) FILTER (WHERE i.state <> 'deleted') AS items,
+            COUNT(*) FILTER (WHERE i.state = 'created') AS pending
+        FROM bill_item i
+        WHERE i.bill_id = b.id
+    ) activity
+    WHERE b.state = $1 AND activity.items > 0 AND activity.pending = 0
+    AND activity.last_at <= CURRENT_TIMESTAMP - make_interval(mins => $3)
+"#;
+
+/// A bill stale now, a row of [`STALE_BILLS`]
+#[derive(Debug, Clone, PartialEq)]
+struct Stale {
+    bill_id: i64,
+    table_id: i16,
+    reason: AlertReason,
+    since: DateTime<Utc>,
+}
+
+/// An alert, active or resolved, with the state of its bill
+#[derive(Debug, Clone, PartialEq)]
+struct Known {
+    id: i64,
+    bill_id: i64,
+    reason: AlertReason,
+    resolved_at: Option<DateTime<Utc>>,
+    bill_state: String,
+}
+
+/// Alerts to raise for stale bills and active alerts to resolve
+#[derive(Debug, PartialEq)]
+struct Changes<'a> {
+    raise: Vec<&'a Stale>,
+    resolve: Vec<i64>,
+}
+
+/// Raise an alert for each stale bill and reason, unless one is active or was resolved by hand since the bill went stale.
+/// Resolve active alerts of checked out bills and of open bills no longer stale for the reason, the ones of bills to review stay.
+fn reconcile<'a>(stale: &'a [Stale], known: &[Known]) -> Changes<'a> {
+    let raise = stale.iter()
+        .filter(|stale| !known.iter().any(|alert| {
+            alert.bill_id == stale.bill_id && alert.reason == stale.reason
+                && alert.resolved_at.is_none_or(|resolved_at| resolved_at >= stale.since)
+        }))
+        .collect();
+    let resolve = known.iter()
+        .filter(|alert| alert.resolved_at.is_none())
+        .filter(|alert| alert.bill_state == BILL_STATE_CLOSED || (alert.bill_state == BILL_STATE_OPEN
+            && !stale.iter().any(|stale| stale.bill_id == alert.bill_id && stale.reason == alert.reason)))
+        .map(|alert| alert.id)
+        .collect();
+    Changes { raise, resolve }
+}
+
+/// Flag stale bills, resolve alerts of bills no longer stale, and mark bills flagged long enough for review when configured.
+/// Alerts resolved by hand are not raised again until the bill has activity after that.
+/// Events are published once everything is committed, and the number of alerts and bills changed is returned.
+pub(crate) async fn detect(state: &AppState, config: &AlertConfig) -> Result<u64, Error> {
+    let Some(mut conn) = state.get_db_write_pool().acquire(state.get_db_timeout_seconds()).await else {
+        return Err(anyhow!("no connection available"));
+    };
+    let client = conn.client.as_mut().ok_or(anyhow!("client is None"))?;
+    let txn = client.transaction().await?;
+    let (open_minutes, idle_minutes) = (i32::try_from(config.open_minutes)?, i32::try_from(config.idle_minutes)?);
+    let params: &[&(dyn ToSql + Sync)] = &[&BILL_STATE_OPEN, &open_minutes, &idle_minutes];
+    let stale = txn.query(STALE_BILLS, params).await?.iter().map(|row| Ok(Stale {
+        bill_id: row.get("bill_id"),
+        table_id: row.get("table_id"),
+        reason: AlertReason::from_str(row.get("reason")).map_err(Error::msg)?,
+        since: row.get::<&str, DateTime<Utc>>("since"),
+    })).collect::<Result<Vec<_>, Error>>()?;
+    // active alerts, and the past ones of stale bills to tell whether they were resolved since
+    let bill_ids = stale.iter().map(|stale| stale.bill_id).collect::<Vec<_>>();
+    let known = txn.query(r#"
+        SELECT a.id, a.bill_id, a.reason, a.resolved_at, b.state
+        FROM bill_alert a
+        JOIN bill b ON b.id = a.bill_id
+        WHERE a.resolved_at IS NULL OR a.bill_id = ANY($1)
+    "#, &[&bill_ids as &(dyn ToSql + Sync)]).await?.iter().map(|row| Ok(Known {
+        id: row.get("id"),
+        bill_id: row.get("bill_id"),
+        reason: AlertReason::from_str(row.get("reason")).map_err(Error::msg)?,
+        resolved_at: row.get::<&str, Option<DateTime<Utc>>>("resolved_at"),
+        bill_state: row.get("state"),
+    })).collect::<Result<Vec<_>, Error>>()?;
+    let changes = reconcile(&stale, &known);
+
+    let mut events = vec![];
+    let mut flagged = 0;
+    if !changes.raise.is_empty() {
+        let bill_ids = changes.raise.iter().map(|stale| stale.bill_id).collect::<Vec<_>>();
+        let table_ids = changes.raise.iter().map(|stale| stale.table_id).collect::<Vec<_>>();
+        let reasons = changes.raise.iter().map(|stale| stale.reason.to_string()).collect::<Vec<_>>();
+        let rows = txn.query(r#"
+            INSERT INTO bill_alert (bill_id, table_id, reason, detected_at)
+            SELECT bill_id, table_id, reason, CURRENT_TIMESTAMP
+            FROM UNNEST($1::bigint[], $2::smallint[], $3::text[]) AS raised(bill_id, table_id, reason)
+            RETURNING id, bill_id, table_id, reason, detected_at
+        "#, &[&bill_ids as &(dyn ToSql + Sync), &table_ids, &reasons]).await?;
+        for row in &rows {
+            events.push(Event::BillFlagged {
+                alert_id: row.get("id"),
+                bill_id: row.get("bill_id"),
+                table_id: row.get("table_id"),
+                reason: AlertReason::from_str(row.get("reason")).map_err(Error::msg)?,
+                detected_at: row.get::<&str, DateTime<Utc>>("detected_at"),
+            });
+        }
+        flagged = rows.len();
+    }
+    let resolved = if changes.resolve.is_empty() {
+        0
+    } else {
+        txn.execute(
+            "UPDATE bill_alert SET resolved_at = CURRENT_TIMESTAMP WHERE id = ANY($1) AND resolved_at IS NULL",
+            &[&changes.resolve as &(dyn ToSql + Sync)],
+        ).await?
+    };
+
+    let mut marked = 0;
+    if let Some(minutes) = config.review_after_minutes {
+        // the bill stays on its table, so it is still paid and checked out as usual, which resolves its alerts
+        let review_params: &[&(dyn ToSql + Sync)] = &[&BILL_STATE_OPEN, &i32::try_from(minutes)?, &BILL_STATE_NEEDS_REVIEW];
+        let bills = txn.query(r#"
+            UPDATE bill b
+            SET state = $3, updated_at = CURRENT_TIMESTAMP
+            WHERE b.state = $1 AND b.checkout_at IS NULL AND EXISTS (
+                SELECT 1 FROM bill_alert a
+                WHERE a.bill_id = b.id AND a.resolved_at IS NULL
+                AND a.detected_at <= CURRENT_TIMESTAMP - make_interval(mins => $2)
+            )
+            RETURNING id, table_id
+        "#, review_params).await?;
+        for row in &bills {
+            let (bill_id, table_id) = (row.get("id"), row.get("table_id"));
+            warn!("stale bill {} on table {} needs review", bill_id, table_id);
+            events.push(Event::BillNeedsReview { bill_id, table_id });
+        }
+        marked = bills.len() as u64;
+    }
+    txn.commit().await?;
+
+    if flagged > 0 {
+        info!("flagged {} stale bills", flagged);
+    }
+    for event in events {
+        state.get_events().publish(event);
+    }
+    Ok(flagged as u64 + resolved + marked)
+}
+
+/// Active alerts, oldest first
+pub(crate) async fn list(state: &AppState) -> Result<Vec<Alert>, Error> {
+    let Some(conn) = state.get_db_read_pool().acquire(state.get_db_timeout_seconds()).await else {
+        return Err(anyhow!("no connection available"));
+    };
+    let client = conn.client.as_ref().ok_or(anyhow!("client is None"))?;
+    let params: &[&(dyn ToSql + Sync)] = &[&BILL_STATE_NEEDS_REVIEW];
+    let rows = client.query(r#"
+        SELECT a.id, a.bill_id, a.table_id, a.reason, a.detected_at, b.state = $1 AS needs_review
+        FROM bill_alert a
+        JOIN bill b ON b.id = a.bill_id
+        WHERE a.resolved_at IS NULL
+        ORDER BY a.detected_at, a.id
+    "#, params).await?;
+    rows.iter().map(|row| Ok(Alert {
+        id: row.get("id"),
+        bill_id: row.get("bill_id"),
+        table_id: row.get("table_id"),
+        reason: AlertReason::from_str(row.get("reason")).map_err(Error::msg)?,
+        detected_at: row.get::<&str, DateTime<Utc>>("detected_at"),
+        needs_review: row.get("needs_review"),
+    })).collect()
+}
+
+/// Resolve an active alert by hand, telling whether there was one.
+/// A bill in review is open again once none of its alerts is active.
+pub(crate) async fn resolve(state: &AppState, id: i64) -> Result<bool, Error> {
+    let Some(conn) = state.get_db_write_pool().acquire(state.get_db_timeout_seconds()).await else {
+        return Err(anyhow!("no connection available"));
+    };
+    let client = conn.client.as_ref().ok_or(anyhow!("client is None"))?;
+    let params: &[&(dyn ToSql + Sync)] = &[&id, &BILL_STATE_OPEN, &BILL_STATE_NEEDS_REVIEW];
+    let resolved = client.query(r#"
+        WITH resolved AS (
+            UPDATE bill_alert SET resolved_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND resolved_at IS NULL
+            RETURNING bill_id
+        ), reopened AS (
+            UPDATE bill b
+            SET state = $2, updated_at = CURRENT_TIMESTAMP
+            WHERE b.id IN (SELECT bill_id FROM resolved) AND b.state = $3
+            AND NOT EXISTS (SELECT 1 FROM bill_alert a WHERE a.bill_id = b.id AND a.resolved_at IS NULL AND a.id <> $1)
+        )
+        SELECT bill_id FROM resolved
+    "#, params).await?;
+    Ok(!resolved.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+    use super::*;
+
+    fn at(minutes: i64) -> DateTime<Utc> {
+        "2024-05-01T12:00:00Z".parse::<DateTime<Utc>>().unwrap() + TimeDelta::minutes(minutes)
+    }
+
+    fn stale(bill_id: i64, reason: AlertReason, since: i64) -> Stale {
+        Stale { bill_id, table_id: 1, reason, since: at(since) }
+    }
+
+    fn alert(id: i64, bill_id: i64, reason: AlertReason, resolved_at: Option<i64>, bill_state: &str) -> Known {
+        Known { id, bill_id, reason, resolved_at: resolved_at.map(at), bill_state: bill_state.to_string() }
+    }
+
+    #[test]
+    fn test_reconcile() {
+        let stale = vec![
+            stale(1, AlertReason::OpenTooLong, 0),
+            stale(2, AlertReason::Idle, 30),
+            stale(3, AlertReason::Idle, 30),
+            stale(4, AlertReason::Idle, 30),
+        ];
+        let known = vec![
+            // still stale, stays active
+            alert(10, 2, AlertReason::Idle, None, BILL_STATE_OPEN),
+            // resolved by hand before the latest activity, the bill is stale again
+            alert(11, 3, AlertReason::Idle, Some(20), BILL_STATE_OPEN),
+            // resolved by hand since the bill went stale, not raised again
+            alert(12, 4, AlertReason::Idle, Some(40), BILL_STATE_OPEN),
+            // no longer stale for this reason, or checked out
+            alert(13, 2, AlertReason::OpenTooLong, None, BILL_STATE_OPEN),
+            alert(14, 5, AlertReason::Idle, None, BILL_STATE_CLOSED),
+            // bills to review keep their alerts until resolved by hand or checked out
+            alert(15, 6, AlertReason::Idle, None, BILL_STATE_NEEDS_REVIEW),
+        ];
+        let changes = reconcile(&stale, &known);
+        assert_eq!(changes.raise.iter().map(|stale| stale.bill_id).collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(changes.resolve, vec![13, 14]);
+
+        // nothing stale, every active alert of open bills is resolved
+        let changes = reconcile(&[], &known);
+        assert!(changes.raise.is_empty());
+        assert_eq!(changes.resolve, vec![10, 13, 14]);
+    }
+}
@@ -0,0 +1,189 @@
+//! In-process read-through caches of data read far more often than written, each entry is served until its TTL passes
+//! or a write through this instance invalidates it
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use log::debug;
+use tokio_util::sync::CancellationToken;
+use crate::server::model::admin::CacheStats;
+use crate::server::model::config::CacheConfig;
+use crate::server::model::event::Event;
+use crate::server::model::menu::MenuItem;
+use crate::server::model::table::Table;
+use crate::server::state::AppState;
+
+/// A cached value loaded on a miss, off with a TTL of zero
+pub(crate) struct Cached<T> {
+    name: &'static str,
+    ttl: Duration,
+    entry: RwLock<Option<(Instant, Arc<T>)>>,
+    /// bumped on invalidation, so that a value loaded before is not cached after
+    generation: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<T> Cached<T> {
+    pub fn new(name: &'static str, ttl: Duration) -> Self {
+        Self {
+            name,
+            ttl,
+            entry: RwLock::new(None),
+            generation: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// The cached value while fresh, otherwise the loaded one, which is cached unless loading fails
+    pub async fn get_or_load<E, F, Fut>(&self, load: F) -> Result<Arc<T>, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        if let Some((cached_at, value)) = self.entry.read().unwrap().as_ref() {
+            if cached_at.elapsed() < self.ttl {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(value.clone());
+            }
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let generation = self.generation.load(Ordering::Acquire);
+        let value = Arc::new(load().await?);
+        if !self.ttl.is_zero() {
+            let mut entry = self.entry.write().unwrap();
+            if self.generation.load(Ordering::Acquire) == generation {
+                *entry = Some((Instant::now(), value.clone()));
+            }
+        }
+        Ok(value)
+    }
+
+    /// Drop the cached value after a write, reads in progress do not cache what they load
+    pub fn invalidate(&self) {
+        let mut entry = self.entry.write().unwrap();
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        *entry = None;
+        debug!("invalidated the {} cache", self.name);
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            name: self.name.to_string(),
+            ttl_seconds: self.ttl.as_secs(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            invalidations: self.generation.load(Ordering::Relaxed),
+            cached: self.entry.read().unwrap().is_some(),
+        }
+    }
+}
+
+/// Caches of the instance
+pub(crate) struct Caches {
+    /// every menu item with its modifiers
+    pub menu: Cached<Vec<MenuItem>>,
+    /// tables with their capacities, without bills
+    pub tables: Cached<Vec<Table>>,
+    /// bills by the id of the table they are on
+    pub occupancy: Cached<HashMap<u8, i64>>,
+}
+
+impl Caches {
+    pub fn new(config: &CacheConfig) -> Self {
+        Self {
+            menu: Cached::new("menu", Duration::from_secs(config.menu_ttl_seconds)),
+            tables: Cached::new("tables", Duration::from_secs(config.tables_ttl_seconds)),
+            occupancy: Cached::new("occupancy", Duration::from_secs(config.occupancy_ttl_seconds)),
+        }
+    }
+
+    pub fn stats(&self) -> Vec<CacheStats> {
+        vec![self.menu.stats(), self.tables.stats(), self.occupancy.stats()]
+    }
+}
+
+/// Invalidate the occupancy cache on table changes made through any instance, as the listener publishes them,
+/// until the event bus closes or the shutdown starts
+pub(crate) async fn watch(state: &'static AppState, cancel_token: CancellationToken) {
+    let mut subscription = state.get_events().subscribe();
+    loop {
+        let received = tokio::select! {
+            _ = cancel_token.cancelled() => return,
+            received = subscription.recv() => received,
+        };
+        match received {
+            Some(Ok(Event::TableChanged { .. })) | Some(Err(_)) => state.get_caches().occupancy.invalidate(),
+            Some(Ok(_)) => {},
+            None => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::model::event::RowChange;
+    use super::*;
+
+    async fn load(loads: &AtomicU64) -> Result<u64, ()> {
+        Ok(loads.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+
+    #[tokio::test]
+    async fn test_get_or_load() {
+        let loads = AtomicU64::new(0);
+        let cached = Cached::new("test", Duration::from_secs(60));
+        assert_eq!(*cached.get_or_load(|| load(&loads)).await.unwrap(), 1);
+        assert_eq!(*cached.get_or_load(|| load(&loads)).await.unwrap(), 1);
+        assert_eq!(cached.get_or_load(|| async { Err::<u64, _>("unused") }).await, Ok(Arc::new(1)));
+        cached.invalidate();
+        assert_eq!(cached.get_or_load(|| async { Err::<u64, _>("failed") }).await, Err("failed"));
+        assert_eq!(*cached.get_or_load(|| load(&loads)).await.unwrap(), 2);
+        let stats = cached.stats();
+        assert_eq!((stats.hits, stats.misses, stats.invalidations, stats.cached), (2, 3, 1, true));
+
+        // off
+        let cached = Cached::new("test", Duration::ZERO);
+        assert_eq!(*cached.get_or_load(|| load(&loads)).await.unwrap(), 3);
+        assert_eq!(*cached.get_or_load(|| load(&loads)).await.unwrap(), 4);
+        assert_eq!((cached.stats().hits, cached.stats().misses, cached.stats().cached), (0, 2, false));
+    }
+
+    #[tokio::test]
+    async fn test_expiry() {
+        let loads = AtomicU64::new(0);
+        let cached = Cached::new("test", Duration::from_millis(100));
+        assert_eq!(*cached.get_or_load(|| load(&loads)).await.unwrap(), 1);
+        assert_eq!(*cached.get_or_load(|| load(&loads)).await.unwrap(), 1);
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(*cached.get_or_load(|| load(&loads)).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_while_loading() {
+        let cached = Cached::new("test", Duration::from_secs(60));
+        let value = cached.get_or_load(|| async {
+            // a write commits and invalidates while the old value is read
+            cached.invalidate();
+            Ok::<_, ()>("old")
+        }).await.unwrap();
+        assert_eq!(*value, "old");
+        assert!(!cached.stats().cached);
+    }
+
+    #[tokio::test]
+    async fn test_watch() {
+        let state: &'static AppState = Box::leak(Box::new(AppState::mock().await));
+        state.get_caches().occupancy.invalidate();
+        let watch = tokio::spawn(watch(state, CancellationToken::new()));
+        tokio::task::yield_now().await;
+        state.get_events().publish(Event::BillItemChanged { bill_id: 1, bill_item_id: 2, change: RowChange::Inserted, state: None });
+        state.get_events().publish(Event::TableChanged { table_id: 1, bill_id: Some(1) });
+        state.get_events().close();
+        watch.await.unwrap();
+        assert_eq!(state.get_caches().occupancy.stats().invalidations, 2);
+    }
+}
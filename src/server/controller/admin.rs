@@ -0,0 +1,217 @@
+use actix_web::{delete, get, post, web, HttpResponse, Responder};
+use log::{error, info};
+use crate::server::controller::error::CustomError;
+use crate::server::controller::error::CustomError::{BadRequest, DbError, ResourceNotFound};
+use crate::server::model::admin::{GetCachesResponse, GetJobsParams, GetJobsResponse, GetLeasesResponse, GetRetentionResponse, JobRun, JobStatus, JobTrigger};
+use crate::server::model::config::RetentionConfig;
+use crate::server::model::webhook::{GetWebhookDeliveriesParams, GetWebhookDeliveriesResponse, GetWebhooksResponse, PostWebhookRequest, PostWebhookResponse};
+use crate::server::{retention, webhook};
+use crate::server::scheduler::history;
+use crate::server::scheduler::lease::Leases;
+use crate::server::scheduler::Scheduler;
+use crate::server::state::AppState;
+
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/v1/admin",
+    responses(
+        (status = 200, description = "Leases of scheduled jobs and the id of the instance answering", body = GetLeasesResponse),
+    ),
+)]
+#[get("/leases")]
+/// List which instance holds the lease of each scheduled job
+async fn get_leases(data: web::Data<&AppState>) -> Result<impl Responder, CustomError> {
+    match Leases::list(&data).await {
+        Ok(leases) => Ok(web::Json(GetLeasesResponse { instance: data.get_instance_id().to_string(), leases })),
+        Err(e) => {
+            error!("get_leases failed, {:#}", e);
+            Err(DbError(e))
+        }
+    }
+}
+
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/v1/admin",
+    params(
+        GetJobsParams,
+    ),
+    responses(
+        (status = 200, description = "Scheduled jobs with their next run on the instance answering and their latest runs on every instance", body = GetJobsResponse),
+    ),
+)]
+#[get("/jobs")]
+/// List scheduled jobs, their next run and recent history
+async fn get_jobs(params: web::Query<GetJobsParams>, scheduler: web::Data<Scheduler>, data: web::Data<&AppState>) -> Result<impl Responder, CustomError> {
+    let limit = params.into_inner().runs.unwrap_or(10);
+    let names = scheduler.jobs().iter().map(|job| job.name()).collect::<Vec<_>>();
+    let mut runs = match history::recent(&data, &names, limit.into()).await {
+        Ok(runs) => runs,
+        Err(e) => {
+            error!("get_jobs failed, {:#}", e);
+            return Err(DbError(e));
+        }
+    };
+    let jobs = scheduler.jobs().iter().map(|job| JobStatus {
+        name: job.name().to_string(),
+        enabled: job.is_enabled(),
+        schedule: job.schedule().to_string(),
+        next_run_at: job.next_run_at(),
+        running: job.is_running(),
+        runs: runs.extract_if(.., |run| run.name == job.name()).collect(),
+    }).collect();
+    Ok(web::Json(GetJobsResponse { instance: data.get_instance_id().to_string(), jobs }))
+}
+
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/v1/admin",
+    params(
+        ("name" = String, Path, description = "job name"),
+    ),
+    responses(
+        (status = 200, description = "The finished run, failed ones included", body = JobRun),
+        (status = 404, description = "No such job"),
+    ),
+)]
+#[post("/jobs/{name}/run")]
+/// Run a job now on the instance answering, regardless of its schedule and lease.
+/// Responds once the run finishes, after a run in progress on this instance.
+async fn post_job_run(name: web::Path<String>, scheduler: web::Data<Scheduler>, data: web::Data<&'static AppState>) -> Result<impl Responder, CustomError> {
+    let Some(job) = scheduler.get(&name) else {
+        return Err(ResourceNotFound);
+    };
+    info!("job {} is triggered on demand", job.name());
+    Ok(web::Json(job.run(data.get_ref(), JobTrigger::Manual).await))
+}
+
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/v1/admin",
+    responses(
+        (status = 200, description = "Rows due for retention by entity, as the retention job would move them now", body = GetRetentionResponse),
+    ),
+)]
+#[get("/retention")]
+/// Report what the retention job would archive and purge, without changing anything
+async fn get_retention(config: web::Data<RetentionConfig>, data: web::Data<&AppState>) -> Result<impl Responder, CustomError> {
+    match retention::report(&data, &config).await {
+        Ok(entities) => Ok(web::Json(GetRetentionResponse { target: config.target.to_string(), dry_run: config.dry_run, entities })),
+        Err(e) => {
+            error!("get_retention failed, {:#}", e);
+            Err(DbError(e))
+        }
+    }
+}
+
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/v1/admin",
+    responses(
+        (status = 200, description = "Hits, misses and invalidations of each cache of the instance answering", body = GetCachesResponse),
+    ),
+)]
+#[get("/caches")]
+/// Report how well the in-process caches serve reads
+async fn get_caches(data: web::Data<&AppState>) -> Result<impl Responder, CustomError> {
+    Ok(web::Json(GetCachesResponse { caches: data.get_caches().stats() }))
+}
+
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/v1/admin",
+    request_body = PostWebhookRequest,
+    responses(
+        (status = 200, description = "Webhook is subscribed, with the secret its deliveries are signed with", body = PostWebhookResponse),
+        (status = 400, description = "URL is not HTTP or HTTPS, or the secret is blank"),
+    ),
+)]
+#[post("/webhooks")]
+/// Subscribe a webhook to integration events, it gets the events relayed from now on
+async fn post_webhook(body: web::Json<PostWebhookRequest>, data: web::Data<&AppState>) -> Result<impl Responder, CustomError> {
+    let PostWebhookRequest { url, events, secret } = body.into_inner();
+    if !reqwest::Url::parse(&url).is_ok_and(|url| ["http", "https"].contains(&url.scheme())) {
+        return Err(BadRequest);
+    }
+    let secret = match secret {
+        Some(secret) if secret.trim().is_empty() => return Err(BadRequest),
+        Some(secret) => secret,
+        None => webhook::generate_secret(),
+    };
+    match webhook::create(&data, &url, &events, &secret).await {
+        Ok(id) => {
+            info!("webhook {} is subscribed to {}", id, url);
+            Ok(web::Json(PostWebhookResponse { id, secret }))
+        },
+        Err(e) => {
+            error!("post_webhook failed, {:#}", e);
+            Err(DbError(e))
+        }
+    }
+}
+
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/v1/admin",
+    responses(
+        (status = 200, description = "Webhook subscriptions, oldest first, without their secrets", body = GetWebhooksResponse),
+    ),
+)]
+#[get("/webhooks")]
+/// List webhook subscriptions
+async fn get_webhooks(data: web::Data<&AppState>) -> Result<impl Responder, CustomError> {
+    match webhook::list(&data).await {
+        Ok(webhooks) => Ok(web::Json(GetWebhooksResponse { webhooks })),
+        Err(e) => {
+            error!("get_webhooks failed, {:#}", e);
+            Err(DbError(e))
+        }
+    }
+}
+
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/v1/admin",
+    responses(
+        (status = 200, description = "Webhook is unsubscribed"),
+        (status = 404, description = "Webhook not found"),
+    ),
+)]
+#[delete("/webhooks/{id}")]
+/// Unsubscribe a webhook, its pending deliveries are dropped along with its delivery log
+async fn delete_webhook(id: web::Path<i64>, data: web::Data<&AppState>) -> Result<impl Responder, CustomError> {
+    match webhook::remove(&data, id.into_inner()).await {
+        Ok(true) => Ok(HttpResponse::Ok()),
+        Ok(false) => Err(ResourceNotFound),
+        Err(e) => {
+            error!("delete_webhook failed, {:#}", e);
+            Err(DbError(e))
+        }
+    }
+}
+
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/v1/admin",
+    params(
+        ("id" = i64, Path, description = "webhook id"),
+        GetWebhookDeliveriesParams,
+    ),
+    responses(
+        (status = 200, description = "Latest deliveries of the webhook, newest first", body = GetWebhookDeliveriesResponse),
+        (status = 404, description = "Webhook not found"),
+    ),
+)]
+#[get("/webhooks/{id}/deliveries")]
+/// Inspect the delivery log of a webhook, with the status and error of the latest attempt of each delivery
+async fn get_webhook_deliveries(id: web::Path<i64>, params: web::Query<GetWebhookDeliveriesParams>, data: web::Data<&AppState>) -> Result<impl Responder, CustomError> {
+    let GetWebhookDeliveriesParams { state, limit } = params.into_inner();
+    match webhook::deliveries(&data, id.into_inner(), state, limit.unwrap_or(50).into()).await {
+        Ok(Some(deliveries)) => Ok(web::Json(GetWebhookDeliveriesResponse { deliveries })),
+        Ok(None) => Err(ResourceNotFound),
+        Err(e) => {
+            error!("get_webhook_deliveries failed, {:#}", e);
+            Err(DbError(e))
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use actix_web::{delete, get, web, HttpResponse, Responder};
+use log::error;
+use crate::server::alert;
+use crate::server::controller::error::CustomError;
+use crate::server::controller::error::CustomError::{DbError, ResourceNotFound};
+use crate::server::model::alert::GetAlertsResponse;
+use crate::server::state::AppState;
+
+#[utoipa::path(
+    tag = "alert",
+    context_path = "/v1",
+    responses(
+        (status = 200, description = "Active alerts of stale bills, oldest first", body = GetAlertsResponse),
+    ),
+)]
+#[get("/alerts")]
+/// List bills flagged by the stale bill detector
+async fn get_alerts(data: web::Data<&AppState>) -> Result<impl Responder, CustomError> {
+    match alert::list(&data).await {
+        Ok(alerts) => Ok(web::Json(GetAlertsResponse { alerts })),
+        Err(e) => {
+            error!("get_alerts failed, {:#}", e);
+            Err(DbError(e))
+        }
+    }
+}
+
+#[utoipa::path(
+    tag = "alert",
+    context_path = "/v1",
+    responses(
+        (status = 200, description = "Alert is resolved"),
+        (status = 404, description = "Alert not found or resolved already"),
+    ),
+)]
+#[delete("/alert/{id}")]
+/// Resolve an alert once the bill is looked after, it is not raised again until the bill has activity after that.
+/// A bill in review is open again once none of its alerts is active.
+async fn delete_alert(id: web::Path<i64>, data: web::Data<&AppState>) -> Result<impl Responder, CustomError> {
+    match alert::resolve(&data, id.into_inner()).await {
+        Ok(true) => Ok(HttpResponse::Ok()),
+        Ok(false) => Err(ResourceNotFound),
+        Err(e) => {
+            error!("delete_alert failed, {:#}", e);
+            Err(DbError(e))
+        }
+    }
+}
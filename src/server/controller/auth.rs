@@ -0,0 +1,96 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::middleware::Next;
+use actix_web::web;
+use log::warn;
+use crate::server::controller::error::CustomError;
+use crate::server::model::config::AuthConfig;
+
+/// Require the configured bearer token, wraps the `/v1` scope so the API documents stay public.
+/// When no token is configured everything passes, privileged routes are refused by [`privileged`].
+pub(crate) async fn authorize(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if let Some(expected) = token(&req) {
+        let given = req.headers().get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if !given.is_some_and(|given| matches(expected.as_bytes(), given.as_bytes())) {
+            return Err(CustomError::Unauthorized.into());
+        }
+    }
+    next.call(req).await
+}
+
+/// Refuse routes which change how the server runs, dump or rewrite data, when no token is configured.
+/// Wraps the admin, import and export scopes and the discount changes, inside the `/v1` scope.
+pub(crate) async fn privileged(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if token(&req).is_none() {
+        warn!("refused {} {}, configure auth.token to use it", req.method(), req.path());
+        return Err(CustomError::Unauthorized.into());
+    }
+    next.call(req).await
+}
+
+fn token(req: &ServiceRequest) -> Option<&str> {
+    req.app_data::<web::Data<Option<AuthConfig>>>()
+        .and_then(|auth| auth.as_ref().as_ref())
+        .map(|auth| auth.token.as_str())
+}
+
+/// Compare in time independent of where the bytes differ
+fn matches(expected: &[u8], given: &[u8]) -> bool {
+    expected.len() == given.len() && expected.iter().zip(given).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, App};
+    use actix_web::http::StatusCode;
+    use crate::server::routes;
+    use super::*;
+
+    // handlers reached without app state answer 500, so passing the middlewares shows as anything but 401
+
+    #[actix_web::test]
+    async fn test_authorize() {
+        let auth = Some(AuthConfig { token: "secret".to_string() });
+        let app = test::init_service(App::new().app_data(web::Data::new(auth)).configure(routes)).await;
+        let status = |req: test::TestRequest| async { test::try_call_service(&app, req.to_request()).await.map_or_else(|e| e.error_response().status(), |res| res.status()) };
+
+        assert_eq!(status(test::TestRequest::get().uri("/v1/tables")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(test::TestRequest::get().uri("/v1/tables").insert_header((AUTHORIZATION, "Bearer secreT"))).await, StatusCode::UNAUTHORIZED);
+        assert_ne!(status(test::TestRequest::get().uri("/v1/tables").insert_header((AUTHORIZATION, "Bearer secret"))).await, StatusCode::UNAUTHORIZED);
+        assert_ne!(status(test::TestRequest::get().uri("/v1/admin/jobs").insert_header((AUTHORIZATION, "Bearer secret"))).await, StatusCode::UNAUTHORIZED);
+        assert_ne!(status(test::TestRequest::post().uri("/v1/discounts").insert_header((AUTHORIZATION, "Bearer secret"))).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(test::TestRequest::get().uri("/openapi.json")).await, StatusCode::OK);
+        // routing decodes the path, the token is required on whatever it routes to
+        assert_eq!(status(test::TestRequest::get().uri("/%761/admin/jobs")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(test::TestRequest::get().uri("/v1/%74ables")).await, StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn test_authorize_without_token() {
+        let app = test::init_service(App::new().app_data(web::Data::new(None::<AuthConfig>)).configure(routes)).await;
+        let status = |req: test::TestRequest| async { test::try_call_service(&app, req.to_request()).await.map_or_else(|e| e.error_response().status(), |res| res.status()) };
+
+        assert_ne!(status(test::TestRequest::get().uri("/v1/tables")).await, StatusCode::UNAUTHORIZED);
+        assert_ne!(status(test::TestRequest::get().uri("/v1/discounts")).await, StatusCode::UNAUTHORIZED);
+        // privileged routes stay closed rather than open to anyone
+        assert_eq!(status(test::TestRequest::get().uri("/v1/admin/jobs")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(test::TestRequest::post().uri("/v1/import/menu")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(test::TestRequest::get().uri("/v1/export/bills")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(test::TestRequest::post().uri("/v1/discounts")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(test::TestRequest::delete().uri("/v1/discount/1")).await, StatusCode::UNAUTHORIZED);
+        // also when the path is encoded
+        assert_eq!(status(test::TestRequest::get().uri("/v1/%61dmin/jobs")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(test::TestRequest::get().uri("/%761/admin/jobs")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(test::TestRequest::get().uri("/v1/%65xport/bills")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(test::TestRequest::delete().uri("/v1/%64iscount/1")).await, StatusCode::UNAUTHORIZED);
+    }
+}
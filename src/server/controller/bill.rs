@@ -1,7 +1,8 @@
 use crate::server::database::pool::GenericRow;
+use std::collections::{HashMap, HashSet};
 use std::ops::{RangeInclusive};
 use std::time::Duration;
-use crate::server::model::bill::{Bill, GetBillResponse, PostBillItemsRequest};
+use crate::server::model::bill::{Bill, BillItems, BillView, GetBillParams, GetBillResponse, OrderedItem, PostBillItemsRequest, PostBillItemsResponse};
 use crate::server::state::AppState;
 use actix_web::{delete, get, post, web, HttpRequest, HttpResponse, Responder};
 use actix_web::rt::time;
@@ -12,36 +13,32 @@ use tokio_postgres::{Client, Row};
 use tokio_postgres::error::SqlState;
 use tokio_postgres::types::ToSql;
 use crate::server::controller::error::CustomError;
-use crate::server::DB_TIMEOUT_SECONDS;
 use crate::server::model::CommonRequestParams;
-use crate::server::model::item::Item;
+use crate::server::model::item::{Item, ItemGroup};
 use crate::server::database::pool::DbClient;
+use crate::server::database::pool::GenericTransaction;
+use crate::server::menu;
+use crate::server::model::event::Event;
+use crate::server::outbox;
+use crate::server::pricing;
 
-#[post("/v1/bill/{id}/items")]
-/// Add bill associated items
+#[utoipa::path(
+    tag = "bill",
+    context_path = "/v1",
+    request_body = PostBillItemsRequest,
+    responses(
+        (status = 200, description = "Items are ordered", body = PostBillItemsResponse),
+        (status = 400, description = "Unknown menu item, invalid modifiers, quantity or note, or the bill is split"),
+        (status = 404, description = "Bill not found or checked out already"),
+        (status = 504, description = "Bill is locked by another request"),
+    ),
+)]
+#[post("/bill/{id}/items")]
+/// Add bill associated items, with their modifiers and notes validated against the menu
 async fn post_bill_items(id: web::Path<i64>, body: web::Json<PostBillItemsRequest>, data: web::Data<&AppState>) -> Result<impl Responder, CustomError> {
-    const COLUMN_LEN: usize = 5;
     const TIME_TO_DELIVER_RANGE: RangeInclusive<i32> = 5..=15;
-    if let Some(mut conn) = data.get_db_write_pool().acquire(DB_TIMEOUT_SECONDS).await {
-        let mut stmt = "INSERT INTO bill_item(bill_id, menu_item_id, state, time_to_deliver, created_at) VALUES".to_string();
-        let mut idx = 1;
-        let mut params: Vec<&(dyn ToSql + Sync)> = Vec::with_capacity(body.items.len() * COLUMN_LEN);
+    if let Some(mut conn) = data.get_db_write_pool().acquire(data.get_db_timeout_seconds()).await {
         let id = id.into_inner();
-        let rand_v = (0..body.items.len()).fold(Vec::with_capacity(body.items.len()), |mut acc, _| {
-            acc.push(rand::thread_rng().gen_range(TIME_TO_DELIVER_RANGE));
-            acc
-        }).into_iter().collect::<Vec<_>>();
-        let created_at = crate::server::util::time::helper::get_utc_now();
-        for (i, menu_item_id) in body.items.iter().enumerate() {
-            let maybe_comma = if i != body.items.len() - 1 { "," } else { "" };
-            stmt.extend(format!(" (${}, ${}, ${}, ${}, ${}){}", idx, idx+1, idx+2, idx+3, idx+4, maybe_comma).chars());
-            let cur_params = [&id as &(dyn ToSql + Sync), menu_item_id as &(dyn ToSql + Sync), &"created", &rand_v[i], &created_at];
-            params.extend(cur_params.into_iter());
-            idx += COLUMN_LEN;
-        }
-
-        stmt.extend(" RETURNING id".chars());
-
         let client = match &mut conn.client {
             Some(client) => client,
             None => {
@@ -49,48 +46,196 @@ async fn post_bill_items(id: web::Path<i64>, body: web::Json<PostBillItemsReques
                 return Err(CustomError::Unknown);
             },
         };
-        match client.query(&stmt, &params.as_slice()).await {
-            Ok(rows) => rows,
+        let txn = match client.transaction().await {
+            Ok(txn) => txn,
             Err(e) => {
-                match e.code().unwrap() {
-                    &SqlState::FOREIGN_KEY_VIOLATION => {
-                        warn!("the requested bill or menu item does not exist");
-                        return Err(CustomError::BadRequest);
-                    },
-                    code => {
-                        error!("unhandled db error, code={:?}", code);
-                    },
-                };
+                error!("db error, {}", e);
                 return Err(CustomError::DbError(e.into()));
+            }
+        };
+        let params: &[&(dyn ToSql + Sync)] = &[&id];
+        let sleep = time::sleep(Duration::new(data.get_db_timeout_seconds(), 0));
+        tokio::pin!(sleep);
+        // lock the open bill, so that it cannot be split or checked out meanwhile
+        tokio::select! {
+            result = txn.query(r#"SELECT id FROM bill WHERE id = $1 AND checkout_at IS NULL FOR UPDATE"#, params) => {
+                match result {
+                    Ok(rows) if rows.is_empty() => {
+                        warn!("bill {} does not exist or is checked out already", id);
+                        return Err(CustomError::ResourceNotFound);
+                    },
+                    Ok(_) => {},
+                    Err(e) => {
+                        error!("failed to query, {}", e);
+                        return Err(CustomError::DbError(e.into()));
+                    }
+                }
+            },
+            _ = &mut sleep => {
+                warn!("timeout when trying to select bill for update");
+                return Err(CustomError::Timeout);
+            }
+        }
+        // items added after splitting would not belong to any split
+        match txn.query("SELECT id FROM bill_split WHERE bill_id = $1 LIMIT 1", params).await {
+            Ok(rows) if !rows.is_empty() => {
+                warn!("bill {} is split already, cannot add items", id);
+                return Err(CustomError::BadRequest);
             },
+            Ok(_) => {},
+            Err(e) => {
+                error!("failed to query splits, {}", e);
+                return Err(CustomError::DbError(e.into()));
+            }
+        }
+        let menu_item_ids = body.items.iter().map(|item| item.menu_item_id).collect::<HashSet<_>>().into_iter().collect();
+        let menu = match menu::load(&txn, Some(menu_item_ids)).await {
+            Ok(menu) => menu.into_iter().map(|item| (item.id, item)).collect::<HashMap<_, _>>(),
+            Err(e) => {
+                error!("failed to load menu, {}", e);
+                return Err(CustomError::DbError(e.into()));
+            }
         };
+        for item in body.items.iter() {
+            let Some(menu_item) = menu.get(&item.menu_item_id) else {
+                warn!("menu item {} does not exist", item.menu_item_id);
+                return Err(CustomError::BadRequest);
+            };
+            if let Err(e) = menu_item.select(item) {
+                warn!("invalid order of menu item {}, {}", item.menu_item_id, e);
+                return Err(CustomError::BadRequest);
+            }
+        }
 
-        return Ok(HttpResponse::Ok());
+        let created_at = crate::server::util::time::helper::get_utc_now();
+        let mut ordered = Vec::with_capacity(body.items.len());
+        for item in body.items.iter() {
+            // units of one order line are prepared together, so they share the delivery time
+            let time_to_deliver = rand::thread_rng().gen_range(TIME_TO_DELIVER_RANGE);
+            // copy the price so that later menu changes do not alter the bill
+            let unit_price = menu[&item.menu_item_id].price;
+            let params: &[&(dyn ToSql + Sync)] = &[&id, &item.menu_item_id, &"created", &time_to_deliver, &created_at, &item.note, &unit_price, &item.quantity];
+            let bill_item_ids: Vec<i64> = match txn.query(r#"
+                INSERT INTO bill_item(bill_id, menu_item_id, state, time_to_deliver, created_at, note, unit_price)
+                SELECT $1, $2, $3, $4, $5, $6, $7
+                FROM generate_series(1, $8)
+                RETURNING id
+            "#, params).await {
+                Ok(rows) => rows.iter().map(|row| row.get("id")).collect(),
+                Err(e) => {
+                    match e.code() {
+                        Some(&SqlState::FOREIGN_KEY_VIOLATION) => {
+                            warn!("the requested bill or menu item does not exist");
+                            return Err(CustomError::BadRequest);
+                        },
+                        code => {
+                            error!("unhandled db error, code={:?}", code);
+                        },
+                    };
+                    return Err(CustomError::DbError(e.into()));
+                },
+            };
+            if !item.modifiers.is_empty() {
+                // copy names and prices so that later menu changes do not alter the bill
+                let params: &[&(dyn ToSql + Sync)] = &[&bill_item_ids, &item.modifiers];
+                if let Err(e) = txn.execute(r#"
+                    INSERT INTO bill_item_modifier(bill_item_id, modifier_option_id, name, price_delta)
+                    SELECT bi.id, o.id, o.name, o.price_delta
+                    FROM unnest($1::bigint[]) AS bi(id)
+                    CROSS JOIN modifier_option o
+                    WHERE o.id = ANY($2)
+                "#, params).await {
+                    error!("failed to insert modifiers, {}", e);
+                    return Err(CustomError::DbError(e.into()));
+                }
+            }
+            ordered.push(OrderedItem { menu_item_id: item.menu_item_id, ids: bill_item_ids });
+        }
+        if let Err(e) = outbox::enqueue(&txn, &Event::ItemsOrdered { bill_id: id, items: ordered.clone(), ordered_at: created_at }).await {
+            error!("failed to write the outbox, {:#}", e);
+            return Err(CustomError::DbError(e));
+        }
+        if let Err(e) = txn.commit().await {
+            error!("failed to commit, {}", e);
+            return Err(CustomError::DbError(e.into()));
+        }
+
+        return Ok(web::Json(PostBillItemsResponse { items: ordered }));
 
     }
     Err(CustomError::ServerIsBusy)
 }
 
-#[delete("/v1/bill/{id}/item/{item_id}")]
+#[utoipa::path(
+    tag = "bill",
+    context_path = "/v1",
+    responses(
+        (status = 200, description = "Item is removed"),
+        (status = 400, description = "Bill is split, undo the split first"),
+        (status = 404, description = "Item not found, or the bill is checked out"),
+        (status = 504, description = "Bill is locked by another request"),
+    ),
+)]
+#[delete("/bill/{id}/item/{item_id}")]
 /// Remove one specific bill item
 async fn delete_bill_items(path: web::Path<(i64, i64)>, data: web::Data<&AppState>) -> Result<impl Responder, CustomError> {
     let (id, item_id) = path.into_inner();
     let params: &[&(dyn ToSql + Sync)] = &[&item_id, &id];
-    if let Some(conn) = data.get_db_write_pool().acquire(DB_TIMEOUT_SECONDS).await {
-        let sleep = time::sleep(Duration::new(DB_TIMEOUT_SECONDS, 0));
+    if let Some(mut conn) = data.get_db_write_pool().acquire(data.get_db_timeout_seconds()).await {
+        let sleep = time::sleep(Duration::new(data.get_db_timeout_seconds(), 0));
         tokio::pin!(sleep);
-        let client = conn.client.as_ref().unwrap();
-        return tokio::select! {
-            result = client.execute(r#"
+        let client = conn.client.as_mut().unwrap();
+        let txn = match client.transaction().await {
+            Ok(txn) => txn,
+            Err(e) => {
+                error!("db error, {}", e);
+                return Err(CustomError::DbError(e.into()));
+            }
+        };
+        // lock the open bill, so that it cannot be split meanwhile
+        let params_id: &[&(dyn ToSql + Sync)] = &[&id];
+        tokio::select! {
+            result = txn.query(r#"SELECT id FROM bill WHERE id = $1 AND checkout_at IS NULL FOR UPDATE"#, params_id) => {
+                match result {
+                    Ok(rows) if rows.is_empty() => {
+                        warn!("bill {} does not exist or is checked out already", id);
+                        return Err(CustomError::ResourceNotFound);
+                    },
+                    Ok(_) => {},
+                    Err(e) => {
+                        error!("failed to query, {}", e);
+                        return Err(CustomError::DbError(e.into()));
+                    }
+                }
+            },
+            _ = &mut sleep => {
+                warn!("timeout when trying to select bill for update");
+                return Err(CustomError::Timeout);
+            }
+        }
+        // the amounts of splits are fixed when splitting, so items of a split bill stay until the split is undone
+        let split = txn.query("SELECT id FROM bill_split WHERE bill_id = $1 LIMIT 1", params_id).await;
+        match split {
+            Ok(rows) if !rows.is_empty() => {
+                warn!("bill {} is split, item {} is not removed", id, item_id);
+                return Err(CustomError::BadRequest);
+            },
+            Ok(_) => {},
+            Err(e) => {
+                error!("failed to query splits, {}", e);
+                return Err(CustomError::DbError(e.into()));
+            }
+        }
+        let removed = tokio::select! {
+            result = txn.execute(r#"
                 UPDATE bill_item SET state = 'deleted'
                 WHERE id = $1 AND bill_id = $2
             "#, params) => {
                 match result {
-                    Ok(0) => Err(CustomError::ResourceNotFound),
-                    Ok(_) => Ok(HttpResponse::Ok()),
+                    Ok(removed) => removed,
                     Err(e) => {
                         warn!("delete_bill_items failed, {}", e);
-                        Err(CustomError::DbError(e.into()))
+                        return Err(CustomError::DbError(e.into()));
                     }
                 }
             },
@@ -98,15 +243,36 @@ async fn delete_bill_items(path: web::Path<(i64, i64)>, data: web::Data<&AppStat
                 warn!("timeout deleting a bill item");
                 return Err(CustomError::Timeout);
             }
+        };
+        if removed == 0 {
+            return Err(CustomError::ResourceNotFound);
+        }
+        if let Err(e) = outbox::enqueue(&txn, &Event::ItemRemoved { bill_id: id, bill_item_id: item_id }).await {
+            error!("failed to write the outbox, {:#}", e);
+            return Err(CustomError::DbError(e));
+        }
+        if let Err(e) = txn.commit().await {
+            error!("failed to commit, {}", e);
+            return Err(CustomError::DbError(e.into()));
         }
+        return Ok(HttpResponse::Ok());
     }
     Err(CustomError::ServerIsBusy)
 }
 
-#[get("/v1/bill/{id}")]
+#[utoipa::path(
+    tag = "bill",
+    context_path = "/v1",
+    params(CommonRequestParams, GetBillParams),
+    responses(
+        (status = 200, description = "Bill with a page of items and totals", body = GetBillResponse),
+        (status = 400, description = "Invalid paging or view"),
+    ),
+)]
+#[get("/bill/{id}")]
 /// get bill items
 async fn get_bill(id: web::Path<i64>, req: HttpRequest, data: web::Data<&AppState>) -> Result<impl Responder, CustomError> {
-    if let Some(conn) = data.get_db_read_pool().acquire(DB_TIMEOUT_SECONDS).await {
+    if let Some(mut conn) = data.get_db_read_pool().acquire(data.get_db_timeout_seconds()).await {
         let maybe_queries = web::Query::<CommonRequestParams>::from_query(req.query_string()).context("failed to parse query string");
         if maybe_queries.is_err() {
             return Err(CustomError::BadRequest);
@@ -117,23 +283,44 @@ async fn get_bill(id: web::Path<i64>, req: HttpRequest, data: web::Data<&AppStat
         } = maybe_queries.unwrap().into_inner();
         let (page, page_size) = (maybe_page.unwrap_or(0), maybe_page_size.unwrap_or(20));
         let id = id.into_inner();
-        let client = conn.client.as_ref().unwrap();
-        return match client.query(r##"
-            SELECT b.id, mi.name, b.time_to_deliver, b.state
-            FROM bill_item b
-            JOIN menu_item mi
-            ON b.menu_item_id = mi.id
-            WHERE bill_id = $1 AND b.state IS DISTINCT FROM 'deleted'
-            OFFSET $2
-            LIMIT $3
-            ;
-        "##, &[&id, &(page as i64) as &(dyn ToSql + Sync), &(page_size as i64) as &(dyn ToSql + Sync)]).await {
-            Ok(rows) => {
+        let client = conn.client.as_mut().unwrap();
+        // read items and totals from the same snapshot
+        let txn = match client.transaction().await {
+            Ok(txn) => txn,
+            Err(e) => {
+                error!("db error, {}", e);
+                return Err(CustomError::DbError(e.into()));
+            }
+        };
+        let totals = match pricing::load(&txn, id, data.get_time_zone()).await {
+            Ok(totals) => totals,
+            Err(e) => {
+                error!("failed to price bill, {}", e);
+                return Err(CustomError::DbError(e.into()));
+            }
+        };
+        let view = match web::Query::<GetBillParams>::from_query(req.query_string()) {
+            Ok(params) => params.into_inner().view.unwrap_or_default(),
+            Err(_) => return Err(CustomError::BadRequest),
+        };
+        let params: &[&(dyn ToSql + Sync)] = &[&id, &(page as i64), &(page_size as i64)];
+        let items = match view {
+            BillView::Detailed => txn.query(r##"
+                SELECT b.id, mi.name, b.time_to_deliver, b.state, b.note,
+                    ARRAY(SELECT m.name FROM bill_item_modifier m WHERE m.bill_item_id = b.id ORDER BY m.modifier_option_id) AS modifiers
+                FROM bill_item b
+                JOIN menu_item mi
+                ON b.menu_item_id = mi.id
+                WHERE bill_id = $1 AND b.state IS DISTINCT FROM 'deleted'
+                OFFSET $2
+                LIMIT $3
+                ;
+            "##, params).await.map(|rows| {
                 let items = rows.into_iter().map_while(|r|
-                    match (r.try_get("id"), r.try_get("name"), r.try_get("time_to_deliver"), r.try_get("state")) {
-                        (Ok(id), Ok(name), Ok(time_to_deliver), Ok(state)) => {
+                    match (r.try_get("id"), r.try_get("name"), r.try_get("time_to_deliver"), r.try_get("state"), r.try_get("modifiers"), r.try_get("note")) {
+                        (Ok(id), Ok(name), Ok(time_to_deliver), Ok(state), Ok(modifiers), Ok(note)) => {
                             Some(Item {
-                                id, name, time_to_deliver, state
+                                id, name, time_to_deliver, state, modifiers, note
                             })
                         },
                         _ => {
@@ -141,15 +328,49 @@ async fn get_bill(id: web::Path<i64>, req: HttpRequest, data: web::Data<&AppStat
                         }
                     }
                 ).collect::<Vec<_>>();
-                
+                (!items.is_empty()).then_some(BillItems::Detailed(items))
+            }),
+            // item_list_idx only finds the items of the bill, they are grouped after their modifiers are joined, which stays
+            // cheap as a bill has few items
+            BillView::Aggregated => txn.query(r##"
+                SELECT b.menu_item_id, mi.name, b.state, b.note, mods.names AS modifiers,
+                    COUNT(*) AS quantity, array_agg(b.id ORDER BY b.id) AS ids
+                FROM bill_item b
+                JOIN menu_item mi
+                ON b.menu_item_id = mi.id
+                CROSS JOIN LATERAL (
+                    SELECT ARRAY(SELECT m.name FROM bill_item_modifier m WHERE m.bill_item_id = b.id ORDER BY m.modifier_option_id) AS names
+                ) mods
+                WHERE bill_id = $1 AND b.state IS DISTINCT FROM 'deleted'
+                GROUP BY b.menu_item_id, mi.name, b.state, b.note, mods.names
+                ORDER BY MIN(b.id)
+                OFFSET $2
+                LIMIT $3
+                ;
+            "##, params).await.map(|rows| {
+                let groups = rows.into_iter().map_while(|r|
+                    match (r.try_get("menu_item_id"), r.try_get("name"), r.try_get("modifiers"), r.try_get("note"), r.try_get("state"), r.try_get("quantity"), r.try_get("ids")) {
+                        (Ok(menu_item_id), Ok(name), Ok(modifiers), Ok(note), Ok(state), Ok(quantity), Ok(ids)) => {
+                            Some(ItemGroup {
+                                menu_item_id, name, modifiers, note, state, quantity, ids
+                            })
+                        },
+                        _ => {
+                            None
+                        }
+                    }
+                ).collect::<Vec<_>>();
+                (!groups.is_empty()).then_some(BillItems::Aggregated(groups))
+            }),
+        };
+        return match items {
+            Ok(items) => {
                 Ok(web::Json(GetBillResponse {
-                    bill: match items.is_empty() {
-                        true => None,
-                        false => Some(Bill {
-                            id,
-                            items,
-                        })
-                    },
+                    bill: items.map(|items| Bill {
+                        id,
+                        items,
+                        totals,
+                    }),
                 }))
             }
             Err(e) => {
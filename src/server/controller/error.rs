@@ -15,6 +15,12 @@ pub(crate) enum CustomError {
     DbError(anyhow::Error),
     #[display("timeout occurred")]
     Timeout,
+    #[display("payment declined")]
+    PaymentDeclined,
+    #[display("unauthorized")]
+    Unauthorized,
+    #[display("refunded at the provider as {_0}, but not recorded")]
+    RefundNotRecorded(#[error(not(source))] String),
     #[display("unknown")]
     Unknown
 }
@@ -22,10 +28,12 @@ pub(crate) enum CustomError {
 impl error::ResponseError for CustomError {
     fn status_code(&self) -> StatusCode {
         match *self {
-            CustomError::ServerIsBusy | CustomError::DbError(_) | CustomError::Unknown => StatusCode::INTERNAL_SERVER_ERROR,
+            CustomError::ServerIsBusy | CustomError::DbError(_) | CustomError::RefundNotRecorded(_) | CustomError::Unknown => StatusCode::INTERNAL_SERVER_ERROR,
             CustomError::BadRequest => StatusCode::BAD_REQUEST,
             CustomError::ResourceNotFound => StatusCode::NOT_FOUND,
             CustomError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            CustomError::PaymentDeclined => StatusCode::PAYMENT_REQUIRED,
+            CustomError::Unauthorized => StatusCode::UNAUTHORIZED,
         }
     }
 
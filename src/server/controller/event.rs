@@ -0,0 +1,39 @@
+use std::time::Duration;
+use actix_web::{get, web, HttpResponse, Responder};
+use actix_web::http::header::{CacheControl, CacheDirective};
+use futures_util::stream;
+use tokio::{select, time};
+use crate::server::model::event::Event;
+use crate::server::state::AppState;
+
+/// Seconds between comments sent on an idle stream, so that proxies keep the connection open
+const KEEP_ALIVE_SECONDS: u64 = 15;
+
+#[utoipa::path(
+    tag = "event",
+    context_path = "/v1",
+    responses(
+        (status = 200, description = "Server-sent events, the data of each is an event in JSON. \
+            A `lagged` event tells how many events a slow client missed", body = Event, content_type = "text/event-stream"),
+    ),
+)]
+#[get("/events")]
+/// Stream events as they happen, until the server shuts down
+async fn get_events(data: web::Data<&AppState>) -> impl Responder {
+    let subscription = data.get_events().subscribe();
+    let keep_alive = time::interval(Duration::from_secs(KEEP_ALIVE_SECONDS));
+    let events = stream::unfold((subscription, keep_alive), |(mut subscription, mut keep_alive)| async move {
+        let chunk = select! {
+            received = subscription.recv() => match received? {
+                Ok(event) => format!("data: {}\n\n", serde_json::to_string(&event).ok()?),
+                Err(missed) => format!("event: lagged\ndata: {{\"missed\":{}}}\n\n", missed),
+            },
+            _ = keep_alive.tick() => ": keep-alive\n\n".to_string(),
+        };
+        Some((Ok::<_, actix_web::Error>(web::Bytes::from(chunk)), (subscription, keep_alive)))
+    });
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .streaming(events)
+}
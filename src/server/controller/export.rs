@@ -0,0 +1,176 @@
+#[cfg(test)]
+use crate::server::database::pool::{DbClient, GenericRow, GenericTransaction};
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use actix_web::http::header::CONTENT_TYPE;
+use actix_web::web::Bytes;
+use chrono::DateTime;
+use futures_util::stream;
+use log::{error, info, warn};
+use tokio_postgres::types::ToSql;
+use crate::server::controller::error::CustomError;
+use crate::server::controller::error::CustomError::DbError;
+use crate::server::export;
+use crate::server::model::export::{ExportFormat, ExportKind, ExportParams, ImportMenuParams, ImportMenuResponse, MenuItemRecord};
+use crate::server::state::AppState;
+
+#[utoipa::path(
+    tag = "export",
+    context_path = "/v1/export",
+    params(
+        ("kind" = ExportKind, Path, description = "bills, items or payments"),
+        ExportParams,
+    ),
+    responses(
+        (status = 200, description = "CSV with a header line, or one JSON record per line", content((String = "text/csv"), (String = "application/x-ndjson"))),
+    ),
+)]
+#[get("/{kind}")]
+/// Stream bills, items or payments created in a time range, page by page in id order
+async fn get_export(
+    kind: web::Path<ExportKind>,
+    params: web::Query<ExportParams>,
+    data: web::Data<&AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let kind = kind.into_inner();
+    let ExportParams { from, to, format } = params.into_inner();
+    let range = (
+        from.unwrap_or(DateTime::UNIX_EPOCH),
+        to.unwrap_or_else(crate::server::util::time::helper::get_utc_now),
+    );
+    let format = format.unwrap_or_default();
+    let state = (**data).clone();
+    // fetch the first page before responding, so that an unavailable database is reported with a status code
+    let (records, next) = export::page(&state, kind, range, 0).await.map_err(|e| {
+        error!("failed to export {:?}, {:#}", kind, e);
+        DbError(e)
+    })?;
+    let first = export::encode(&records, format, true).map_err(|e| {
+        error!("failed to encode {:?}, {:#}", kind, e);
+        CustomError::Unknown
+    })?;
+    let rest = stream::unfold(next, move |after| {
+        let state = state.clone();
+        async move {
+            let after = after?;
+            let page = export::page(&state, kind, range, after).await.and_then(|(records, next)| {
+                Ok((Bytes::from(export::encode(&records, format, false)?), next))
+            });
+            match page {
+                Ok((bytes, next)) => Some((Ok(bytes), next)),
+                Err(e) => {
+                    error!("failed to export {:?} after {}, {:#}", kind, after, e);
+                    Some((Err(e), None))
+                }
+            }
+        }
+    });
+    let content_type = match format {
+        ExportFormat::Csv => "text/csv; charset=utf-8",
+        ExportFormat::Ndjson => "application/x-ndjson",
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .streaming(stream::StreamExt::chain(stream::iter([Ok::<_, anyhow::Error>(Bytes::from(first))]), rest)))
+}
+
+/// Prices of the menu items with the ids given as $1
+const EXISTING_PRICES_QUERY: &str = "SELECT id, price FROM menu_item WHERE id = ANY($1)";
+
+#[utoipa::path(
+    tag = "export",
+    context_path = "/v1/import",
+    params(
+        ImportMenuParams,
+    ),
+    request_body(description = "CSV with a header line, or one JSON item per line", content((Vec<MenuItemRecord> = "text/csv"), (MenuItemRecord = "application/x-ndjson"))),
+    responses(
+        (status = 200, description = "Counts of created and updated items, with the repriced ones", body = ImportMenuResponse),
+        (status = 400, description = "Items with problems, nothing is written", body = ImportMenuResponse),
+    ),
+)]
+#[post("/menu")]
+/// Create or update menu items from CSV (`text/csv`) or NDJSON, nothing is written when any item is invalid or in dry run
+async fn post_import_menu(
+    req: HttpRequest,
+    body: Bytes,
+    params: web::Query<ImportMenuParams>,
+    data: web::Data<&AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let dry_run = params.into_inner().dry_run;
+    let format = match req.headers().get(CONTENT_TYPE).and_then(|value| value.to_str().ok()) {
+        Some(content_type) if content_type.starts_with("text/csv") => ExportFormat::Csv,
+        _ => ExportFormat::Ndjson,
+    };
+    let (records, mut errors) = export::parse_menu(&body, format);
+    errors.extend(export::validate_menu(&records));
+    if !errors.is_empty() {
+        warn!("rejected menu import with {} errors", errors.len());
+        return Ok(HttpResponse::BadRequest().json(ImportMenuResponse { dry_run, errors, ..ImportMenuResponse::default() }));
+    }
+    if let Some(mut conn) = data.get_db_write_pool().acquire(data.get_db_timeout_seconds()).await {
+        let client = conn.client.as_mut().unwrap();
+        let mut response = ImportMenuResponse { dry_run, ..ImportMenuResponse::default() };
+        if dry_run {
+            // sequences are not rolled back with a transaction, so a dry run only reads which of the ids exist,
+            // items given with ids are updated when they do and every other item is created
+            let ids = records.iter().filter_map(|(_, record)| record.id).collect::<Vec<_>>();
+            let params: &[&(dyn ToSql + Sync)] = &[&ids];
+            let existing = client.query(EXISTING_PRICES_QUERY, params).await;
+            let existing = existing.map_err(|e| {
+                error!("failed to query menu items, {}", e);
+                DbError(e.into())
+            })?;
+            let existing = existing.iter().map(|row| (row.get("id"), row.get("price"))).collect::<Vec<_>>();
+            response.repriced = export::repriced(&records, &existing);
+            response.updated = existing.len() as u64;
+            response.created = records.len() as u64 - response.updated;
+            return Ok(HttpResponse::Ok().json(response));
+        }
+        let txn = client.transaction().await.map_err(|e| {
+            error!("db error, {}", e);
+            DbError(e.into())
+        })?;
+        // bill items keep the price they were ordered at, so changing prices does not alter any bill
+        let ids = records.iter().filter_map(|(_, record)| record.id).collect::<Vec<_>>();
+        let params: &[&(dyn ToSql + Sync)] = &[&ids];
+        let existing = txn.query(EXISTING_PRICES_QUERY, params).await.map_err(|e| {
+            error!("failed to query menu items, {}", e);
+            DbError(e.into())
+        })?;
+        let existing = existing.iter().map(|row| (row.get("id"), row.get("price"))).collect::<Vec<_>>();
+        response.repriced = export::repriced(&records, &existing);
+        for (_, record) in records.iter().filter(|(_, record)| record.id.is_some()) {
+            let params: &[&(dyn ToSql + Sync)] = &[&record.id, &record.name, &record.category, &record.price];
+            let rows = txn.query(r#"
+                INSERT INTO menu_item(id, name, category, price)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (id) DO UPDATE SET name = $2, category = $3, price = $4
+                RETURNING (xmax = 0) AS created
+            "#, params).await.map_err(|e| {
+                error!("failed to upsert menu item, {}", e);
+                DbError(e.into())
+            })?;
+            match rows.first().map(|row| row.get::<&str, bool>("created")) {
+                Some(true) => response.created += 1,
+                _ => response.updated += 1,
+            }
+        }
+        // items given with ids may be beyond the sequence
+        txn.execute("SELECT setval(pg_get_serial_sequence('menu_item', 'id'), (SELECT MAX(id) FROM menu_item))", &[]).await.map_err(|e| {
+            error!("failed to advance menu item ids, {}", e);
+            DbError(e.into())
+        })?;
+        for (_, record) in records.iter().filter(|(_, record)| record.id.is_none()) {
+            let params: &[&(dyn ToSql + Sync)] = &[&record.name, &record.category, &record.price];
+            response.created += txn.execute("INSERT INTO menu_item(name, category, price) VALUES ($1, $2, $3)", params).await.map_err(|e| {
+                error!("failed to insert menu item, {}", e);
+                DbError(e.into())
+            })?;
+        }
+        txn.commit().await.map_err(|e| DbError(e.into()))?;
+        data.get_caches().menu.invalidate();
+        info!("imported menu, created {}, updated {}, repriced {:?}", response.created, response.updated, response.repriced);
+        return Ok(HttpResponse::Ok().json(response));
+    }
+    Err(CustomError::ServerIsBusy)
+}
@@ -0,0 +1,33 @@
+#[cfg(test)]
+use crate::server::database::pool::DbClient;
+use actix_web::{get, web, Responder};
+use log::error;
+use crate::server::controller::error::CustomError;
+use crate::server::controller::error::CustomError::DbError;
+use crate::server::menu;
+use crate::server::model::menu::GetMenuResponse;
+use crate::server::state::AppState;
+
+#[utoipa::path(
+    tag = "menu",
+    context_path = "/v1",
+    responses(
+        (status = 200, description = "Menu items with their modifier groups", body = GetMenuResponse),
+    ),
+)]
+#[get("/menu")]
+/// List menu items with their modifier groups and options, served from the menu cache while fresh
+async fn get_menu(data: web::Data<&AppState>) -> Result<impl Responder, CustomError> {
+    let items = data.get_caches().menu.get_or_load(|| async {
+        let Some(mut conn) = data.get_db_read_pool().acquire(data.get_db_timeout_seconds()).await else {
+            return Err(CustomError::ServerIsBusy);
+        };
+        let client = conn.client.as_mut().unwrap();
+        let txn = client.transaction().await.map_err(|e| DbError(e.into()))?;
+        menu::load(&txn, None).await.map_err(|e| {
+            error!("get_menu failed, {}", e);
+            DbError(e.into())
+        })
+    }).await?;
+    Ok(web::Json(GetMenuResponse { items: items.to_vec() }))
+}
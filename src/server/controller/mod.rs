@@ -1,3 +1,16 @@
+pub mod admin;
+pub mod alert;
 pub mod bill;
 pub mod table;
+pub mod split;
+pub mod payment;
+pub mod pricing;
+pub mod receipt;
+pub mod menu;
+pub mod reservation;
+pub mod report;
+pub mod export;
+pub mod event;
+pub mod openapi;
+pub mod auth;
 pub mod error;
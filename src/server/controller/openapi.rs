@@ -0,0 +1,87 @@
+use actix_web::{get, web, Responder};
+use utoipa::OpenApi;
+use crate::server::controller::{admin, alert, bill, event, export, menu, payment, pricing, receipt, report, reservation, split, table};
+
+/// OpenAPI document of every handler, schemas of models are collected from the handlers
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "bookish-eureka", description = "Restaurant ordering, payment and reporting API"),
+    paths(
+        table::get_tables,
+        table::patch_table,
+        table::post_table,
+        bill::get_bill,
+        bill::post_bill_items,
+        bill::delete_bill_items,
+        split::post_bill_splits,
+        split::get_bill_splits,
+        split::delete_bill_splits,
+        payment::post_bill_payments,
+        payment::get_bill_payments,
+        pricing::post_discount,
+        pricing::get_discounts,
+        pricing::delete_discount,
+        pricing::post_bill_comps,
+        receipt::get_bill_receipt,
+        receipt::get_bill_ticket,
+        menu::get_menu,
+        reservation::post_reservation,
+        reservation::get_reservations,
+        reservation::delete_reservation,
+        reservation::post_waitlist,
+        reservation::get_waitlist,
+        reservation::delete_waitlist,
+        report::get_revenue_report,
+        report::get_item_sales_report,
+        report::get_category_sales_report,
+        report::get_turnover_report,
+        report::get_delivery_report,
+        report::get_void_report,
+        export::get_export,
+        export::post_import_menu,
+        admin::get_leases,
+        admin::get_jobs,
+        admin::post_job_run,
+        admin::get_retention,
+        admin::get_caches,
+        admin::post_webhook,
+        admin::get_webhooks,
+        admin::delete_webhook,
+        admin::get_webhook_deliveries,
+        event::get_events,
+        alert::get_alerts,
+        alert::delete_alert,
+    ),
+)]
+pub(crate) struct ApiDoc;
+
+#[get("/openapi.json")]
+/// Serve the OpenAPI document
+async fn get_openapi() -> impl Responder {
+    web::Json(ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod tests {
+    use utoipa::openapi::path::HttpMethod;
+    use super::*;
+
+    #[test]
+    fn test_openapi() {
+        let doc = ApiDoc::openapi();
+        let operations = doc.paths.paths.values().map(|item| {
+            [&item.get, &item.post, &item.patch, &item.delete].iter().filter(|operation| operation.is_some()).count()
+        }).sum::<usize>();
+        assert_eq!(operations, 44);
+
+        let bill = doc.paths.get_path_operation("/v1/bill/{id}", HttpMethod::Get).unwrap();
+        let mut params = bill.parameters.as_ref().unwrap().iter().map(|param| param.name.as_str()).collect::<Vec<_>>();
+        params.sort();
+        assert_eq!(params, vec!["id", "page", "page_size", "view"]);
+
+        let schemas = &doc.components.as_ref().unwrap().schemas;
+        for name in ["PostBillItemsRequest", "OrderItem", "GetBillResponse", "PricedBill", "Reservation", "RevenueRow"] {
+            assert!(schemas.contains_key(name), "{} is missing", name);
+        }
+    }
+}
@@ -0,0 +1,320 @@
+#[cfg(test)]
+use crate::server::database::pool::DbClient;
+use crate::server::database::pool::{GenericRow, GenericTransaction, WrappedRow};
+use std::time::Duration;
+use actix_web::{get, post, web, Responder};
+use actix_web::rt::time;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use log::{error, info, warn};
+use tokio_postgres::types::ToSql;
+use crate::server::controller::error::CustomError;
+use crate::server::controller::error::CustomError::{BadRequest, DbError, PaymentDeclined, RefundNotRecorded, ResourceNotFound, Timeout};
+use crate::server::model::payment::{Balance, GetPaymentsResponse, Payment, PostPaymentRequest, PostPaymentResponse};
+use crate::server::model::split::{SPLIT_STATE_OPEN, SPLIT_STATE_SETTLED};
+use crate::server::payment;
+use crate::server::payment::{PAYMENT_KIND_PAYMENT, PAYMENT_KIND_REFUND};
+use crate::server::state::AppState;
+
+#[utoipa::path(
+    tag = "payment",
+    context_path = "/v1",
+    request_body = PostPaymentRequest,
+    responses(
+        (status = 200, description = "Recorded payment or refund with the balance after it", body = PostPaymentResponse),
+        (status = 400, description = "Invalid amount, or more than outstanding or refundable"),
+        (status = 402, description = "Payment declined by the provider"),
+        (status = 404, description = "Bill, split or payment not found"),
+        (status = 500, description = "Refunded at the provider but not recorded, the body names the provider reference"),
+    ),
+)]
+#[post("/bill/{id}/payments")]
+/// Record a payment or a refund for a bill
+async fn post_bill_payments(
+    id: web::Path<i64>,
+    body: web::Json<PostPaymentRequest>,
+    data: web::Data<&AppState>,
+) -> Result<impl Responder, CustomError> {
+    if let Some(mut conn) = data.get_db_write_pool().acquire(data.get_db_timeout_seconds()).await {
+        let client = conn.client.as_mut().unwrap();
+        let id = id.into_inner();
+        let timeout_seconds = data.get_db_timeout_seconds();
+        let time_zone = data.get_time_zone();
+        let created_at = crate::server::util::time::helper::get_utc_now();
+
+        let (payment, balance) = match body.into_inner() {
+            PostPaymentRequest::Payment { provider, amount, tip, split_id } => {
+                // charging may take a while, so the bill is locked to check the payment before it and to record it after it,
+                // as another payment may be recorded meanwhile, a charge that cannot be recorded is refunded
+                {
+                    let txn = client.transaction().await.map_err(|e| {
+                        error!("db error, {}", e);
+                        DbError(e.into())
+                    })?;
+                    let checked_out = lock_bill(&txn, id, timeout_seconds).await?;
+                    check_payment(&txn, id, time_zone, checked_out, amount, tip, split_id).await?;
+                }
+                let (provider, reference) = payment::charge(provider, amount, tip).await.map_err(|e| {
+                    warn!("failed to charge bill {}, {}", id, e);
+                    PaymentDeclined
+                })?;
+                let recorded: Result<_, CustomError> = async {
+                    let txn = client.transaction().await.map_err(|e| {
+                        error!("db error, {}", e);
+                        DbError(e.into())
+                    })?;
+                    let checked_out = lock_bill(&txn, id, timeout_seconds).await?;
+                    check_payment(&txn, id, time_zone, checked_out, amount, tip, split_id).await?;
+                    record(txn, id, time_zone, Payment {
+                        id: 0,
+                        split_id,
+                        refund_of: None,
+                        kind: PAYMENT_KIND_PAYMENT.to_string(),
+                        provider: provider.to_string(),
+                        amount,
+                        tip,
+                        reference: reference.clone(),
+                        created_at,
+                    }).await
+                }.await;
+                if recorded.is_err() {
+                    match payment::refund(provider, reference.as_deref(), amount + tip).await {
+                        Ok(_) => warn!("refunded the charge {:?} of bill {}, as it is not recorded", reference, id),
+                        Err(e) => error!("failed to refund the charge {:?} of bill {}, which is not recorded, {}", reference, id, e),
+                    }
+                }
+                recorded?
+            },
+            PostPaymentRequest::Refund { payment_id, amount } => {
+                // as with charges, the provider is not called while the bill is locked,
+                // a refund that cannot be recorded is logged with its reference to be recorded by hand
+                let (provider, original_reference) = {
+                    let txn = client.transaction().await.map_err(|e| {
+                        error!("db error, {}", e);
+                        DbError(e.into())
+                    })?;
+                    lock_bill(&txn, id, timeout_seconds).await?;
+                    let original = check_refund(&txn, id, payment_id, amount).await?;
+                    (original.provider, original.reference)
+                };
+                let reference = payment::refund(&provider, original_reference.as_deref(), amount).await.map_err(|e| {
+                    warn!("failed to refund payment {}, {}", payment_id, e);
+                    PaymentDeclined
+                })?;
+                let recorded: Result<_, CustomError> = async {
+                    let txn = client.transaction().await.map_err(|e| {
+                        error!("db error, {}", e);
+                        DbError(e.into())
+                    })?;
+                    lock_bill(&txn, id, timeout_seconds).await?;
+                    let original = check_refund(&txn, id, payment_id, amount).await?;
+                    record(txn, id, time_zone, Payment {
+                        id: 0,
+                        split_id: original.split_id,
+                        refund_of: Some(payment_id),
+                        kind: PAYMENT_KIND_REFUND.to_string(),
+                        provider: provider.clone(),
+                        amount,
+                        tip: 0,
+                        reference: reference.clone(),
+                        created_at,
+                    }).await
+                }.await;
+                recorded.map_err(|e| {
+                    error!("refunded {} of payment {} of bill {} at {} as {:?}, but failed to record it, {}", amount, payment_id, id, provider, reference, e);
+                    RefundNotRecorded(reference.unwrap_or_else(|| "no reference".to_string()))
+                })?
+            },
+        };
+        info!("recorded {} {} for bill {}, amount={}", payment.kind, payment.id, id, payment.amount);
+        return Ok(web::Json(PostPaymentResponse { payment, balance }));
+    }
+    Err(CustomError::ServerIsBusy)
+}
+
+/// Lock the bill within the transaction, so that concurrent payments cannot overpay it, returns whether it is checked out
+async fn lock_bill<R, T>(txn: &T, id: i64, timeout_seconds: u64) -> Result<bool, CustomError>
+where
+    R: GenericRow,
+    WrappedRow<R>: GenericRow,
+    T: GenericTransaction<R>,
+{
+    let params: &[&(dyn ToSql + Sync)] = &[&id];
+    let sleep = time::sleep(Duration::new(timeout_seconds, 0));
+    tokio::pin!(sleep);
+    tokio::select! {
+        result = txn.query(r#"SELECT checkout_at FROM bill WHERE id = $1 FOR UPDATE"#, params) => {
+            match result {
+                Ok(rows) if rows.is_empty() => {
+                    warn!("bill {} does not exist", id);
+                    Err(ResourceNotFound)
+                },
+                Ok(rows) => Ok(rows[0].get::<&str, Option<DateTime<Utc>>>("checkout_at").is_some()),
+                Err(e) => {
+                    error!("failed to query, {}", e);
+                    Err(DbError(e.into()))
+                }
+            }
+        },
+        _ = &mut sleep => {
+            warn!("timeout when trying to select bill for update");
+            Err(Timeout)
+        }
+    }
+}
+
+/// Check a payment against what is outstanding of the locked bill and of the split it pays for
+async fn check_payment<R, T>(txn: &T, id: i64, time_zone: Tz, checked_out: bool, amount: i64, tip: i64, split_id: Option<i64>) -> Result<(), CustomError>
+where
+    R: GenericRow,
+    WrappedRow<R>: GenericRow,
+    T: GenericTransaction<R>,
+{
+    let balance = payment::balance(txn, id, time_zone).await.map_err(|e| DbError(e.into()))?;
+    if checked_out || amount <= 0 || tip < 0 || amount > balance.outstanding {
+        warn!("invalid payment for bill {}, amount={}, tip={}, outstanding={}", id, amount, tip, balance.outstanding);
+        return Err(BadRequest);
+    }
+    if let Some(split_id) = split_id {
+        let rows = txn.query(r#"
+            SELECT s.amount - COALESCE(SUM(CASE WHEN p.kind = 'payment' THEN p.amount ELSE -p.amount END), 0)::bigint AS outstanding
+            FROM bill_split s
+            LEFT JOIN payment p
+            ON p.split_id = s.id
+            WHERE s.id = $1 AND s.bill_id = $2
+            GROUP BY s.id
+        "#, &[&split_id as &(dyn ToSql + Sync), &id]).await.map_err(|e| DbError(e.into()))?;
+        match rows.first().map(|row| row.get::<&str, i64>("outstanding")) {
+            None => return Err(ResourceNotFound),
+            Some(outstanding) if amount > outstanding => {
+                warn!("payment exceeds outstanding amount {} of split {}", outstanding, split_id);
+                return Err(BadRequest);
+            },
+            Some(_) => {},
+        }
+    }
+    Ok(())
+}
+
+/// The payment a refund is for
+struct Refunded {
+    split_id: Option<i64>,
+    provider: String,
+    reference: Option<String>,
+}
+
+/// Check a refund against what is left to refund of the payment of the locked bill
+async fn check_refund<R, T>(txn: &T, id: i64, payment_id: i64, amount: i64) -> Result<Refunded, CustomError>
+where
+    R: GenericRow,
+    WrappedRow<R>: GenericRow,
+    T: GenericTransaction<R>,
+{
+    let rows = txn.query(r#"
+        SELECT p.split_id, p.provider, p.reference, p.amount - COALESCE((
+            SELECT SUM(r.amount) FROM payment r WHERE r.refund_of = p.id
+        ), 0)::bigint AS refundable
+        FROM payment p
+        WHERE p.id = $1 AND p.bill_id = $2 AND p.kind = $3
+    "#, &[&payment_id as &(dyn ToSql + Sync), &id, &PAYMENT_KIND_PAYMENT]).await.map_err(|e| DbError(e.into()))?;
+    let Some(original) = rows.first() else {
+        warn!("payment {} of bill {} does not exist", payment_id, id);
+        return Err(ResourceNotFound);
+    };
+    let refundable: i64 = original.get("refundable");
+    if amount <= 0 || amount > refundable {
+        warn!("invalid refund for payment {}, amount={}, refundable={}", payment_id, amount, refundable);
+        return Err(BadRequest);
+    }
+    Ok(Refunded {
+        split_id: original.get("split_id"),
+        provider: original.get("provider"),
+        reference: original.get("reference"),
+    })
+}
+
+/// Record a payment or refund of the locked bill and commit, returns it with the balance after it
+async fn record<R, T>(txn: T, id: i64, time_zone: Tz, payment: Payment) -> Result<(Payment, Balance), CustomError>
+where
+    R: GenericRow,
+    WrappedRow<R>: GenericRow,
+    T: GenericTransaction<R>,
+{
+    let row = txn.query_one(r#"
+        INSERT INTO payment(bill_id, split_id, refund_of, kind, provider, amount, tip, reference, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        RETURNING id
+    "#, &[&id as &(dyn ToSql + Sync), &payment.split_id, &payment.refund_of, &payment.kind, &payment.provider, &payment.amount, &payment.tip, &payment.reference, &payment.created_at]).await.map_err(|e| {
+        error!("failed to insert payment, {}", e);
+        DbError(e.into())
+    })?;
+    let payment = Payment {
+        id: row.get("id"),
+        ..payment
+    };
+
+    // a split is settled as long as its payments cover its amount
+    if let Some(split_id) = payment.split_id {
+        txn.execute(r#"
+            UPDATE bill_split s
+            SET state = CASE WHEN p.paid >= s.amount THEN $2 ELSE $3 END,
+                settled_at = CASE WHEN p.paid >= s.amount THEN COALESCE(s.settled_at, $4) END
+            FROM (
+                SELECT COALESCE(SUM(CASE WHEN kind = 'payment' THEN amount ELSE -amount END), 0)::bigint AS paid
+                FROM payment
+                WHERE split_id = $1
+            ) p
+            WHERE s.id = $1
+        "#, &[&split_id as &(dyn ToSql + Sync), &SPLIT_STATE_SETTLED, &SPLIT_STATE_OPEN, &payment.created_at]).await.map_err(|e| {
+            error!("failed to update split state, {}", e);
+            DbError(e.into())
+        })?;
+    }
+
+    let balance = payment::balance(&txn, id, time_zone).await.map_err(|e| DbError(e.into()))?;
+    txn.commit().await.map_err(|e| DbError(e.into()))?;
+    Ok((payment, balance))
+}
+
+#[utoipa::path(
+    tag = "payment",
+    context_path = "/v1",
+    responses(
+        (status = 200, description = "Payments and refunds of the bill with its balance", body = GetPaymentsResponse),
+        (status = 404, description = "Bill not found"),
+    ),
+)]
+#[get("/bill/{id}/payments")]
+/// Get payments and balance of a bill
+async fn get_bill_payments(id: web::Path<i64>, data: web::Data<&AppState>) -> Result<impl Responder, CustomError> {
+    if let Some(mut conn) = data.get_db_read_pool().acquire(data.get_db_timeout_seconds()).await {
+        let client = conn.client.as_mut().unwrap();
+        // read balance and payments from the same snapshot
+        let txn = client.transaction().await.map_err(|e| DbError(e.into()))?;
+        let id = id.into_inner();
+        let rows = txn.query(r#"
+            SELECT id, split_id, refund_of, kind, provider, amount, tip, reference, created_at
+            FROM payment
+            WHERE bill_id = $1
+            ORDER BY id
+        "#, &[&id]).await.map_err(|e| {
+            error!("get_bill_payments failed, {}", e);
+            DbError(e.into())
+        })?;
+        let payments = rows.iter().map(|row| Payment {
+            id: row.get("id"),
+            split_id: row.get("split_id"),
+            refund_of: row.get("refund_of"),
+            kind: row.get("kind"),
+            provider: row.get("provider"),
+            amount: row.get("amount"),
+            tip: row.get("tip"),
+            reference: row.get("reference"),
+            created_at: row.get("created_at"),
+        }).collect::<Vec<_>>();
+        let balance = payment::balance(&txn, id, data.get_time_zone()).await.map_err(|e| DbError(e.into()))?;
+        txn.commit().await.map_err(|e| DbError(e.into()))?;
+        return Ok(web::Json(GetPaymentsResponse { balance, payments }));
+    }
+    Err(CustomError::ServerIsBusy)
+}
@@ -0,0 +1,195 @@
+#[cfg(test)]
+use crate::server::database::pool::{DbClient, GenericRow, GenericTransaction};
+use std::time::Duration;
+use actix_web::{delete, get, post, web, HttpResponse, Responder};
+use actix_web::middleware::from_fn;
+use actix_web::rt::time;
+use log::{error, info, warn};
+use tokio_postgres::error::SqlState;
+use tokio_postgres::types::ToSql;
+use crate::server::controller::auth::privileged;
+use crate::server::controller::error::CustomError;
+use crate::server::controller::error::CustomError::{BadRequest, DbError, ResourceNotFound, Timeout};
+use crate::server::model::pricing::{Comp, DiscountRule, GetDiscountRulesResponse, PostCompRequest, PricedBill};
+use crate::server::pricing;
+use crate::server::state::AppState;
+
+#[utoipa::path(
+    tag = "pricing",
+    context_path = "/v1",
+    request_body = DiscountRule,
+    responses(
+        (status = 200, description = "Created rule", body = DiscountRule),
+        (status = 400, description = "Invalid rule"),
+    ),
+)]
+#[post("/discounts", wrap = "from_fn(privileged)")]
+/// Create a discount rule
+async fn post_discount(body: web::Json<DiscountRule>, data: web::Data<&AppState>) -> Result<impl Responder, CustomError> {
+    let rule = body.into_inner();
+    if let Err(e) = rule.validate() {
+        warn!("invalid discount rule, {}", e);
+        return Err(BadRequest);
+    }
+    if let Some(conn) = data.get_db_write_pool().acquire(data.get_db_timeout_seconds()).await {
+        let client = conn.client.as_ref().unwrap();
+        return match client.query(r#"
+            INSERT INTO discount_rule(name, kind, scope, value, category, menu_item_id, starts_at, ends_at, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            RETURNING id
+        "#, &[&rule.name as &(dyn ToSql + Sync), &rule.kind.to_string(), &rule.scope.to_string(), &rule.value, &rule.category,
+            &rule.menu_item_id, &rule.starts_at, &rule.ends_at, &crate::server::util::time::helper::get_utc_now()]).await {
+            Ok(rows) => {
+                let id = rows.first().map(|row| row.get("id")).unwrap_or_default();
+                info!("created discount rule {}", id);
+                Ok(web::Json(DiscountRule { id, ..rule.clone() }))
+            },
+            Err(e) => {
+                if let Some(&SqlState::FOREIGN_KEY_VIOLATION) = e.code() {
+                    warn!("the requested menu item does not exist");
+                    return Err(BadRequest);
+                }
+                error!("post_discount failed, {}", e);
+                Err(DbError(e.into()))
+            }
+        };
+    }
+    Err(CustomError::ServerIsBusy)
+}
+
+#[utoipa::path(
+    tag = "pricing",
+    context_path = "/v1",
+    responses(
+        (status = 200, description = "Active rules", body = GetDiscountRulesResponse),
+    ),
+)]
+#[get("/discounts")]
+/// List active discount rules
+async fn get_discounts(data: web::Data<&AppState>) -> Result<impl Responder, CustomError> {
+    if let Some(conn) = data.get_db_read_pool().acquire(data.get_db_timeout_seconds()).await {
+        let client = conn.client.as_ref().unwrap();
+        return match client.query(pricing::ACTIVE_RULES_QUERY, &[]).await {
+            Ok(rows) => {
+                let rules = rows.iter().filter_map(pricing::rule_from_row).collect::<Vec<_>>();
+                Ok(web::Json(GetDiscountRulesResponse { rules }))
+            },
+            Err(e) => {
+                error!("get_discounts failed, {}", e);
+                Err(DbError(e.into()))
+            }
+        };
+    }
+    Err(CustomError::ServerIsBusy)
+}
+
+#[utoipa::path(
+    tag = "pricing",
+    context_path = "/v1",
+    responses(
+        (status = 200, description = "Rule is deactivated"),
+        (status = 404, description = "Rule not found or inactive"),
+    ),
+)]
+#[delete("/discount/{id}", wrap = "from_fn(privileged)")]
+/// Deactivate a discount rule, open bills are repriced without it while split or checked out bills keep their discounts
+async fn delete_discount(id: web::Path<i32>, data: web::Data<&AppState>) -> Result<impl Responder, CustomError> {
+    if let Some(conn) = data.get_db_write_pool().acquire(data.get_db_timeout_seconds()).await {
+        let client = conn.client.as_ref().unwrap();
+        return match client.execute("UPDATE discount_rule SET active = false WHERE id = $1 AND active", &[&id.into_inner()]).await {
+            Ok(0) => Err(ResourceNotFound),
+            Ok(_) => Ok(HttpResponse::Ok()),
+            Err(e) => {
+                warn!("delete_discount failed, {}", e);
+                Err(DbError(e.into()))
+            }
+        };
+    }
+    Err(CustomError::ServerIsBusy)
+}
+
+#[utoipa::path(
+    tag = "pricing",
+    context_path = "/v1",
+    request_body = PostCompRequest,
+    responses(
+        (status = 200, description = "Bill totals with the comp applied", body = PricedBill),
+        (status = 400, description = "Invalid comp"),
+        (status = 404, description = "Bill or item not found"),
+    ),
+)]
+#[post("/bill/{id}/comps")]
+/// Comp a bill item or an amount of a bill, with a reason code
+async fn post_bill_comps(
+    id: web::Path<i64>,
+    body: web::Json<PostCompRequest>,
+    data: web::Data<&AppState>,
+) -> Result<impl Responder, CustomError> {
+    let PostCompRequest { bill_item_id, reason_code, amount } = body.into_inner();
+    let comp = Comp {
+        bill_item_id,
+        reason_code: reason_code.to_string(),
+        amount,
+    };
+    if let Err(e) = comp.validate() {
+        warn!("invalid comp, {}", e);
+        return Err(BadRequest);
+    }
+    if let Some(mut conn) = data.get_db_write_pool().acquire(data.get_db_timeout_seconds()).await {
+        let client = conn.client.as_mut().unwrap();
+        let txn = client.transaction().await.map_err(|e| {
+            error!("db error, {}", e);
+            DbError(e.into())
+        })?;
+        let id = id.into_inner();
+        let params: &[&(dyn ToSql + Sync)] = &[&id];
+        let sleep = time::sleep(Duration::new(data.get_db_timeout_seconds(), 0));
+        tokio::pin!(sleep);
+        tokio::select! {
+            result = txn.query(r#"SELECT id FROM bill WHERE id = $1 AND checkout_at IS NULL FOR UPDATE"#, params) => {
+                match result {
+                    Ok(rows) if rows.is_empty() => {
+                        warn!("bill {} does not exist or is checked out already", id);
+                        return Err(ResourceNotFound);
+                    },
+                    Ok(_) => {},
+                    Err(e) => {
+                        error!("failed to query, {}", e);
+                        return Err(DbError(e.into()));
+                    }
+                }
+            },
+            _ = &mut sleep => {
+                warn!("timeout when trying to select bill for update");
+                return Err(Timeout);
+            }
+        }
+        // comps after splitting would make splits disagree with the total
+        let split = txn.query("SELECT id FROM bill_split WHERE bill_id = $1 LIMIT 1", params).await.map_err(|e| DbError(e.into()))?;
+        if !split.is_empty() {
+            warn!("bill {} is split already, cannot comp", id);
+            return Err(BadRequest);
+        }
+        if let Some(bill_item_id) = comp.bill_item_id {
+            let item = txn.query(r#"
+                SELECT id FROM bill_item
+                WHERE id = $1 AND bill_id = $2 AND state IS DISTINCT FROM 'deleted'
+            "#, &[&bill_item_id as &(dyn ToSql + Sync), &id]).await.map_err(|e| DbError(e.into()))?;
+            if item.is_empty() {
+                return Err(ResourceNotFound);
+            }
+        }
+        txn.execute(r#"
+            INSERT INTO bill_comp(bill_id, bill_item_id, reason_code, amount, created_at)
+            VALUES ($1, $2, $3, $4, $5)
+        "#, &[&id as &(dyn ToSql + Sync), &comp.bill_item_id, &comp.reason_code, &comp.amount, &crate::server::util::time::helper::get_utc_now()]).await.map_err(|e| {
+            error!("failed to insert comp, {}", e);
+            DbError(e.into())
+        })?;
+        let priced = pricing::load(&txn, id, data.get_time_zone()).await.map_err(|e| DbError(e.into()))?;
+        txn.commit().await.map_err(|e| DbError(e.into()))?;
+        info!("comped bill {} for {}", id, comp.reason_code);
+        return Ok(web::Json(priced));
+    }
+    Err(CustomError::ServerIsBusy)
+}
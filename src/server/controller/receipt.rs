@@ -0,0 +1,203 @@
+#[cfg(test)]
+use crate::server::database::pool::{DbClient, GenericRow, GenericTransaction};
+use std::collections::HashMap;
+use actix_web::{get, web, HttpResponse, Responder};
+use actix_web::http::header::ContentType;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use log::{error, warn};
+use tokio_postgres::types::ToSql;
+use crate::server::controller::error::CustomError;
+use crate::server::controller::error::CustomError::{DbError, ResourceNotFound, Unknown};
+use crate::server::model::receipt::{GetReceiptParams, GetTicketParams, KitchenTicket, Receipt, ReceiptFormat, ReceiptLine, TicketItem};
+use crate::server::payment;
+use crate::server::payment::PAYMENT_KIND_PAYMENT;
+use crate::server::pricing;
+use crate::server::receipt::Template;
+use crate::server::state::AppState;
+
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+fn format_time(at: DateTime<Utc>, time_zone: Tz) -> String {
+    at.with_timezone(&time_zone).format(TIME_FORMAT).to_string()
+}
+
+#[utoipa::path(
+    tag = "receipt",
+    context_path = "/v1",
+    params(
+        GetReceiptParams,
+    ),
+    responses(
+        (status = 200, description = "Rendered receipt", content((String = "text/plain"), (String = "text/html"))),
+        (status = 404, description = "Bill not found"),
+    ),
+)]
+#[get("/bill/{id}/receipt")]
+/// Render a printable receipt of a bill, as fixed width text or html
+async fn get_bill_receipt(
+    id: web::Path<i64>,
+    params: web::Query<GetReceiptParams>,
+    data: web::Data<&AppState>,
+) -> Result<impl Responder, CustomError> {
+    if let Some(mut conn) = data.get_db_read_pool().acquire(data.get_db_timeout_seconds()).await {
+        let client = conn.client.as_mut().unwrap();
+        // read the bill, its prices and payments from the same snapshot
+        let txn = client.transaction().await.map_err(|e| DbError(e.into()))?;
+        let id = id.into_inner();
+        let time_zone = data.get_time_zone();
+        let params_id: &[&(dyn ToSql + Sync)] = &[&id];
+        let bill = txn.query("SELECT table_id, created_at, checkout_at FROM bill WHERE id = $1", params_id).await.map_err(|e| {
+            error!("failed to query bill, {}", e);
+            DbError(e.into())
+        })?;
+        let Some(bill) = bill.first() else {
+            warn!("bill {} does not exist", id);
+            return Err(ResourceNotFound);
+        };
+        let names = txn.query(r#"
+            SELECT bi.id, mi.name || COALESCE(' (' || (
+                SELECT string_agg(m.name, ', ' ORDER BY m.modifier_option_id)
+                FROM bill_item_modifier m
+                WHERE m.bill_item_id = bi.id
+            ) || ')', '') AS name
+            FROM bill_item bi
+            JOIN menu_item mi
+            ON bi.menu_item_id = mi.id
+            WHERE bi.bill_id = $1 AND bi.state IS DISTINCT FROM 'deleted'
+        "#, params_id).await
+            .map_err(|e| DbError(e.into()))?
+            .iter()
+            .map(|row| (row.get::<&str, i64>("id"), row.get::<&str, String>("name")))
+            .collect::<HashMap<_, _>>();
+        let priced = pricing::load(&txn, id, time_zone).await.map_err(|e| DbError(e.into()))?;
+        let payments = txn.query(r#"
+            SELECT kind, provider, amount
+            FROM payment
+            WHERE bill_id = $1
+            ORDER BY id
+        "#, params_id).await.map_err(|e| DbError(e.into()))?;
+
+        let payments = payments.iter().map(|row| {
+            let (kind, provider, amount): (String, String, i64) = (row.get("kind"), row.get("provider"), row.get("amount"));
+            if kind == PAYMENT_KIND_PAYMENT {
+                ReceiptLine { label: provider, amount }
+            } else {
+                ReceiptLine { label: format!("{} {}", kind, provider), amount: -amount }
+            }
+        }).collect::<Vec<_>>();
+        let balance = payment::balance_of(&txn, id, priced.total).await.map_err(|e| DbError(e.into()))?;
+        let config = data.get_renderer().config();
+        let tax_rate = config.tax_rate;
+        let name_of = |bill_item_id: i64| names.get(&bill_item_id).cloned().unwrap_or_default();
+        let receipt = Receipt {
+            restaurant: config.restaurant_name.clone(),
+            bill_id: id,
+            table_id: bill.get("table_id"),
+            opened_at: format_time(bill.get("created_at"), time_zone),
+            checkout_at: bill.get::<&str, Option<DateTime<Utc>>>("checkout_at").map(|at| format_time(at, time_zone)),
+            printed_at: format_time(crate::server::util::time::helper::get_utc_now(), time_zone),
+            items: priced.items.iter()
+                .map(|item| ReceiptLine { label: name_of(item.bill_item_id), amount: item.gross })
+                .collect(),
+            subtotal: priced.subtotal,
+            adjustments: priced.adjustments.iter().map(|adjustment| ReceiptLine {
+                label: match adjustment.bill_item_id {
+                    Some(bill_item_id) => format!("{} ({})", adjustment.label, name_of(bill_item_id)),
+                    None => adjustment.label.clone(),
+                },
+                amount: adjustment.amount,
+            }).collect(),
+            total: priced.total,
+            tax_rate,
+            tax: priced.total * tax_rate as i64 / (100 + tax_rate as i64),
+            payments,
+            paid: balance.paid,
+            tips: balance.tips,
+            outstanding: balance.outstanding,
+        };
+
+        let format = params.into_inner().format.unwrap_or_default();
+        let (template, content_type) = match format {
+            ReceiptFormat::Text => (Template::ReceiptText, ContentType::plaintext()),
+            ReceiptFormat::Html => (Template::ReceiptHtml, ContentType::html()),
+        };
+        return match data.get_renderer().render(template, receipt) {
+            Ok(body) => Ok(HttpResponse::Ok().insert_header(content_type).body(body)),
+            Err(e) => {
+                error!("failed to render receipt of bill {}, {:#}", id, e);
+                Err(Unknown)
+            }
+        };
+    }
+    Err(CustomError::ServerIsBusy)
+}
+
+#[utoipa::path(
+    tag = "receipt",
+    context_path = "/v1",
+    params(
+        GetTicketParams,
+    ),
+    responses(
+        (status = 200, description = "Rendered kitchen ticket", body = String, content_type = "text/plain"),
+        (status = 404, description = "Bill not found"),
+    ),
+)]
+#[get("/bill/{id}/ticket")]
+/// Render a kitchen ticket for items ordered after a point of time, or items not delivered yet
+async fn get_bill_ticket(
+    id: web::Path<i64>,
+    params: web::Query<GetTicketParams>,
+    data: web::Data<&AppState>,
+) -> Result<impl Responder, CustomError> {
+    if let Some(conn) = data.get_db_read_pool().acquire(data.get_db_timeout_seconds()).await {
+        let client = conn.client.as_ref().unwrap();
+        let id = id.into_inner();
+        let time_zone = data.get_time_zone();
+        let since = params.into_inner().since;
+        let params: &[&(dyn ToSql + Sync)] = &[&id, &since];
+        let rows = client.query(r#"
+            SELECT b.table_id, bi.id, mi.name, bi.note, bi.created_at,
+                ARRAY(SELECT m.name FROM bill_item_modifier m WHERE m.bill_item_id = bi.id ORDER BY m.modifier_option_id) AS modifiers
+            FROM bill b
+            LEFT JOIN bill_item bi
+            ON bi.bill_id = b.id
+            AND bi.state IS DISTINCT FROM 'deleted'
+            AND CASE WHEN $2::timestamptz IS NULL THEN bi.state = 'created' ELSE bi.created_at > $2 END
+            LEFT JOIN menu_item mi
+            ON bi.menu_item_id = mi.id
+            WHERE b.id = $1
+            ORDER BY bi.id
+        "#, params).await.map_err(|e| {
+            error!("get_bill_ticket failed, {}", e);
+            DbError(e.into())
+        })?;
+        let Some(first) = rows.first() else {
+            warn!("bill {} does not exist", id);
+            return Err(ResourceNotFound);
+        };
+        let ticket = KitchenTicket {
+            bill_id: id,
+            table_id: first.get("table_id"),
+            printed_at: format_time(crate::server::util::time::helper::get_utc_now(), time_zone),
+            items: rows.iter()
+                .filter_map(|row| Some(TicketItem {
+                    id: row.try_get::<&str, i64>("id").ok()?,
+                    name: row.get("name"),
+                    modifiers: row.get("modifiers"),
+                    note: row.get("note"),
+                    ordered_at: format_time(row.get("created_at"), time_zone),
+                }))
+                .collect(),
+        };
+        return match data.get_renderer().render(Template::KitchenTicket, ticket) {
+            Ok(body) => Ok(HttpResponse::Ok().insert_header(ContentType::plaintext()).body(body)),
+            Err(e) => {
+                error!("failed to render kitchen ticket of bill {}, {:#}", id, e);
+                Err(Unknown)
+            }
+        };
+    }
+    Err(CustomError::ServerIsBusy)
+}
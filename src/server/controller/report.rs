@@ -0,0 +1,281 @@
+#[cfg(test)]
+use crate::server::database::pool::{DbClient, GenericRow};
+use actix_web::{get, web, HttpResponse};
+use log::error;
+use serde::Serialize;
+use tokio_postgres::types::ToSql;
+use crate::server::controller::error::CustomError;
+use crate::server::controller::error::CustomError::{DbError, Unknown};
+use crate::server::model::report::{CategorySalesRow, DeliveryRow, Granularity, ItemSalesRow, ReportParams, ReportResponse, RevenueRow, TurnoverRow, VoidRow};
+use crate::server::report;
+use crate::server::state::AppState;
+
+fn respond<T: Serialize>(rows: Vec<T>, params: &ReportParams) -> Result<HttpResponse, CustomError> {
+    report::respond(rows, params.format.unwrap_or_default()).map_err(|e| {
+        error!("failed to write report, {:#}", e);
+        Unknown
+    })
+}
+
+#[utoipa::path(
+    tag = "report",
+    context_path = "/v1",
+    params(
+        ReportParams,
+    ),
+    responses(
+        (status = 200, description = "Report rows, or CSV with a header line", content((ReportResponse<RevenueRow> = "application/json"), (String = "text/csv"))),
+    ),
+)]
+#[get("/reports/revenue")]
+/// Money collected per day or hour of the configured time zone
+async fn get_revenue_report(params: web::Query<ReportParams>, data: web::Data<&AppState>) -> Result<HttpResponse, CustomError> {
+    if let Some(conn) = data.get_db_read_pool().acquire(data.get_db_timeout_seconds()).await {
+        let client = conn.client.as_ref().unwrap();
+        let (from, to) = params.range(crate::server::util::time::helper::get_utc_now());
+        let granularity = match params.granularity.unwrap_or_default() {
+            Granularity::Day => "day",
+            Granularity::Hour => "hour",
+        };
+        // bucketed by the offset of each payment's own time, so that days across a DST change stay whole
+        let query_params: &[&(dyn ToSql + Sync)] = &[&from, &to, &granularity, &data.get_time_zone().name()];
+        let rows = client.query(r#"
+            SELECT date_trunc($3::text, created_at AT TIME ZONE $4::text) AS period,
+                COALESCE(SUM(amount) FILTER (WHERE kind = 'payment'), 0)::bigint AS payments,
+                COALESCE(SUM(amount) FILTER (WHERE kind = 'refund'), 0)::bigint AS refunds,
+                COALESCE(SUM(tip) FILTER (WHERE kind = 'payment'), 0)::bigint AS tips,
+                COUNT(DISTINCT bill_id) AS bills
+            FROM payment
+            WHERE created_at >= $1 AND created_at < $2
+            GROUP BY period
+            ORDER BY period
+        "#, query_params).await.map_err(|e| {
+            error!("get_revenue_report failed, {}", e);
+            DbError(e.into())
+        })?;
+        let rows = rows.iter().map(|row| {
+            let (payments, refunds): (i64, i64) = (row.get("payments"), row.get("refunds"));
+            RevenueRow {
+                period: row.get("period"),
+                payments,
+                refunds,
+                net: payments - refunds,
+                tips: row.get("tips"),
+                bills: row.get("bills"),
+            }
+        }).collect::<Vec<_>>();
+        return respond(rows, &params);
+    }
+    Err(CustomError::ServerIsBusy)
+}
+
+#[utoipa::path(
+    tag = "report",
+    context_path = "/v1",
+    params(
+        ReportParams,
+    ),
+    responses(
+        (status = 200, description = "Report rows, or CSV with a header line", content((ReportResponse<ItemSalesRow> = "application/json"), (String = "text/csv"))),
+    ),
+)]
+#[get("/reports/items")]
+/// Best selling menu items by units ordered
+async fn get_item_sales_report(params: web::Query<ReportParams>, data: web::Data<&AppState>) -> Result<HttpResponse, CustomError> {
+    if let Some(conn) = data.get_db_read_pool().acquire(data.get_db_timeout_seconds()).await {
+        let client = conn.client.as_ref().unwrap();
+        let (from, to) = params.range(crate::server::util::time::helper::get_utc_now());
+        let limit = params.limit.unwrap_or(report::DEFAULT_LIMIT) as i64;
+        let query_params: &[&(dyn ToSql + Sync)] = &[&from, &to, &limit];
+        let rows = client.query(r#"
+            SELECT mi.id, mi.name, mi.category, COUNT(*) AS quantity,
+                SUM(bi.unit_price + COALESCE((SELECT SUM(m.price_delta) FROM bill_item_modifier m WHERE m.bill_item_id = bi.id), 0))::bigint AS gross
+            FROM bill_item bi
+            JOIN menu_item mi
+            ON bi.menu_item_id = mi.id
+            WHERE bi.created_at >= $1 AND bi.created_at < $2 AND bi.state IS DISTINCT FROM 'deleted'
+            GROUP BY mi.id, mi.name, mi.category
+            ORDER BY quantity DESC, gross DESC, mi.id
+            LIMIT $3
+        "#, query_params).await.map_err(|e| {
+            error!("get_item_sales_report failed, {}", e);
+            DbError(e.into())
+        })?;
+        let rows = rows.iter().map(|row| ItemSalesRow {
+            menu_item_id: row.get("id"),
+            name: row.get("name"),
+            category: row.get("category"),
+            quantity: row.get("quantity"),
+            gross: row.get("gross"),
+        }).collect::<Vec<_>>();
+        return respond(rows, &params);
+    }
+    Err(CustomError::ServerIsBusy)
+}
+
+#[utoipa::path(
+    tag = "report",
+    context_path = "/v1",
+    params(
+        ReportParams,
+    ),
+    responses(
+        (status = 200, description = "Report rows, or CSV with a header line", content((ReportResponse<CategorySalesRow> = "application/json"), (String = "text/csv"))),
+    ),
+)]
+#[get("/reports/categories")]
+/// Best selling menu categories by units ordered
+async fn get_category_sales_report(params: web::Query<ReportParams>, data: web::Data<&AppState>) -> Result<HttpResponse, CustomError> {
+    if let Some(conn) = data.get_db_read_pool().acquire(data.get_db_timeout_seconds()).await {
+        let client = conn.client.as_ref().unwrap();
+        let (from, to) = params.range(crate::server::util::time::helper::get_utc_now());
+        let limit = params.limit.unwrap_or(report::DEFAULT_LIMIT) as i64;
+        let query_params: &[&(dyn ToSql + Sync)] = &[&from, &to, &limit];
+        let rows = client.query(r#"
+            SELECT mi.category, COUNT(*) AS quantity,
+                SUM(bi.unit_price + COALESCE((SELECT SUM(m.price_delta) FROM bill_item_modifier m WHERE m.bill_item_id = bi.id), 0))::bigint AS gross
+            FROM bill_item bi
+            JOIN menu_item mi
+            ON bi.menu_item_id = mi.id
+            WHERE bi.created_at >= $1 AND bi.created_at < $2 AND bi.state IS DISTINCT FROM 'deleted'
+            GROUP BY mi.category
+            ORDER BY quantity DESC, gross DESC, mi.category
+            LIMIT $3
+        "#, query_params).await.map_err(|e| {
+            error!("get_category_sales_report failed, {}", e);
+            DbError(e.into())
+        })?;
+        let rows = rows.iter().map(|row| CategorySalesRow {
+            category: row.get("category"),
+            quantity: row.get("quantity"),
+            gross: row.get("gross"),
+        }).collect::<Vec<_>>();
+        return respond(rows, &params);
+    }
+    Err(CustomError::ServerIsBusy)
+}
+
+#[utoipa::path(
+    tag = "report",
+    context_path = "/v1",
+    params(
+        ReportParams,
+    ),
+    responses(
+        (status = 200, description = "Report rows, or CSV with a header line", content((ReportResponse<TurnoverRow> = "application/json"), (String = "text/csv"))),
+    ),
+)]
+#[get("/reports/turnover")]
+/// Average time from claiming to checking out a table, of bills checked out in the range
+async fn get_turnover_report(params: web::Query<ReportParams>, data: web::Data<&AppState>) -> Result<HttpResponse, CustomError> {
+    if let Some(conn) = data.get_db_read_pool().acquire(data.get_db_timeout_seconds()).await {
+        let client = conn.client.as_ref().unwrap();
+        let (from, to) = params.range(crate::server::util::time::helper::get_utc_now());
+        let query_params: &[&(dyn ToSql + Sync)] = &[&from, &to];
+        let rows = client.query(r#"
+            SELECT table_id, COUNT(*) AS bills,
+                COALESCE(AVG(EXTRACT(EPOCH FROM checkout_at - created_at) / 60), 0)::float8 AS average_minutes
+            FROM bill
+            WHERE checkout_at >= $1 AND checkout_at < $2
+            GROUP BY ROLLUP(table_id)
+            ORDER BY table_id NULLS LAST
+        "#, query_params).await.map_err(|e| {
+            error!("get_turnover_report failed, {}", e);
+            DbError(e.into())
+        })?;
+        let rows = rows.iter().map(|row| TurnoverRow {
+            table_id: row.get("table_id"),
+            bills: row.get("bills"),
+            average_minutes: row.get("average_minutes"),
+        }).collect::<Vec<_>>();
+        return respond(rows, &params);
+    }
+    Err(CustomError::ServerIsBusy)
+}
+
+#[utoipa::path(
+    tag = "report",
+    context_path = "/v1",
+    params(
+        ReportParams,
+    ),
+    responses(
+        (status = 200, description = "Report rows, or CSV with a header line", content((ReportResponse<DeliveryRow> = "application/json"), (String = "text/csv"))),
+    ),
+)]
+#[get("/reports/delivery")]
+/// Average estimated and actual delivery time of items ordered in the range
+async fn get_delivery_report(params: web::Query<ReportParams>, data: web::Data<&AppState>) -> Result<HttpResponse, CustomError> {
+    if let Some(conn) = data.get_db_read_pool().acquire(data.get_db_timeout_seconds()).await {
+        let client = conn.client.as_ref().unwrap();
+        let (from, to) = params.range(crate::server::util::time::helper::get_utc_now());
+        let query_params: &[&(dyn ToSql + Sync)] = &[&from, &to];
+        // delivered_at is stamped by the sweeper, so actual times are late by up to its interval
+        let rows = client.query(r#"
+            SELECT bi.menu_item_id, mi.name, COUNT(*) AS delivered,
+                COALESCE(AVG(bi.time_to_deliver), 0)::float8 AS average_estimated_minutes,
+                COALESCE(AVG(EXTRACT(EPOCH FROM bi.delivered_at - bi.created_at) / 60), 0)::float8 AS average_actual_minutes
+            FROM bill_item bi
+            JOIN menu_item mi
+            ON bi.menu_item_id = mi.id
+            WHERE bi.created_at >= $1 AND bi.created_at < $2 AND bi.delivered_at IS NOT NULL
+            GROUP BY ROLLUP((bi.menu_item_id, mi.name))
+            ORDER BY bi.menu_item_id NULLS LAST
+        "#, query_params).await.map_err(|e| {
+            error!("get_delivery_report failed, {}", e);
+            DbError(e.into())
+        })?;
+        let rows = rows.iter().map(|row| DeliveryRow {
+            menu_item_id: row.get("menu_item_id"),
+            name: row.get("name"),
+            delivered: row.get("delivered"),
+            average_estimated_minutes: row.get("average_estimated_minutes"),
+            average_actual_minutes: row.get("average_actual_minutes"),
+        }).collect::<Vec<_>>();
+        return respond(rows, &params);
+    }
+    Err(CustomError::ServerIsBusy)
+}
+
+#[utoipa::path(
+    tag = "report",
+    context_path = "/v1",
+    params(
+        ReportParams,
+    ),
+    responses(
+        (status = 200, description = "Report rows, or CSV with a header line", content((ReportResponse<VoidRow> = "application/json"), (String = "text/csv"))),
+    ),
+)]
+#[get("/reports/voids")]
+/// Share of items ordered in the range that are removed later
+async fn get_void_report(params: web::Query<ReportParams>, data: web::Data<&AppState>) -> Result<HttpResponse, CustomError> {
+    if let Some(conn) = data.get_db_read_pool().acquire(data.get_db_timeout_seconds()).await {
+        let client = conn.client.as_ref().unwrap();
+        let (from, to) = params.range(crate::server::util::time::helper::get_utc_now());
+        let query_params: &[&(dyn ToSql + Sync)] = &[&from, &to];
+        let rows = client.query(r#"
+            SELECT bi.menu_item_id, mi.name, COUNT(*) AS ordered,
+                COUNT(*) FILTER (WHERE bi.state = 'deleted') AS voided,
+                COALESCE((COUNT(*) FILTER (WHERE bi.state = 'deleted'))::float8 / NULLIF(COUNT(*), 0), 0) AS void_rate
+            FROM bill_item bi
+            JOIN menu_item mi
+            ON bi.menu_item_id = mi.id
+            WHERE bi.created_at >= $1 AND bi.created_at < $2
+            GROUP BY ROLLUP((bi.menu_item_id, mi.name))
+            ORDER BY bi.menu_item_id NULLS LAST
+        "#, query_params).await.map_err(|e| {
+            error!("get_void_report failed, {}", e);
+            DbError(e.into())
+        })?;
+        let rows = rows.iter().map(|row| VoidRow {
+            menu_item_id: row.get("menu_item_id"),
+            name: row.get("name"),
+            ordered: row.get("ordered"),
+            voided: row.get("voided"),
+            void_rate: row.get("void_rate"),
+        }).collect::<Vec<_>>();
+        return respond(rows, &params);
+    }
+    Err(CustomError::ServerIsBusy)
+}
@@ -0,0 +1,285 @@
+#[cfg(test)]
+use crate::server::database::pool::{DbClient, GenericRow, GenericTransaction};
+use std::time::Duration;
+use actix_web::{delete, get, post, web, HttpResponse, Responder};
+use actix_web::rt::time;
+use log::{error, info, warn};
+use tokio_postgres::types::ToSql;
+use crate::server::controller::error::CustomError;
+use crate::server::controller::error::CustomError::{BadRequest, DbError, ResourceNotFound, Timeout};
+use crate::server::model::reservation::{GetReservationsParams, GetReservationsResponse, GetWaitlistResponse, PostReservationRequest, PostWaitlistRequest, Reservation, WaitlistEntry, RESERVATION_STATE_BOOKED, RESERVATION_STATE_CANCELLED, WAITLIST_STATE_REMOVED, WAITLIST_STATE_WAITING};
+use crate::server::reservation;
+use crate::server::state::AppState;
+
+#[utoipa::path(
+    tag = "reservation",
+    context_path = "/v1",
+    request_body = PostReservationRequest,
+    responses(
+        (status = 200, description = "Booked reservation", body = Reservation),
+        (status = 400, description = "Invalid party size or slot, or no table seats the party in the slot"),
+    ),
+)]
+#[post("/reservations")]
+/// Book a table for a party, the table is picked when not given
+async fn post_reservation(body: web::Json<PostReservationRequest>, data: web::Data<&AppState>) -> Result<impl Responder, CustomError> {
+    let request = body.into_inner();
+    let now = crate::server::util::time::helper::get_utc_now();
+    let slot = request.validate(now).map_err(|e| {
+        warn!("invalid reservation, {}", e);
+        BadRequest
+    })?;
+    if let Some(mut conn) = data.get_db_write_pool().acquire(data.get_db_timeout_seconds()).await {
+        let client = conn.client.as_mut().unwrap();
+        let txn = client.transaction().await.map_err(|e| {
+            error!("db error, {}", e);
+            DbError(e.into())
+        })?;
+        // lock every candidate table so that concurrent bookings cannot take the same slot
+        let params: &[&(dyn ToSql + Sync)] = &[&request.party_size, &request.table_id];
+        let sleep = time::sleep(Duration::new(data.get_db_timeout_seconds(), 0));
+        tokio::pin!(sleep);
+        tokio::select! {
+            result = txn.query(r#"
+                SELECT id FROM "table"
+                WHERE capacity >= $1 AND ($2::smallint IS NULL OR id = $2)
+                ORDER BY id
+                FOR UPDATE
+            "#, params) => {
+                if let Err(e) = result {
+                    error!("failed to query, {}", e);
+                    return Err(DbError(e.into()));
+                }
+            },
+            _ = &mut sleep => {
+                warn!("timeout when trying to select tables for update");
+                return Err(Timeout);
+            }
+        }
+        let tables = reservation::load_tables(&txn, request.party_size, now, now).await.map_err(|e| {
+            error!("failed to load tables, {}", e);
+            DbError(e.into())
+        })?
+            .into_iter()
+            .filter(|table| request.table_id.is_none_or(|id| id == table.id))
+            .collect::<Vec<_>>();
+        let table_id = reservation::pick_table(&tables, request.party_size, &slot).map_err(|e| {
+            warn!("cannot book for {} at {}, {}", request.party_size, slot.starts_at, e);
+            BadRequest
+        })?;
+        let params: &[&(dyn ToSql + Sync)] = &[&table_id, &request.party_size, &slot.starts_at, &slot.ends_at, &request.name, &request.phone, &RESERVATION_STATE_BOOKED, &now];
+        let row = txn.query_one(r#"
+            INSERT INTO reservation(table_id, party_size, starts_at, ends_at, name, phone, state, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING id
+        "#, params).await.map_err(|e| {
+            error!("failed to insert reservation, {}", e);
+            DbError(e.into())
+        })?;
+        let id: i64 = row.get("id");
+        txn.commit().await.map_err(|e| DbError(e.into()))?;
+        info!("booked table {} for reservation {}", table_id, id);
+        return Ok(web::Json(Reservation {
+            id,
+            table_id,
+            party_size: request.party_size,
+            starts_at: slot.starts_at,
+            ends_at: slot.ends_at,
+            name: request.name.clone(),
+            phone: request.phone.clone(),
+            state: RESERVATION_STATE_BOOKED.to_string(),
+            bill_id: None,
+        }));
+    }
+    Err(CustomError::ServerIsBusy)
+}
+
+#[utoipa::path(
+    tag = "reservation",
+    context_path = "/v1",
+    params(
+        GetReservationsParams,
+    ),
+    responses(
+        (status = 200, description = "Booked and seated reservations in the range", body = GetReservationsResponse),
+    ),
+)]
+#[get("/reservations")]
+/// List reservations overlapping a time range
+async fn get_reservations(params: web::Query<GetReservationsParams>, data: web::Data<&AppState>) -> Result<impl Responder, CustomError> {
+    if let Some(conn) = data.get_db_read_pool().acquire(data.get_db_timeout_seconds()).await {
+        let client = conn.client.as_ref().unwrap();
+        let GetReservationsParams { from, to } = params.into_inner();
+        let from = from.unwrap_or_else(crate::server::util::time::helper::get_utc_now);
+        let params: &[&(dyn ToSql + Sync)] = &[&from, &to];
+        return match client.query(r#"
+            SELECT id, table_id, party_size, starts_at, ends_at, name, phone, state, bill_id
+            FROM reservation
+            WHERE ends_at > $1 AND ($2::timestamptz IS NULL OR starts_at < $2)
+            ORDER BY starts_at, id
+        "#, params).await {
+            Ok(rows) => {
+                let reservations = rows.iter().map(|row| Reservation {
+                    id: row.get("id"),
+                    table_id: row.get("table_id"),
+                    party_size: row.get("party_size"),
+                    starts_at: row.get("starts_at"),
+                    ends_at: row.get("ends_at"),
+                    name: row.get("name"),
+                    phone: row.get("phone"),
+                    state: row.get("state"),
+                    bill_id: row.get("bill_id"),
+                }).collect::<Vec<_>>();
+                Ok(web::Json(GetReservationsResponse { reservations }))
+            },
+            Err(e) => {
+                error!("get_reservations failed, {}", e);
+                Err(DbError(e.into()))
+            }
+        };
+    }
+    Err(CustomError::ServerIsBusy)
+}
+
+#[utoipa::path(
+    tag = "reservation",
+    context_path = "/v1",
+    responses(
+        (status = 200, description = "Reservation is cancelled"),
+        (status = 404, description = "Reservation not found or not booked"),
+    ),
+)]
+#[delete("/reservation/{id}")]
+/// Cancel a reservation that is not seated yet
+async fn delete_reservation(id: web::Path<i64>, data: web::Data<&AppState>) -> Result<impl Responder, CustomError> {
+    if let Some(conn) = data.get_db_write_pool().acquire(data.get_db_timeout_seconds()).await {
+        let client = conn.client.as_ref().unwrap();
+        let params: &[&(dyn ToSql + Sync)] = &[&id.into_inner(), &RESERVATION_STATE_CANCELLED, &RESERVATION_STATE_BOOKED];
+        return match client.execute("UPDATE reservation SET state = $2 WHERE id = $1 AND state = $3", params).await {
+            Ok(0) => Err(ResourceNotFound),
+            Ok(_) => Ok(HttpResponse::Ok()),
+            Err(e) => {
+                warn!("delete_reservation failed, {}", e);
+                Err(DbError(e.into()))
+            }
+        };
+    }
+    Err(CustomError::ServerIsBusy)
+}
+
+#[utoipa::path(
+    tag = "reservation",
+    context_path = "/v1",
+    request_body = PostWaitlistRequest,
+    responses(
+        (status = 200, description = "Party is added", body = WaitlistEntry),
+        (status = 400, description = "Invalid party"),
+    ),
+)]
+#[post("/waitlist")]
+/// Add a walk-in party to the waitlist
+async fn post_waitlist(body: web::Json<PostWaitlistRequest>, data: web::Data<&AppState>) -> Result<impl Responder, CustomError> {
+    let request = body.into_inner();
+    if let Err(e) = request.validate() {
+        warn!("invalid waitlist entry, {}", e);
+        return Err(BadRequest);
+    }
+    if let Some(conn) = data.get_db_write_pool().acquire(data.get_db_timeout_seconds()).await {
+        let client = conn.client.as_ref().unwrap();
+        let created_at = crate::server::util::time::helper::get_utc_now();
+        let params: &[&(dyn ToSql + Sync)] = &[&request.party_size, &request.name, &request.phone, &WAITLIST_STATE_WAITING, &created_at];
+        return match client.query(r#"
+            INSERT INTO waitlist(party_size, name, phone, state, created_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id
+        "#, params).await {
+            Ok(rows) => {
+                let id = rows.first().map(|row| row.get("id")).unwrap_or_default();
+                info!("party {} joined the waitlist", id);
+                Ok(web::Json(WaitlistEntry {
+                    id,
+                    party_size: request.party_size,
+                    name: request.name.clone(),
+                    phone: request.phone.clone(),
+                    created_at,
+                    estimated_wait_minutes: None,
+                }))
+            },
+            Err(e) => {
+                error!("post_waitlist failed, {}", e);
+                Err(DbError(e.into()))
+            }
+        };
+    }
+    Err(CustomError::ServerIsBusy)
+}
+
+#[utoipa::path(
+    tag = "reservation",
+    context_path = "/v1",
+    responses(
+        (status = 200, description = "Waiting parties with estimated waits", body = GetWaitlistResponse),
+    ),
+)]
+#[get("/waitlist")]
+/// List waiting parties with estimated wait times
+async fn get_waitlist(data: web::Data<&AppState>) -> Result<impl Responder, CustomError> {
+    if let Some(mut conn) = data.get_db_read_pool().acquire(data.get_db_timeout_seconds()).await {
+        let client = conn.client.as_mut().unwrap();
+        // read the waitlist, tables and bookings from the same snapshot
+        let txn = client.transaction().await.map_err(|e| DbError(e.into()))?;
+        let now = crate::server::util::time::helper::get_utc_now();
+        let rows = txn.query(r#"
+            SELECT id, party_size, name, phone, created_at
+            FROM waitlist
+            WHERE state = $1
+            ORDER BY created_at, id
+        "#, &[&WAITLIST_STATE_WAITING]).await.map_err(|e| {
+            error!("get_waitlist failed, {}", e);
+            DbError(e.into())
+        })?;
+        let tables = reservation::load_tables(&txn, 1, now, now).await.map_err(|e| {
+            error!("failed to load tables, {}", e);
+            DbError(e.into())
+        })?;
+        let party_sizes = rows.iter().map(|row| row.get("party_size")).collect::<Vec<i16>>();
+        let waits = reservation::estimate_waits(&tables, &party_sizes, now);
+        let parties = rows.iter().zip(waits).map(|(row, estimated_wait_minutes)| WaitlistEntry {
+            id: row.get("id"),
+            party_size: row.get("party_size"),
+            name: row.get("name"),
+            phone: row.get("phone"),
+            created_at: row.get("created_at"),
+            estimated_wait_minutes,
+        }).collect::<Vec<_>>();
+        return Ok(web::Json(GetWaitlistResponse { parties }));
+    }
+    Err(CustomError::ServerIsBusy)
+}
+
+#[utoipa::path(
+    tag = "reservation",
+    context_path = "/v1",
+    responses(
+        (status = 200, description = "Party is removed"),
+        (status = 404, description = "Party not found or not waiting"),
+    ),
+)]
+#[delete("/waitlist/{id}")]
+/// Remove a party from the waitlist, when seated or gone
+async fn delete_waitlist(id: web::Path<i64>, data: web::Data<&AppState>) -> Result<impl Responder, CustomError> {
+    if let Some(conn) = data.get_db_write_pool().acquire(data.get_db_timeout_seconds()).await {
+        let client = conn.client.as_ref().unwrap();
+        let removed_at = crate::server::util::time::helper::get_utc_now();
+        let params: &[&(dyn ToSql + Sync)] = &[&id.into_inner(), &WAITLIST_STATE_REMOVED, &removed_at, &WAITLIST_STATE_WAITING];
+        return match client.execute("UPDATE waitlist SET state = $2, removed_at = $3 WHERE id = $1 AND state = $4", params).await {
+            Ok(0) => Err(ResourceNotFound),
+            Ok(_) => Ok(HttpResponse::Ok()),
+            Err(e) => {
+                warn!("delete_waitlist failed, {}", e);
+                Err(DbError(e.into()))
+            }
+        };
+    }
+    Err(CustomError::ServerIsBusy)
+}
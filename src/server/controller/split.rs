@@ -0,0 +1,241 @@
+#[cfg(test)]
+use crate::server::database::pool::{DbClient, GenericRow, GenericTransaction};
+use std::collections::HashMap;
+use std::time::Duration;
+use actix_web::{delete, get, post, web, HttpResponse, Responder};
+use actix_web::rt::time;
+use log::{error, info, warn};
+use tokio_postgres::types::ToSql;
+use crate::server::controller::error::CustomError;
+use crate::server::controller::error::CustomError::{BadRequest, DbError, ResourceNotFound, Timeout};
+use crate::server::model::split::{equal_shares, partition_by_items, GetBillSplitsResponse, PostBillSplitsRequest, Split, SPLIT_STATE_OPEN, SPLIT_STATE_SETTLED};
+use crate::server::pricing;
+use crate::server::state::AppState;
+
+#[utoipa::path(
+    tag = "split",
+    context_path = "/v1",
+    request_body = PostBillSplitsRequest,
+    responses(
+        (status = 200, description = "Created splits", body = GetBillSplitsResponse),
+        (status = 400, description = "Invalid split, or the bill is already split"),
+        (status = 404, description = "Bill not found"),
+    ),
+)]
+#[post("/bill/{id}/splits")]
+/// Split a bill by items or by equal shares
+async fn post_bill_splits(
+    id: web::Path<i64>,
+    body: web::Json<PostBillSplitsRequest>,
+    data: web::Data<&AppState>,
+) -> Result<impl Responder, CustomError> {
+    if let Some(mut conn) = data.get_db_write_pool().acquire(data.get_db_timeout_seconds()).await {
+        let client = conn.client.as_mut().unwrap();
+        let txn = client.transaction().await.map_err(|e| {
+            error!("db error, {}", e);
+            DbError(e.into())
+        })?;
+        let id = id.into_inner();
+        let params: &[&(dyn ToSql + Sync)] = &[&id];
+        let sleep = time::sleep(Duration::new(data.get_db_timeout_seconds(), 0));
+        tokio::pin!(sleep);
+        // lock the open bill, so that concurrent split requests are serialized
+        tokio::select! {
+            result = txn.query(r#"SELECT id FROM bill WHERE id = $1 AND checkout_at IS NULL FOR UPDATE"#, params) => {
+                match result {
+                    Ok(rows) if rows.is_empty() => {
+                        warn!("bill {} does not exist or is checked out already", id);
+                        return Err(ResourceNotFound);
+                    },
+                    Ok(_) => {},
+                    Err(e) => {
+                        error!("failed to query, {}", e);
+                        return Err(DbError(e.into()));
+                    }
+                }
+            },
+            _ = &mut sleep => {
+                warn!("timeout when trying to select bill for update");
+                return Err(Timeout);
+            }
+        }
+
+        let existing = txn.query("SELECT id FROM bill_split WHERE bill_id = $1 LIMIT 1", params).await.map_err(|e| DbError(e.into()))?;
+        if !existing.is_empty() {
+            warn!("bill {} is split already", id);
+            return Err(BadRequest);
+        }
+
+        // splits are priced once, so the discounts are frozen with them
+        let priced = pricing::freeze(&txn, id, data.get_time_zone()).await.map_err(|e| DbError(e.into()))?;
+        let bill_items = priced.items.iter().map(|item| (item.bill_item_id, item.net)).collect::<Vec<_>>();
+
+        let body = body.into_inner();
+        let amounts = match &body {
+            PostBillSplitsRequest::Items { items } => partition_by_items(&bill_items, items),
+            PostBillSplitsRequest::Equal { parts } => equal_shares(priced.total, *parts),
+        }.map_err(|e| {
+            warn!("invalid split request for bill {}, {}", id, e);
+            BadRequest
+        })?;
+
+        let created_at = crate::server::util::time::helper::get_utc_now();
+        let mut splits = Vec::with_capacity(amounts.len());
+        for (i, amount) in amounts.into_iter().enumerate() {
+            let row = txn.query_one(r#"
+                INSERT INTO bill_split(bill_id, amount, state, created_at)
+                VALUES ($1, $2, $3, $4)
+                RETURNING id
+            "#, &[&id as &(dyn ToSql + Sync), &amount, &SPLIT_STATE_OPEN, &created_at]).await.map_err(|e| {
+                error!("failed to insert split, {}", e);
+                DbError(e.into())
+            })?;
+            let split_id: i64 = row.get("id");
+            let items = match &body {
+                PostBillSplitsRequest::Items { items } => items[i].clone(),
+                PostBillSplitsRequest::Equal { .. } => vec![],
+            };
+            for item_id in &items {
+                txn.execute(r#"
+                    INSERT INTO bill_split_item(split_id, bill_item_id)
+                    VALUES ($1, $2)
+                "#, &[&split_id as &(dyn ToSql + Sync), item_id]).await.map_err(|e| {
+                    error!("failed to assign item to split, {}", e);
+                    DbError(e.into())
+                })?;
+            }
+            splits.push(Split {
+                id: split_id,
+                amount,
+                state: SPLIT_STATE_OPEN.to_string(),
+                items,
+            });
+        }
+
+        txn.commit().await.map_err(|e| DbError(e.into()))?;
+        info!("split bill {} into {} parts", id, splits.len());
+        return Ok(web::Json(GetBillSplitsResponse { splits }));
+    }
+    Err(CustomError::ServerIsBusy)
+}
+
+#[utoipa::path(
+    tag = "split",
+    context_path = "/v1",
+    responses(
+        (status = 200, description = "Splits of the bill", body = GetBillSplitsResponse),
+    ),
+)]
+#[get("/bill/{id}/splits")]
+/// Get splits of a bill
+async fn get_bill_splits(id: web::Path<i64>, data: web::Data<&AppState>) -> Result<impl Responder, CustomError> {
+    if let Some(conn) = data.get_db_read_pool().acquire(data.get_db_timeout_seconds()).await {
+        let client = conn.client.as_ref().unwrap();
+        let id = id.into_inner();
+        return match client.query(r#"
+            SELECT s.id, s.amount, s.state, si.bill_item_id
+            FROM bill_split s
+            LEFT JOIN bill_split_item si
+            ON si.split_id = s.id
+            WHERE s.bill_id = $1
+            ORDER BY s.id
+        "#, &[&id]).await {
+            Ok(rows) => {
+                let mut splits: Vec<Split> = vec![];
+                let mut positions = HashMap::new();
+                for row in rows {
+                    let split_id: i64 = row.get("id");
+                    let idx = *positions.entry(split_id).or_insert_with(|| {
+                        splits.push(Split {
+                            id: split_id,
+                            amount: row.get("amount"),
+                            state: row.get("state"),
+                            items: vec![],
+                        });
+                        splits.len() - 1
+                    });
+                    if let Ok(item_id) = row.try_get::<&str, i64>("bill_item_id") {
+                        splits[idx].items.push(item_id);
+                    }
+                }
+                Ok(web::Json(GetBillSplitsResponse { splits }))
+            },
+            Err(e) => {
+                error!("get_bill_splits failed, {}", e);
+                Err(DbError(e.into()))
+            }
+        };
+    }
+    Err(CustomError::ServerIsBusy)
+}
+
+#[utoipa::path(
+    tag = "split",
+    context_path = "/v1",
+    responses(
+        (status = 200, description = "Splits are removed"),
+        (status = 400, description = "Some split is settled"),
+        (status = 404, description = "Bill not found, checked out or not split"),
+    ),
+)]
+#[delete("/bill/{id}/splits")]
+/// Undo splitting a bill, only allowed before any split is settled
+async fn delete_bill_splits(id: web::Path<i64>, data: web::Data<&AppState>) -> Result<impl Responder, CustomError> {
+    if let Some(mut conn) = data.get_db_write_pool().acquire(data.get_db_timeout_seconds()).await {
+        let client = conn.client.as_mut().unwrap();
+        let txn = client.transaction().await.map_err(|e| {
+            error!("db error, {}", e);
+            DbError(e.into())
+        })?;
+        let id = id.into_inner();
+        let params: &[&(dyn ToSql + Sync)] = &[&id];
+        let sleep = time::sleep(Duration::new(data.get_db_timeout_seconds(), 0));
+        tokio::pin!(sleep);
+        // lock the open bill, so that payments of its splits are not recorded meanwhile
+        tokio::select! {
+            result = txn.query(r#"SELECT id FROM bill WHERE id = $1 AND checkout_at IS NULL FOR UPDATE"#, params) => {
+                match result {
+                    Ok(rows) if rows.is_empty() => {
+                        warn!("bill {} does not exist or is checked out already", id);
+                        return Err(ResourceNotFound);
+                    },
+                    Ok(_) => {},
+                    Err(e) => {
+                        error!("failed to query, {}", e);
+                        return Err(DbError(e.into()));
+                    }
+                }
+            },
+            _ = &mut sleep => {
+                warn!("timeout when trying to select bill for update");
+                return Err(Timeout);
+            }
+        }
+
+        let splits = txn.query("SELECT state FROM bill_split WHERE bill_id = $1", params).await.map_err(|e| DbError(e.into()))?;
+        if splits.is_empty() {
+            warn!("bill {} is not split", id);
+            return Err(ResourceNotFound);
+        }
+        if splits.iter().any(|split| split.get::<&str, String>("state") == SPLIT_STATE_SETTLED) {
+            warn!("bill {} has a settled split", id);
+            return Err(BadRequest);
+        }
+
+        // the bill is open for changes again, so it is priced with active rules until split again
+        let removed: Result<_, tokio_postgres::Error> = async {
+            txn.execute("DELETE FROM bill_split WHERE bill_id = $1", params).await?;
+            txn.execute("DELETE FROM bill_discount WHERE bill_id = $1", params).await?;
+            txn.execute("UPDATE bill SET discounts_frozen_at = NULL WHERE id = $1", params).await?;
+            txn.commit().await
+        }.await;
+        return match removed {
+            Ok(()) => Ok(HttpResponse::Ok()),
+            Err(e) => {
+                warn!("delete_bill_splits failed, {}", e);
+                Err(DbError(e.into()))
+            }
+        };
+    }
+    Err(CustomError::ServerIsBusy)
+}
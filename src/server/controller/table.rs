@@ -1,30 +1,52 @@
 use crate::server::database::pool::GenericTransaction;
 use crate::server::database::pool::GenericRow;
 use crate::server::database::pool::DbClient;
+use std::collections::HashMap;
 use std::time::Duration;
+use chrono::{DateTime, Utc};
 use actix_web::{get, patch, post, put, web, Responder};
 use actix_web::rt::time;
 use log::{error, info, warn};
 use tokio_postgres::types::{ToSql};
 use crate::server::controller::error::CustomError;
 use crate::server::controller::error::CustomError::{BadRequest, DbError, Timeout};
-use crate::server::DB_TIMEOUT_SECONDS;
-use crate::server::model::table::{GetTablesResponse, PatchTablesResponse, PostTablesResponse, Table};
+use crate::server::model::bill::BILL_STATE_CLOSED;
+use crate::server::model::event::Event;
+use crate::server::outbox;
+use crate::server::model::split::SPLIT_STATE_SETTLED;
+use crate::server::payment;
+use crate::server::pricing;
+use crate::server::model::reservation::{RESERVATION_STATE_BOOKED, RESERVATION_STATE_SEATED};
+use crate::server::model::table::{GetTablesResponse, PatchTableParams, PatchTablesResponse, PostTablesResponse, Table};
+use crate::server::reservation::HOLD_MINUTES;
 use crate::server::state::AppState;
 
-#[patch("/v1/table/{id}")]
-/// occupy a table
+#[utoipa::path(
+    tag = "table",
+    context_path = "/v1",
+    params(
+        PatchTableParams,
+    ),
+    responses(
+        (status = 200, description = "Table is claimed with a new bill", body = PatchTablesResponse),
+        (status = 400, description = "Table is taken or held for a reservation, or the reservation is not due"),
+        (status = 504, description = "Table is locked by another request"),
+    ),
+)]
+#[patch("/table/{id}")]
+/// occupy a table, a table held for a reservation can only be claimed by that reservation
 async fn patch_table(
     id: web::Path<i16>,
+    query: web::Query<PatchTableParams>,
     data: web::Data<&AppState>,
 ) -> Result<impl Responder, CustomError> {
-    if let Some(mut conn) = data.get_db_write_pool().acquire(DB_TIMEOUT_SECONDS).await {
+    if let Some(mut conn) = data.get_db_write_pool().acquire(data.get_db_timeout_seconds()).await {
         let client = conn.client.as_mut().unwrap();
         match client.transaction().await {
             Ok(txn) => {
                 let id = id.into_inner();
                 let params: &[&(dyn ToSql + Sync)] = &[&id];
-                let sleep = time::sleep(Duration::new(DB_TIMEOUT_SECONDS, 0));
+                let sleep = time::sleep(Duration::new(data.get_db_timeout_seconds(), 0));
                 tokio::pin!(sleep);
                 tokio::select! {
                     // check table availability
@@ -57,6 +79,33 @@ async fn patch_table(
                     }
                 }
 
+                // check the table is not held for another party
+                let reservation_id = query.into_inner().reservation_id;
+                let now = crate::server::util::time::helper::get_utc_now();
+                let hold_params: &[&(dyn ToSql + Sync)] = &[&id, &RESERVATION_STATE_BOOKED, &now, &(HOLD_MINUTES as i32)];
+                let held = match txn.query(r#"
+                    SELECT id FROM reservation
+                    WHERE table_id = $1 AND state = $2
+                    AND starts_at - make_interval(mins => $4) <= $3 AND ends_at > $3
+                "#, hold_params).await {
+                    Ok(rows) => rows.iter().map(|row| row.get::<&str, i64>("id")).collect::<Vec<_>>(),
+                    Err(e) => {
+                        error!("failed to query reservations, {}", e);
+                        return Err(DbError(e.into()));
+                    }
+                };
+                match reservation_id {
+                    Some(reservation_id) if !held.contains(&reservation_id) => {
+                        warn!("reservation {} is not due at table {}", reservation_id, id);
+                        return Err(BadRequest);
+                    },
+                    None if !held.is_empty() => {
+                        warn!("table {} is held for reservation {:?}", id, held);
+                        return Err(BadRequest);
+                    },
+                    _ => {},
+                }
+
                 // insert bill
                 let result: Result<i64, CustomError> = match txn.query_one(r#"
                     INSERT INTO bill(table_id, created_at)
@@ -72,7 +121,19 @@ async fn patch_table(
                             WHERE ta.id = $1
                         "#, &[&id as &(dyn ToSql + Sync), &bill_id]).await {
                             Ok(_) => {
-                                Ok(bill_id)
+                                match reservation_id {
+                                    Some(reservation_id) => txn.execute(r#"
+                                        UPDATE reservation
+                                        SET state = $2, bill_id = $3
+                                        WHERE id = $1
+                                    "#, &[&reservation_id as &(dyn ToSql + Sync), &RESERVATION_STATE_SEATED, &bill_id]).await
+                                        .map(|_| bill_id)
+                                        .map_err(|e| {
+                                            error!("failed to seat reservation, {}", e);
+                                            DbError(e.into())
+                                        }),
+                                    None => Ok(bill_id),
+                                }
                             },
                             Err(e) => {
                                 error!("failed to bind bill to table, {}", e);
@@ -87,7 +148,12 @@ async fn patch_table(
                 };
                 match result {
                     Ok(bill_id) => {
+                        outbox::enqueue(&txn, &Event::BillOpened { bill_id, table_id: id, reservation_id }).await.map_err(|e| {
+                            error!("failed to write the outbox, {:#}", e);
+                            DbError(e)
+                        })?;
                         txn.commit().await.map_err(|e| DbError(e.into()))?;
+                        data.get_caches().occupancy.invalidate();
                         Ok(web::Json(PatchTablesResponse {
                             bill_id,
                         }))
@@ -105,74 +171,148 @@ async fn patch_table(
     }
 }
 
-#[get("/v1/tables")]
-/// get tables
+#[utoipa::path(
+    tag = "table",
+    context_path = "/v1",
+    responses(
+        (status = 200, description = "Tables with their availability", body = GetTablesResponse),
+    ),
+)]
+#[get("/tables")]
+/// get tables, the layout comes from the tables cache and the bills on them from the occupancy cache while fresh
 async fn get_tables(data: web::Data<&AppState>) -> Result<impl Responder, CustomError> {
-    if let Some(conn) = data.get_db_read_pool().acquire(DB_TIMEOUT_SECONDS).await {
+    let layout = data.get_caches().tables.get_or_load(|| async {
+        let Some(conn) = data.get_db_read_pool().acquire(data.get_db_timeout_seconds()).await else {
+            return Err(CustomError::ServerIsBusy);
+        };
         let client = conn.client.as_ref().unwrap();
-        return match client.query(r##"
-            SELECT t.id as t_id, b.id as b_id, CASE WHEN b.id IS NULL THEN 'Y' ELSE 'N' END AS availabie
-            FROM "table" t
-            LEFT JOIN bill b
-            on t.bill_id = b.id
-            ;
-        "##, &[]).await {
-            Ok(rows) => {
-                let tables = rows.into_iter()
-                    .map(|r| {
-                        Table {
-                            id: r.get::<&str, i16>("t_id") as u8,
-                            bill_id: r.try_get::<&str, i64>("b_id").ok(),
-                        }
-                    })
-                    .collect::<Vec<_>>();
-
-                    Ok(web::Json(GetTablesResponse {
-                        tables: Some(tables),
-                    }))
-            }
+        let result = client.query(r#"SELECT id, capacity FROM "table" ORDER BY id"#, &[]).await;
+        match result {
+            Ok(rows) => Ok(rows.into_iter()
+                .map(|r| Table {
+                    id: r.get::<&str, i16>("id") as u8,
+                    capacity: r.get::<&str, i16>("capacity") as u8,
+                    bill_id: None,
+                })
+                .collect::<Vec<_>>()),
             Err(e) => {
                 error!("get_tables failed, {}", e);
                 Err(DbError(e.into()))
             }
+        }
+    }).await?;
+    let occupancy = data.get_caches().occupancy.get_or_load(|| async {
+        let Some(conn) = data.get_db_read_pool().acquire(data.get_db_timeout_seconds()).await else {
+            return Err(CustomError::ServerIsBusy);
         };
-    }
-    Err(CustomError::ServerIsBusy)
+        let client = conn.client.as_ref().unwrap();
+        let result = client.query(r#"SELECT id, bill_id FROM "table" WHERE bill_id IS NOT NULL"#, &[]).await;
+        match result {
+            Ok(rows) => Ok(rows.into_iter()
+                .map(|r| (r.get::<&str, i16>("id") as u8, r.get::<&str, i64>("bill_id")))
+                .collect::<HashMap<_, _>>()),
+            Err(e) => {
+                error!("get_tables failed, {}", e);
+                Err(DbError(e.into()))
+            }
+        }
+    }).await?;
+    let tables = layout.iter()
+        .map(|table| Table { bill_id: occupancy.get(&table.id).copied(), ..table.clone() })
+        .collect::<Vec<_>>();
+    Ok(web::Json(GetTablesResponse {
+        tables: Some(tables),
+    }))
 }
 
 
-#[post("/v1/table/{id}")]
+#[utoipa::path(
+    tag = "table",
+    context_path = "/v1",
+    responses(
+        (status = 200, description = "Table is checked out", body = PostTablesResponse),
+        (status = 400, description = "Table has no bill, or the bill has unsettled splits or is not paid in full"),
+        (status = 504, description = "Table is locked by another request"),
+    ),
+)]
+#[post("/table/{id}")]
 /// checkout a table
 async fn post_table(
     id: web::Path<i16>,
     data: web::Data<&AppState>,
 ) -> Result<impl Responder, CustomError> {
-    if let Some(mut conn) = data.get_db_write_pool().acquire(DB_TIMEOUT_SECONDS).await {
+    if let Some(mut conn) = data.get_db_write_pool().acquire(data.get_db_timeout_seconds()).await {
         let client = conn.client.as_mut().unwrap();
         match client.transaction().await {
             Ok(txn) => {
                 let id = id.into_inner();
                 let params: &[&(dyn ToSql + Sync)] = &[&id];
                 // check table is eligible for checkout
-                let sleep = time::sleep(Duration::new(DB_TIMEOUT_SECONDS, 0));
+                let sleep = time::sleep(Duration::new(data.get_db_timeout_seconds(), 0));
                 tokio::pin!(sleep);
-                tokio::select! {
+                let closed = tokio::select! {
                     result = txn.query_one(r#"SELECT bill_id FROM "table" WHERE id = $1 FOR UPDATE"#, params) => {
                         match result {
                             Ok(row) => {
                                 match row.try_get::<&str, Option<i64>>("bill_id") {
                                     Ok(Some(bill_id)) => {
+                                        // lock the bill too, so that no payment or refund is recorded between checking its balance and closing it
+                                        match txn.query(r#"SELECT id FROM bill WHERE id = $1 AND checkout_at IS NULL FOR UPDATE"#, &[&bill_id as &(dyn ToSql + Sync)]).await {
+                                            Ok(rows) if rows.is_empty() => {
+                                                warn!("the bill of table :[{}] is checked out already, bill_id={}", id, bill_id);
+                                                return Err(BadRequest);
+                                            },
+                                            Ok(_) => {},
+                                            Err(e) => {
+                                                error!("failed to lock bill, {}", e);
+                                                return Err(DbError(e.into()));
+                                            }
+                                        }
+                                        // a split bill can only be checked out when every split is settled
+                                        match txn.query(r#"
+                                            SELECT id FROM bill_split
+                                            WHERE bill_id = $1 AND state <> $2
+                                            LIMIT 1
+                                        "#, &[&bill_id as &(dyn ToSql + Sync), &SPLIT_STATE_SETTLED]).await {
+                                            Ok(rows) if !rows.is_empty() => {
+                                                warn!("the table :[{}] has unsettled splits, bill_id={}", id, bill_id);
+                                                return Err(BadRequest);
+                                            },
+                                            Ok(_) => {},
+                                            Err(e) => {
+                                                error!("failed to query splits, {}", e);
+                                                return Err(DbError(e.into()));
+                                            }
+                                        }
+                                        // the bill is priced for good from now on
+                                        if let Err(e) = pricing::freeze(&txn, bill_id, data.get_time_zone()).await {
+                                            error!("failed to freeze discounts, {}", e);
+                                            return Err(DbError(e.into()));
+                                        }
+                                        // the paid amount must cover the bill total
+                                        match payment::balance(&txn, bill_id, data.get_time_zone()).await {
+                                            Ok(balance) if !balance.is_settled() => {
+                                                warn!("the table :[{}] is not paid in full, bill_id={}, outstanding={}", id, bill_id, balance.outstanding);
+                                                return Err(BadRequest);
+                                            },
+                                            Ok(_) => {},
+                                            Err(e) => {
+                                                error!("failed to query balance, {}", e);
+                                                return Err(DbError(e.into()));
+                                            }
+                                        }
                                         info!("the table :[{}] is eligible for checkout, bill_id={}. Will continue to checkout.", id, bill_id);
                                         // update the bill
                                         match txn.query_one(r#"
                                             UPDATE bill
-                                            SET checkout_at = CURRENT_TIMESTAMP
+                                            SET checkout_at = CURRENT_TIMESTAMP, state = $2
                                             WHERE id = $1
-                                            RETURNING id
-                                        "#, &[&bill_id]).await {
+                                            RETURNING id, checkout_at
+                                        "#, &[&bill_id as &(dyn ToSql + Sync), &BILL_STATE_CLOSED]).await {
                                             Ok(row) => {
                                                 let bill_id = row.get::<&str, i64>("id");
                                                 info!("checkout table {} with bill id {} successfully", id, bill_id);
+                                                Event::BillClosed { bill_id, table_id: id, closed_at: row.get::<&str, DateTime<Utc>>("checkout_at") }
                                             },
                                             Err(e) => {
                                                 error!("failed to checkout table {}, {}", id, e);
@@ -200,7 +340,7 @@ async fn post_table(
                         warn!("timeout when trying to select table for update");
                         return Err(Timeout);
                     }
-                }
+                };
 
                 // detach bill
                 let result: Result<i16, CustomError> = match txn.query_one(r#"
@@ -222,7 +362,12 @@ async fn post_table(
                 
                 match result {
                     Ok(id) => {
+                        outbox::enqueue(&txn, &closed).await.map_err(|e| {
+                            error!("failed to write the outbox, {:#}", e);
+                            DbError(e)
+                        })?;
                         txn.commit().await.map_err(|e| DbError(e.into()))?;
+                        data.get_caches().occupancy.invalidate();
                         Ok(web::Json(PostTablesResponse {
                             id: id as u8
                         }))
@@ -32,7 +32,7 @@ impl DbClient for Client {
         self.execute(statement, params).await
     }
 
-    async fn transaction(&mut self) -> Result<WrappedTransaction<Transaction>, Error>
+    async fn transaction(&mut self) -> Result<WrappedTransaction<Transaction<'_>>, Error>
     {
         let txn = self.transaction().await;
         match txn {
@@ -79,7 +79,14 @@ impl GenericTransaction<Row> for Transaction<'_> {
     where
         T: ?Sized + ToStatement
     {
-        self.query_one(statement, params).await.map(|row| WrappedRow(row))
+        self.query_one(statement, params).await.map(WrappedRow)
+    }
+
+    async fn query<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Vec<WrappedRow<Row>>, Error>
+    where
+        T: ?Sized + ToStatement
+    {
+        self.query(statement, params).await.map(|rows| rows.into_iter().map(WrappedRow).collect())
     }
 
     async fn execute<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<u64, Error>
@@ -0,0 +1,18 @@
+-- open bills are bound to a table, closed ones are checked out, and stale ones closed by the detector need a review
+ALTER TABLE bill ADD COLUMN IF NOT EXISTS state varchar(16) NOT NULL DEFAULT 'open';
+UPDATE bill SET state = 'closed' WHERE checkout_at IS NOT NULL AND state = 'open';
+
+-- bills flagged by the stale bill detector, an alert is active until it is resolved
+CREATE TABLE IF NOT EXISTS bill_alert (
+    id bigserial PRIMARY KEY,
+    bill_id bigint NOT NULL,
+    table_id smallint NOT NULL,
+    reason varchar(16) NOT NULL, -- open_too_long, idle
+    detected_at timestamptz NOT NULL,
+    resolved_at timestamptz,
+    CONSTRAINT fk_bill_id FOREIGN KEY(bill_id) REFERENCES bill(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS bill_alert_bill_idx on bill_alert(bill_id, reason);
+-- for listing active alerts
+CREATE INDEX IF NOT EXISTS bill_alert_active_idx on bill_alert(detected_at) WHERE resolved_at IS NULL;
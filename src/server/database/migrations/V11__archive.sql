@@ -0,0 +1,13 @@
+-- rows moved out of the hot tables by the retention job, one JSON document per closed bill with everything of it,
+-- per removed bill item, or per job run
+CREATE TABLE IF NOT EXISTS archive (
+    id bigserial PRIMARY KEY,
+    entity varchar(16) NOT NULL, -- bills, deleted_items, job_runs
+    entity_id bigint NOT NULL,
+    document jsonb NOT NULL,
+    archived_at timestamptz NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS archive_entity_idx on archive(entity, entity_id);
+-- for finding bills due for retention
+CREATE INDEX IF NOT EXISTS bill_checkout_idx on bill(checkout_at) WHERE state = 'closed';
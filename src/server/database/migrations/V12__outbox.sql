@@ -0,0 +1,14 @@
+-- integration events, written in the same transaction as the change they tell about and deleted once every sink has them
+CREATE TABLE IF NOT EXISTS outbox (
+    id bigserial PRIMARY KEY,
+    kind varchar(32) NOT NULL,
+    payload jsonb NOT NULL,
+    created_at timestamptz NOT NULL,
+    delivered_to text[] NOT NULL DEFAULT '{}', -- names of the sinks that have the event
+    attempts integer NOT NULL DEFAULT 0,
+    next_attempt_at timestamptz NOT NULL,
+    last_error text
+);
+
+-- for the relay to find events due for delivery
+CREATE INDEX IF NOT EXISTS outbox_next_attempt_idx on outbox(next_attempt_at, id);
@@ -0,0 +1,29 @@
+-- webhooks subscribed to integration events, an empty filter subscribes to every kind
+CREATE TABLE IF NOT EXISTS webhook_subscription (
+    id bigserial PRIMARY KEY,
+    url text NOT NULL,
+    events text[] NOT NULL DEFAULT '{}',
+    secret text NOT NULL, -- key of the HMAC signatures
+    created_at timestamptz NOT NULL
+);
+
+-- deliveries of events to webhooks, queued when the outbox relays an event and kept as the delivery log
+CREATE TABLE IF NOT EXISTS webhook_delivery (
+    id bigserial PRIMARY KEY,
+    subscription_id bigint NOT NULL REFERENCES webhook_subscription(id) ON DELETE CASCADE,
+    event_id bigint NOT NULL, -- id of the event in the outbox, which is gone once relayed
+    kind varchar(32) NOT NULL,
+    payload jsonb NOT NULL,
+    event_created_at timestamptz NOT NULL,
+    state varchar(16) NOT NULL, -- pending, delivered or failed
+    attempts integer NOT NULL DEFAULT 0,
+    next_attempt_at timestamptz, -- null once delivered or failed
+    response_status smallint,
+    last_error text,
+    created_at timestamptz NOT NULL,
+    delivered_at timestamptz,
+    UNIQUE (subscription_id, event_id)
+);
+
+-- for the dispatcher to find deliveries due for an attempt
+CREATE INDEX IF NOT EXISTS webhook_delivery_next_attempt_idx on webhook_delivery(next_attempt_at, id) WHERE state = 'pending';
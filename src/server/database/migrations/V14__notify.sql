@@ -0,0 +1,44 @@
+-- notify listeners of every replica of table and bill item changes, the payload of a notification is the event in JSON
+CREATE OR REPLACE FUNCTION notify_table_changed() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('table_changed', json_build_object(
+        'kind', 'table_changed',
+        'table_id', NEW.id,
+        'bill_id', NEW.bill_id
+    )::text);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION notify_bill_item_changed() RETURNS trigger AS $$
+DECLARE
+    item bill_item;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        item := OLD;
+    ELSE
+        item := NEW;
+    END IF;
+    PERFORM pg_notify('bill_item_changed', json_build_object(
+        'kind', 'bill_item_changed',
+        'bill_id', item.bill_id,
+        'bill_item_id', item.id,
+        'change', CASE TG_OP WHEN 'INSERT' THEN 'inserted' WHEN 'UPDATE' THEN 'updated' ELSE 'deleted' END,
+        'state', item.state
+    )::text);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+-- a table is bound to a bill when it is claimed and freed when the bill closes
+DROP TRIGGER IF EXISTS table_changed ON "table";
+CREATE TRIGGER table_changed AFTER UPDATE OF bill_id ON "table"
+    FOR EACH ROW WHEN (OLD.bill_id IS DISTINCT FROM NEW.bill_id) EXECUTE FUNCTION notify_table_changed();
+
+-- items are ordered, delivered or removed
+DROP TRIGGER IF EXISTS bill_item_changed ON bill_item;
+CREATE TRIGGER bill_item_changed AFTER INSERT OR DELETE ON bill_item
+    FOR EACH ROW EXECUTE FUNCTION notify_bill_item_changed();
+DROP TRIGGER IF EXISTS bill_item_state_changed ON bill_item;
+CREATE TRIGGER bill_item_state_changed AFTER UPDATE OF state ON bill_item
+    FOR EACH ROW WHEN (OLD.state IS DISTINCT FROM NEW.state) EXECUTE FUNCTION notify_bill_item_changed();
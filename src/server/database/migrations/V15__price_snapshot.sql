@@ -0,0 +1,35 @@
+-- menu price of a bill item when ordered, so that later menu changes do not alter the bill
+ALTER TABLE bill_item ADD COLUMN IF NOT EXISTS unit_price bigint;
+UPDATE bill_item bi SET unit_price = mi.price FROM menu_item mi WHERE bi.menu_item_id = mi.id AND bi.unit_price IS NULL;
+ALTER TABLE bill_item ALTER COLUMN unit_price SET NOT NULL;
+
+-- when the discounts of a bill were frozen, on splitting or checking it out, open bills are priced with active rules
+ALTER TABLE bill ADD COLUMN IF NOT EXISTS discounts_frozen_at timestamptz;
+
+-- discount rules applied to a bill when it was frozen, copied so that later rule changes do not alter the bill
+CREATE TABLE IF NOT EXISTS bill_discount (
+    bill_id bigint NOT NULL,
+    rule_id integer NOT NULL,
+    name varchar(32) NOT NULL,
+    kind varchar(16) NOT NULL,
+    scope varchar(16) NOT NULL,
+    value bigint NOT NULL,
+    category varchar(4),
+    menu_item_id integer,
+    starts_at time,
+    ends_at time,
+    PRIMARY KEY(bill_id, rule_id),
+    CONSTRAINT fk_bill_id FOREIGN KEY(bill_id) REFERENCES bill(id) ON DELETE CASCADE
+);
+
+-- bills split or checked out before keep the rules active now
+INSERT INTO bill_discount(bill_id, rule_id, name, kind, scope, value, category, menu_item_id, starts_at, ends_at)
+SELECT b.id, r.id, r.name, r.kind, r.scope, r.value, r.category, r.menu_item_id, r.starts_at, r.ends_at
+FROM bill b
+CROSS JOIN discount_rule r
+WHERE r.active
+AND (b.checkout_at IS NOT NULL OR EXISTS (SELECT 1 FROM bill_split s WHERE s.bill_id = b.id))
+ON CONFLICT DO NOTHING;
+UPDATE bill b SET discounts_frozen_at = CURRENT_TIMESTAMP
+WHERE b.discounts_frozen_at IS NULL
+AND (b.checkout_at IS NOT NULL OR EXISTS (SELECT 1 FROM bill_split s WHERE s.bill_id = b.id));
@@ -0,0 +1,34 @@
+-- price of a menu item, in the smallest currency unit
+ALTER TABLE menu_item ADD COLUMN IF NOT EXISTS price bigint NOT NULL DEFAULT 0;
+
+UPDATE menu_item SET price = CASE id
+    WHEN 1 THEN 250
+    WHEN 2 THEN 120
+    WHEN 3 THEN 80
+    WHEN 4 THEN 320
+    WHEN 5 THEN 300
+    ELSE price
+END;
+
+-- a split is a part of a bill that is settled separately, a bill with splits can only be checked out when all of them are settled
+CREATE TABLE IF NOT EXISTS bill_split (
+    id bigserial PRIMARY KEY,
+    bill_id bigint NOT NULL, -- index
+    amount bigint NOT NULL,
+    state varchar(16) NOT NULL, -- index
+    created_at timestamptz NOT NULL,
+    settled_at timestamptz,
+    CONSTRAINT fk_bill_id FOREIGN KEY(bill_id) REFERENCES bill(id) ON DELETE CASCADE
+);
+
+-- for checking whether all splits of a bill are settled
+CREATE INDEX IF NOT EXISTS bill_split_idx on bill_split(bill_id, state);
+
+-- bill items assigned to a split, only for bills split by item
+CREATE TABLE IF NOT EXISTS bill_split_item (
+    split_id bigint NOT NULL,
+    bill_item_id bigint NOT NULL UNIQUE, -- a bill item belongs to at most one split
+    PRIMARY KEY(split_id, bill_item_id),
+    CONSTRAINT fk_split_id FOREIGN KEY(split_id) REFERENCES bill_split(id) ON DELETE CASCADE,
+    CONSTRAINT fk_bill_item_id FOREIGN KEY(bill_item_id) REFERENCES bill_item(id) ON DELETE CASCADE
+);
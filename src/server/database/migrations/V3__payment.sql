@@ -0,0 +1,19 @@
+-- payments and refunds of a bill, a bill can only be checked out when the net paid amount covers its total
+CREATE TABLE IF NOT EXISTS payment (
+    id bigserial PRIMARY KEY,
+    bill_id bigint NOT NULL, -- index
+    split_id bigint, -- index, only when paying for a split
+    refund_of bigint, -- only for refunds, the payment being refunded
+    kind varchar(16) NOT NULL, -- payment, refund
+    provider varchar(16) NOT NULL,
+    amount bigint NOT NULL, -- excluding tip, always positive
+    tip bigint NOT NULL DEFAULT 0,
+    reference varchar(64), -- transaction reference from the payment provider
+    created_at timestamptz NOT NULL,
+    CONSTRAINT fk_bill_id FOREIGN KEY(bill_id) REFERENCES bill(id) ON DELETE CASCADE,
+    CONSTRAINT fk_split_id FOREIGN KEY(split_id) REFERENCES bill_split(id) ON DELETE SET NULL,
+    CONSTRAINT fk_refund_of FOREIGN KEY(refund_of) REFERENCES payment(id)
+);
+
+-- for summing up paid amount of a bill or a split
+CREATE INDEX IF NOT EXISTS payment_bill_idx on payment(bill_id, split_id);
@@ -0,0 +1,31 @@
+-- discount rules evaluated by the pricing module, the best matching rule wins for each item and for the bill
+CREATE TABLE IF NOT EXISTS discount_rule (
+    id serial PRIMARY KEY,
+    name varchar(32) NOT NULL,
+    kind varchar(16) NOT NULL, -- percentage, fixed
+    scope varchar(16) NOT NULL, -- item, bill
+    value bigint NOT NULL, -- percentage points for percentage rules, amount for fixed rules
+    category varchar(4), -- only items of the category, item scope only
+    menu_item_id integer, -- only the menu item, item scope only
+    starts_at time, -- daily time window in server local time, e.g. happy hour
+    ends_at time,
+    active boolean NOT NULL DEFAULT true, -- index
+    created_at timestamptz NOT NULL,
+    CONSTRAINT fk_menu_item_id FOREIGN KEY(menu_item_id) REFERENCES menu_item(id)
+);
+
+CREATE INDEX IF NOT EXISTS discount_rule_active_idx on discount_rule(active);
+
+-- manual comps given by managers, either for a bill item or for an amount of the whole bill
+CREATE TABLE IF NOT EXISTS bill_comp (
+    id bigserial PRIMARY KEY,
+    bill_id bigint NOT NULL, -- index
+    bill_item_id bigint, -- the comped item, null when comping an amount of the bill
+    reason_code varchar(16) NOT NULL,
+    amount bigint, -- null when comping the whole item
+    created_at timestamptz NOT NULL,
+    CONSTRAINT fk_bill_id FOREIGN KEY(bill_id) REFERENCES bill(id) ON DELETE CASCADE,
+    CONSTRAINT fk_bill_item_id FOREIGN KEY(bill_item_id) REFERENCES bill_item(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS bill_comp_idx on bill_comp(bill_id);
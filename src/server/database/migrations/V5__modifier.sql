@@ -0,0 +1,58 @@
+-- option groups of a menu item, e.g. size or spiciness, min_select and max_select bound how many options can be chosen
+CREATE TABLE IF NOT EXISTS modifier_group (
+    id serial PRIMARY KEY,
+    menu_item_id integer NOT NULL, -- index
+    name varchar(32) NOT NULL,
+    min_select smallint NOT NULL DEFAULT 0,
+    max_select smallint NOT NULL DEFAULT 1,
+    CONSTRAINT fk_menu_item_id FOREIGN KEY(menu_item_id) REFERENCES menu_item(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS modifier_group_idx on modifier_group(menu_item_id);
+
+-- an option of a modifier group, price_delta is added to the menu item price when chosen
+CREATE TABLE IF NOT EXISTS modifier_option (
+    id serial PRIMARY KEY,
+    group_id integer NOT NULL, -- index
+    name varchar(32) NOT NULL,
+    price_delta bigint NOT NULL DEFAULT 0,
+    CONSTRAINT fk_group_id FOREIGN KEY(group_id) REFERENCES modifier_group(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS modifier_option_idx on modifier_option(group_id);
+
+-- options chosen for a bill item, name and price_delta are copied so that later menu changes do not alter the bill
+CREATE TABLE IF NOT EXISTS bill_item_modifier (
+    bill_item_id bigint NOT NULL,
+    modifier_option_id integer NOT NULL,
+    name varchar(32) NOT NULL,
+    price_delta bigint NOT NULL,
+    PRIMARY KEY(bill_item_id, modifier_option_id),
+    CONSTRAINT fk_bill_item_id FOREIGN KEY(bill_item_id) REFERENCES bill_item(id) ON DELETE CASCADE,
+    CONSTRAINT fk_modifier_option_id FOREIGN KEY(modifier_option_id) REFERENCES modifier_option(id)
+);
+
+-- free text special instructions, e.g. "no onions"
+ALTER TABLE bill_item ADD COLUMN IF NOT EXISTS note varchar(140);
+
+INSERT INTO modifier_group(id, menu_item_id, name, min_select, max_select)
+VALUES
+    (1, 3, 'Size', 0, 1),
+    (2, 4, 'Size', 0, 1),
+    (3, 4, 'Spiciness', 0, 1),
+    (4, 4, 'Toppings', 0, 3);
+
+INSERT INTO modifier_option(id, group_id, name, price_delta)
+VALUES
+    (1, 1, 'Regular', 0),
+    (2, 1, 'Large', 40),
+    (3, 2, 'Regular', 0),
+    (4, 2, 'Large', 80),
+    (5, 3, 'Mild', 0),
+    (6, 3, 'Extra spicy', 0),
+    (7, 4, 'Egg', 50),
+    (8, 4, 'Chashu', 120),
+    (9, 4, 'Nori', 30);
+
+SELECT setval(pg_get_serial_sequence('modifier_group', 'id'), (SELECT MAX(id) FROM modifier_group));
+SELECT setval(pg_get_serial_sequence('modifier_option', 'id'), (SELECT MAX(id) FROM modifier_option));
@@ -0,0 +1,40 @@
+-- seats of a table
+ALTER TABLE "table" ADD COLUMN IF NOT EXISTS capacity smallint NOT NULL DEFAULT 4;
+
+UPDATE "table" SET capacity = CASE
+    WHEN id <= 4 THEN 2
+    WHEN id <= 8 THEN 4
+    ELSE 6
+END;
+
+-- a booking of a table for a time slot, state is one of 'booked', 'seated' and 'cancelled'
+CREATE TABLE IF NOT EXISTS reservation (
+    id bigserial PRIMARY KEY,
+    table_id smallint NOT NULL, -- index
+    party_size smallint NOT NULL CHECK (party_size > 0),
+    starts_at timestamptz NOT NULL,
+    ends_at timestamptz NOT NULL CHECK (ends_at > starts_at),
+    name varchar(64) NOT NULL,
+    phone varchar(32),
+    state varchar(16) NOT NULL DEFAULT 'booked',
+    bill_id bigint, -- bill of the party once seated
+    created_at timestamptz NOT NULL,
+    CONSTRAINT fk_table_id FOREIGN KEY(table_id) REFERENCES "table"(id) ON DELETE CASCADE,
+    CONSTRAINT fk_bill_id FOREIGN KEY(bill_id) REFERENCES bill(id) ON DELETE SET NULL
+);
+
+-- for checking conflicts of a table
+CREATE INDEX IF NOT EXISTS reservation_table_idx on reservation(table_id, state, starts_at);
+
+-- walk-in parties waiting for a table, state is one of 'waiting' and 'removed'
+CREATE TABLE IF NOT EXISTS waitlist (
+    id bigserial PRIMARY KEY,
+    party_size smallint NOT NULL CHECK (party_size > 0),
+    name varchar(64) NOT NULL,
+    phone varchar(32),
+    state varchar(16) NOT NULL DEFAULT 'waiting',
+    created_at timestamptz NOT NULL, -- index
+    removed_at timestamptz
+);
+
+CREATE INDEX IF NOT EXISTS waitlist_idx on waitlist(state, created_at);
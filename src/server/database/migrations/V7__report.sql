@@ -0,0 +1,6 @@
+-- when the sweeper marks an item delivered, for comparing actual and estimated delivery time
+ALTER TABLE bill_item ADD COLUMN IF NOT EXISTS delivered_at timestamptz;
+
+-- for reporting over a time range
+CREATE INDEX IF NOT EXISTS bill_item_created_idx on bill_item(created_at);
+CREATE INDEX IF NOT EXISTS payment_created_idx on payment(created_at);
@@ -0,0 +1,7 @@
+-- one row per scheduled job, the instance holding an unexpired lease is the only one running the job
+CREATE TABLE IF NOT EXISTS job_lease (
+    name varchar(64) PRIMARY KEY,
+    holder varchar(255) NOT NULL,
+    acquired_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at timestamptz NOT NULL
+);
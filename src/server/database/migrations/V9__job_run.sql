@@ -0,0 +1,15 @@
+-- one row per finished run of a scheduled job, on any instance
+CREATE TABLE IF NOT EXISTS job_run (
+    id bigserial PRIMARY KEY,
+    name varchar(64) NOT NULL,
+    instance varchar(255) NOT NULL,
+    trigger varchar(16) NOT NULL,
+    outcome varchar(16) NOT NULL,
+    rows_affected bigint,
+    error text,
+    started_at timestamptz NOT NULL,
+    finished_at timestamptz NOT NULL
+);
+
+-- for the latest runs of each job
+CREATE INDEX IF NOT EXISTS job_run_name_started_idx on job_run(name, started_at DESC);
@@ -5,6 +5,7 @@ use anyhow::{anyhow, Error};
 use log::{error, info, warn};
 use std::collections::VecDeque;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 use std::{mem, thread};
 use std::fmt::Display;
@@ -16,6 +17,9 @@ use tokio_postgres::{Client, Row, ToStatement, Transaction};
 use tokio_postgres::row::RowIndex;
 use tokio_postgres::types::{FromSql, ToSql, Type};
 use crate::server::controller::error::CustomError;
+use crate::server::model::config::PoolConfig;
+#[cfg(not(test))]
+use crate::server::tls;
 
 /// A trait that abstracts out the postgres database client interface from implementation
 pub(crate) trait DbClient: Send + Sync + 'static {
@@ -51,6 +55,8 @@ pub(crate) struct CommonPool<M>
 where M : DbClient<Client = M>
 {
     pub connections: Mutex<VecDeque<Connection<M>>>,
+    /// set once the pool is closed, released clients are dropped rather than queued then
+    pub closed: AtomicBool,
 }
 
 /// A connection pool wrapper that is safe to send across threads
@@ -79,6 +85,14 @@ pub(crate) trait GenericTransaction<R: GenericRow> {
     where
         T: ?Sized + ToStatement;
 
+    async fn query<T>(
+        &self,
+        statement: &T,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Vec<WrappedRow<R>>, tokio_postgres::Error>
+    where
+        T: ?Sized + ToStatement;
+
     async fn execute<T>(
         &self,
         statement: &T,
@@ -227,6 +241,14 @@ impl GenericTransaction<MockRow> for MockTransaction {
         Ok(WrappedRow(MockRow::new()))
     }
 
+    #[allow(unused_variables)]
+    async fn query<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Vec<WrappedRow<MockRow>>, tokio_postgres::Error>
+    where
+        T: ?Sized + ToStatement
+    {
+        Ok(vec![])
+    }
+
     #[allow(unused_variables)]
     async fn execute<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<u64, tokio_postgres::Error>
     where
@@ -245,45 +267,124 @@ pub(crate) mod connect_util {
     use anyhow::Context;
     use log::error;
     use tokio_postgres::{Client, NoTls};
+    #[cfg(not(test))]
+    use tokio_postgres::config::SslMode;
+    #[cfg(not(test))]
+    use futures_util::StreamExt;
+    #[cfg(not(test))]
+    use tokio::io::{AsyncRead, AsyncWrite};
+    #[cfg(not(test))]
+    use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+    #[cfg(not(test))]
+    use tokio_postgres::{AsyncMessage, Notification};
+    use tokio_postgres_rustls::MakeRustlsConnect;
     use crate::server::database::pool::{DbClient};
     
+    /// Connect with TLS when a connector is given, the server is required to support it then
     #[cfg(not(test))]
-    pub async fn connect<M: DbClient + From<Client>>(str: &str) -> M {
+    pub async fn connect<M: DbClient + From<Client>>(str: &str, tls: Option<MakeRustlsConnect>) -> M {
         // abort the process if failed to connect db
-        let (client, conn) = tokio_postgres::connect(str, NoTls).await.context("failed to create connection").unwrap();
-        tokio::spawn(async move {
-            if let Err(e) = conn.await {
-                error!("connection returned error and aborted, {}", e);
-                // TODO: publish metrics for monitoring
-            }
-        });
+        let client = match tls {
+            Some(tls) => {
+                let mut config: tokio_postgres::Config = str.parse().context("failed to parse connection string").unwrap();
+                let (client, conn) = config.ssl_mode(SslMode::Require).connect(tls).await.context("failed to create connection").unwrap();
+                tokio::spawn(drive(conn));
+                client
+            },
+            None => {
+                let (client, conn) = tokio_postgres::connect(str, NoTls).await.context("failed to create connection").unwrap();
+                tokio::spawn(drive(conn));
+                client
+            },
+        };
         client.into()
     }
 
+    /// Connect a dedicated connection listening to the channels, with TLS when a connector is given.
+    /// Notifications are received until the connection closes, it closes once the client is dropped.
+    #[cfg(not(test))]
+    pub async fn listen(str: &str, tls: Option<MakeRustlsConnect>, channels: &[&str]) -> Result<(Client, UnboundedReceiver<Notification>), anyhow::Error> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let client = match tls {
+            Some(tls) => {
+                let mut config: tokio_postgres::Config = str.parse().context("failed to parse connection string")?;
+                let (client, conn) = config.ssl_mode(SslMode::Require).connect(tls).await.context("failed to create connection")?;
+                tokio::spawn(forward(conn, sender));
+                client
+            },
+            None => {
+                let (client, conn) = tokio_postgres::connect(str, NoTls).await.context("failed to create connection")?;
+                tokio::spawn(forward(conn, sender));
+                client
+            },
+        };
+        let statements = channels.iter().map(|channel| format!("LISTEN {};", channel)).collect::<String>();
+        client.batch_execute(&statements).await.context("failed to listen")?;
+        Ok((client, receiver))
+    }
+
+    /// Drive a connection until it closes, passing its notifications on, notices are dropped
+    #[cfg(not(test))]
+    async fn forward<S, T>(mut conn: tokio_postgres::Connection<S, T>, sender: UnboundedSender<Notification>)
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut messages = futures_util::stream::poll_fn(move |cx| conn.poll_message(cx));
+        while let Some(message) = messages.next().await {
+            match message {
+                Ok(AsyncMessage::Notification(notification)) => {
+                    let _ = sender.send(notification);
+                },
+                Ok(_) => {},
+                Err(e) => {
+                    error!("listener connection returned error and aborted, {}", e);
+                    break;
+                },
+            }
+        }
+    }
+
+    /// Drive a connection until it closes
+    #[cfg(not(test))]
+    async fn drive(conn: impl std::future::Future<Output = Result<(), tokio_postgres::Error>>) {
+        if let Err(e) = conn.await {
+            error!("connection returned error and aborted, {}", e);
+            // TODO: publish metrics for monitoring
+        }
+    }
+
     #[cfg(test)]
     use crate::server::database::pool::MockClient;
     #[cfg(test)]
-    pub async fn connect<M: DbClient + From<MockClient>>(_: &str) -> M {
+    pub async fn connect<M: DbClient + From<MockClient>>(_: &str, _: Option<MakeRustlsConnect>) -> M {
         let client = MockClient{};
         client.into()
     }
+
+    #[cfg(test)]
+    pub async fn listen(_: &str, _: Option<MakeRustlsConnect>, _: &[&str]) -> Result<(MockClient, tokio::sync::mpsc::UnboundedReceiver<tokio_postgres::Notification>), anyhow::Error> {
+        Err(anyhow::anyhow!("mock clients do not listen"))
+    }
 }
 
 /// A trait for initializing database connection pools
 pub(crate) trait Init {
-    /// Create a connection pool that constitutes database connections 
-    async fn init(&mut self, conn_str: String) -> Result<(), Error>;
+    /// Create a connection pool that constitutes database connections, over TLS when configured
+    async fn init(&mut self, config: &PoolConfig) -> Result<(), Error>;
 }
 
 #[cfg(not(test))]
 impl Init for Pool<Client> {
     #[cfg(not(test))]
-    async fn init(&mut self, conn_str: String) -> Result<(), Error> {
-        let mut connections: VecDeque<Connection<Client>> = VecDeque::with_capacity(Self::DEFAULT_SIZE);
+    async fn init(&mut self, config: &PoolConfig) -> Result<(), Error> {
+        let connector = config.tls.as_ref().map(|tls| tls::postgres_connector(&tls.ca_cert)).transpose()?;
+        let mut connections: VecDeque<Connection<Client>> = VecDeque::with_capacity(config.size);
         let mut set = JoinSet::new();
-        for _ in 0..Self::DEFAULT_SIZE {
-            let str = conn_str.clone();
-            set.spawn(async move { connect_util::connect::<Client>(str.as_str()).await });
+        for _ in 0..config.size {
+            let str = config.conn_str.clone();
+            let connector = connector.clone();
+            set.spawn(async move { connect_util::connect::<Client>(str.as_str(), connector).await });
         }
         while let Some(res) = set.join_next().await {
             match res {
@@ -303,7 +404,7 @@ impl Init for Pool<Client> {
 
 #[cfg(test)]
 impl Init for Pool<MockClient> {
-    async fn init(&mut self, _: String) -> Result<(), Error> {
+    async fn init(&mut self, _: &PoolConfig) -> Result<(), Error> {
         println!("initializing MockClient.");
         self.0.connections.lock().await.push_back(Connection::new(MockClient{}, self.clone()));
         Ok(())
@@ -317,7 +418,8 @@ where M : DbClient<Client = M>
     /// Create a connection pool container with default configuration
     pub async fn new() -> Result<Self, Error> {
         let shared = Arc::new(CommonPool{
-            connections: Mutex::new(VecDeque::with_capacity(Self::DEFAULT_SIZE))
+            connections: Mutex::new(VecDeque::with_capacity(Self::DEFAULT_SIZE)),
+            closed: AtomicBool::new(false),
         });
         let pool = Self(shared);
         Ok(pool)
@@ -342,8 +444,11 @@ where M : DbClient<Client = M>
         }
     }
     
-    /// Put a client back to the pool
+    /// Put a client back to the pool, or drop it to close its connection when the pool is closed
     pub fn release(&mut self, client: M::Client) {
+        if self.0.closed.load(Ordering::Acquire) {
+            return;
+        }
         let pool = self.0.clone();
         let handle = thread::spawn(move || {
             let mut connections = pool.connections.blocking_lock();
@@ -351,6 +456,14 @@ where M : DbClient<Client = M>
         });
         handle.join().unwrap();
     }
+
+    /// Close idle connections and refuse released ones from now on, returning the number of closed ones.
+    /// Connections in use are closed once they are released.
+    pub async fn close(&self) -> usize {
+        self.0.closed.store(true, Ordering::Release);
+        let connections = mem::take(&mut *self.0.connections.lock().await);
+        connections.len()
+    }
 }
 
 #[cfg(test)]
@@ -368,7 +481,7 @@ mod tests {
         let mut pool = Pool::<MockClient>::new().await.unwrap();
         assert!(pool.acquire(DB_TIMEOUT_SECONDS).await.is_none());
 
-        pool.init("conn_str".to_string()).await.unwrap();
+        pool.init(&PoolConfig::default()).await.unwrap();
         {
             let _conn = match pool.acquire(DB_TIMEOUT_SECONDS).await {
                 Some(conn) => conn,
@@ -380,4 +493,15 @@ mod tests {
         assert!(pool.acquire(DB_TIMEOUT_SECONDS).await.is_some());
         assert!(pool.acquire(DB_TIMEOUT_SECONDS).await.is_some());
     }
+
+    #[tokio::test]
+    async fn test_close() {
+        let mut pool = Pool::<MockClient>::new().await.unwrap();
+        pool.init(&PoolConfig::default()).await.unwrap();
+        pool.init(&PoolConfig::default()).await.unwrap();
+        let conn = pool.acquire(DB_TIMEOUT_SECONDS).await.unwrap();
+        assert_eq!(pool.close().await, 1);
+        drop(conn); // released after closing
+        assert!(pool.acquire(DB_TIMEOUT_SECONDS).await.is_none());
+    }
 }
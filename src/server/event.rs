@@ -0,0 +1,91 @@
+//! In-process bus of events, each subscriber receives the events published after it subscribed
+
+use tokio::select;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::sync::CancellationToken;
+use crate::server::model::event::Event;
+
+/// Events kept for a slow subscriber, it misses the oldest ones beyond that
+const CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub(crate) struct EventBus {
+    sender: broadcast::Sender<Event>,
+    closed: CancellationToken,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self {
+            sender: broadcast::channel(CAPACITY).0,
+            closed: CancellationToken::new(),
+        }
+    }
+
+    /// Publish an event to current subscribers, it is dropped when there is none
+    pub fn publish(&self, event: Event) {
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> Subscription {
+        Subscription {
+            receiver: self.sender.subscribe(),
+            closed: self.closed.clone(),
+        }
+    }
+
+    /// End every subscription, so that event streams finish before the server stops
+    pub fn close(&self) {
+        self.closed.cancel();
+    }
+}
+
+pub(crate) struct Subscription {
+    receiver: broadcast::Receiver<Event>,
+    closed: CancellationToken,
+}
+
+impl Subscription {
+    /// Next event, or the number of events missed by a slow subscriber.
+    /// None once the bus is closed and the events published before are received.
+    pub async fn recv(&mut self) -> Option<Result<Event, u64>> {
+        select! {
+            biased;
+            received = self.receiver.recv() => match received {
+                Ok(event) => Some(Ok(event)),
+                Err(RecvError::Lagged(missed)) => Some(Err(missed)),
+                Err(RecvError::Closed) => None,
+            },
+            _ = self.closed.cancelled() => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use super::*;
+
+    fn delivered(bill_item_id: i64) -> Event {
+        Event::ItemDelivered { bill_id: 1, bill_item_id, delivered_at: Utc::now() }
+    }
+
+    #[tokio::test]
+    async fn test_subscribe() {
+        let bus = EventBus::new();
+        // nobody listens yet
+        bus.publish(delivered(1));
+        let mut subscription = bus.subscribe();
+        bus.publish(delivered(2));
+        for id in 3..CAPACITY as i64 + 4 {
+            bus.publish(delivered(id));
+        }
+        assert_eq!(subscription.recv().await, Some(Err(2)));
+        assert!(matches!(subscription.recv().await, Some(Ok(Event::ItemDelivered { bill_item_id: 4, .. }))));
+        bus.close();
+        assert!(subscription.recv().await.is_some(), "events published before closing are received");
+        let mut late = bus.subscribe();
+        assert_eq!(late.recv().await, None);
+    }
+}
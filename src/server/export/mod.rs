@@ -0,0 +1,284 @@
+//! Bulk export of bills, items and payments in pages, and parsing and validation of menu imports
+
+#[cfg(test)]
+use crate::server::database::pool::{DbClient, GenericRow, GenericTransaction};
+use std::collections::{HashMap, HashSet};
+use anyhow::{anyhow, Error};
+use chrono::{DateTime, Utc};
+use tokio_postgres::types::ToSql;
+use crate::server::model::export::{BillRecord, ExportFormat, ExportKind, ExportRecord, ImportIssue, ItemRecord, MenuItemRecord, PaymentRecord};
+use crate::server::pricing;
+use crate::server::state::AppState;
+
+/// Records fetched per query, each page is read from its own snapshot
+pub(crate) const PAGE_SIZE: i64 = 500;
+
+const MAX_NAME_LEN: usize = 32;
+const MAX_CATEGORY_LEN: usize = 4;
+
+impl ExportRecord {
+    fn id(&self) -> i64 {
+        match self {
+            ExportRecord::Bill(bill) => bill.id,
+            ExportRecord::Item(item) => item.id,
+            ExportRecord::Payment(payment) => payment.id,
+        }
+    }
+}
+
+/// Fetch the page of records created in the range with ids after the given one, returns the last id for the next page
+pub(crate) async fn page(
+    state: &AppState,
+    kind: ExportKind,
+    (from, to): (DateTime<Utc>, DateTime<Utc>),
+    after: i64,
+) -> Result<(Vec<ExportRecord>, Option<i64>), Error> {
+    let Some(mut conn) = state.get_db_read_pool().acquire(state.get_db_timeout_seconds()).await else {
+        return Err(anyhow!("no connection available"));
+    };
+    let client = conn.client.as_mut().ok_or(anyhow!("client is None"))?;
+    let txn = client.transaction().await?;
+    let params: &[&(dyn ToSql + Sync)] = &[&after, &from, &to, &PAGE_SIZE];
+    let records = match kind {
+        ExportKind::Bills => {
+            let rows = txn.query(r#"
+                SELECT id, table_id, created_at, checkout_at
+                FROM bill
+                WHERE id > $1 AND created_at >= $2 AND created_at < $3
+                ORDER BY id
+                LIMIT $4
+            "#, params).await?;
+            let ids = rows.iter().map(|row| row.get::<&str, i64>("id")).collect::<Vec<_>>();
+            let mut priced = pricing::load_all(&txn, &ids, state.get_time_zone()).await?;
+            let mut records = Vec::with_capacity(rows.len());
+            for row in rows.iter() {
+                let id = row.get("id");
+                let Some(priced) = priced.remove(&id) else {
+                    continue;
+                };
+                records.push(ExportRecord::Bill(BillRecord {
+                    id,
+                    table_id: row.get("table_id"),
+                    created_at: row.get("created_at"),
+                    checkout_at: row.get("checkout_at"),
+                    subtotal: priced.subtotal,
+                    total: priced.total,
+                }));
+            }
+            records
+        },
+        ExportKind::Items => txn.query(r#"
+            SELECT bi.id, bi.bill_id, bi.menu_item_id, mi.name, bi.state, bi.note, bi.created_at, bi.delivered_at,
+                COALESCE((SELECT string_agg(m.name, ';' ORDER BY m.modifier_option_id) FROM bill_item_modifier m WHERE m.bill_item_id = bi.id), '') AS modifiers,
+                bi.unit_price + COALESCE((SELECT SUM(m.price_delta) FROM bill_item_modifier m WHERE m.bill_item_id = bi.id), 0)::bigint AS price
+            FROM bill_item bi
+            JOIN menu_item mi
+            ON bi.menu_item_id = mi.id
+            WHERE bi.id > $1 AND bi.created_at >= $2 AND bi.created_at < $3
+            ORDER BY bi.id
+            LIMIT $4
+        "#, params).await?
+            .iter()
+            .map(|row| ExportRecord::Item(ItemRecord {
+                id: row.get("id"),
+                bill_id: row.get("bill_id"),
+                menu_item_id: row.get("menu_item_id"),
+                name: row.get("name"),
+                state: row.get("state"),
+                modifiers: row.get("modifiers"),
+                note: row.get("note"),
+                price: row.get("price"),
+                created_at: row.get("created_at"),
+                delivered_at: row.get("delivered_at"),
+            }))
+            .collect(),
+        ExportKind::Payments => txn.query(r#"
+            SELECT id, bill_id, split_id, refund_of, kind, provider, amount, tip, reference, created_at
+            FROM payment
+            WHERE id > $1 AND created_at >= $2 AND created_at < $3
+            ORDER BY id
+            LIMIT $4
+        "#, params).await?
+            .iter()
+            .map(|row| ExportRecord::Payment(PaymentRecord {
+                id: row.get("id"),
+                bill_id: row.get("bill_id"),
+                split_id: row.get("split_id"),
+                refund_of: row.get("refund_of"),
+                kind: row.get("kind"),
+                provider: row.get("provider"),
+                amount: row.get("amount"),
+                tip: row.get("tip"),
+                reference: row.get("reference"),
+                created_at: row.get("created_at"),
+            }))
+            .collect(),
+    };
+    let next = match records.len() as i64 {
+        PAGE_SIZE => records.last().map(ExportRecord::id),
+        _ => None,
+    };
+    Ok((records, next))
+}
+
+/// Encode records, CSV gets a header line only when asked to, so that pages can be concatenated
+pub(crate) fn encode(records: &[ExportRecord], format: ExportFormat, header: bool) -> Result<Vec<u8>, Error> {
+    match format {
+        ExportFormat::Csv => {
+            let mut writer = csv::WriterBuilder::new().has_headers(header).from_writer(vec![]);
+            for record in records {
+                writer.serialize(record)?;
+            }
+            Ok(writer.into_inner()?)
+        },
+        ExportFormat::Ndjson => {
+            let mut buf = vec![];
+            for record in records {
+                serde_json::to_writer(&mut buf, record)?;
+                buf.push(b'\n');
+            }
+            Ok(buf)
+        },
+    }
+}
+
+/// Parse menu items from CSV with a header line, or NDJSON, along with the line each item is on
+pub(crate) fn parse_menu(body: &[u8], format: ExportFormat) -> (Vec<(usize, MenuItemRecord)>, Vec<ImportIssue>) {
+    let mut records = vec![];
+    let mut issues = vec![];
+    match format {
+        ExportFormat::Csv => {
+            let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(body);
+            for result in reader.deserialize::<MenuItemRecord>() {
+                match result {
+                    Ok(record) => records.push((records.len() + issues.len() + 2, record)),
+                    Err(e) => issues.push(ImportIssue {
+                        line: e.position().map(|position| position.line() as usize).unwrap_or_default(),
+                        message: e.to_string(),
+                    }),
+                }
+            }
+        },
+        ExportFormat::Ndjson => {
+            for (i, line) in String::from_utf8_lossy(body).lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str::<MenuItemRecord>(line) {
+                    Ok(record) => records.push((i + 1, record)),
+                    Err(e) => issues.push(ImportIssue { line: i + 1, message: e.to_string() }),
+                }
+            }
+        },
+    }
+    (records, issues)
+}
+
+/// Check menu items fit the menu_item table and ids are not repeated
+pub(crate) fn validate_menu(records: &[(usize, MenuItemRecord)]) -> Vec<ImportIssue> {
+    let mut issues = vec![];
+    let mut ids = HashSet::new();
+    for (line, record) in records {
+        let mut issue = |message: String| issues.push(ImportIssue { line: *line, message });
+        if record.name.is_empty() || record.name.chars().count() > MAX_NAME_LEN {
+            issue(format!("name must be 1 to {} characters", MAX_NAME_LEN));
+        }
+        if record.category.is_empty() || record.category.chars().count() > MAX_CATEGORY_LEN {
+            issue(format!("category must be 1 to {} characters", MAX_CATEGORY_LEN));
+        }
+        if record.price < 0 {
+            issue("price must not be negative".to_string());
+        }
+        match record.id {
+            Some(id) if id <= 0 => issue("id must be positive".to_string()),
+            Some(id) if !ids.insert(id) => issue(format!("id {} is repeated", id)),
+            _ => {},
+        }
+    }
+    issues
+}
+
+/// Ids of the records changing the price of an existing menu item, given `(id, price)` of the existing ones
+pub(crate) fn repriced(records: &[(usize, MenuItemRecord)], existing: &[(i32, i64)]) -> Vec<i32> {
+    let prices = existing.iter().copied().collect::<HashMap<_, _>>();
+    records.iter()
+        .filter_map(|(_, record)| record.id.filter(|id| prices.get(id).is_some_and(|price| *price != record.price)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use super::*;
+
+    fn payment(id: i64) -> ExportRecord {
+        ExportRecord::Payment(PaymentRecord {
+            id,
+            bill_id: 7,
+            split_id: None,
+            refund_of: None,
+            kind: "payment".to_string(),
+            provider: "cash".to_string(),
+            amount: 500,
+            tip: 50,
+            reference: None,
+            created_at: Utc.with_ymd_and_hms(2024, 11, 20, 18, 0, 0).unwrap(),
+        })
+    }
+
+    #[test]
+    fn test_encode() {
+        let csv = String::from_utf8(encode(&[payment(1), payment(2)], ExportFormat::Csv, true).unwrap()).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("id,bill_id,split_id,refund_of,kind,provider,amount,tip,reference,created_at"));
+        assert_eq!(lines.next(), Some("1,7,,,payment,cash,500,50,,2024-11-20T18:00:00Z"));
+        assert_eq!(lines.count(), 1);
+        let next_page = String::from_utf8(encode(&[payment(3)], ExportFormat::Csv, false).unwrap()).unwrap();
+        assert!(next_page.starts_with("3,"));
+
+        let ndjson = String::from_utf8(encode(&[payment(1)], ExportFormat::Ndjson, false).unwrap()).unwrap();
+        let value: serde_json::Value = serde_json::from_str(ndjson.trim_end()).unwrap();
+        assert_eq!(value["amount"], 500);
+        assert!(ndjson.ends_with('\n'));
+    }
+
+    #[test]
+    fn test_parse_menu() {
+        let csv = "id,name,category,price\n1, Fried chicken ,A,250\n,Gyoza,A,180\n2,Fries,A,cheap\n";
+        let (records, issues) = parse_menu(csv.as_bytes(), ExportFormat::Csv);
+        assert_eq!(records, vec![
+            (2, MenuItemRecord { id: Some(1), name: "Fried chicken".to_string(), category: "A".to_string(), price: 250 }),
+            (3, MenuItemRecord { id: None, name: "Gyoza".to_string(), category: "A".to_string(), price: 180 }),
+        ]);
+        assert_eq!(issues.iter().map(|issue| issue.line).collect::<Vec<_>>(), vec![4]);
+
+        let ndjson = "{\"id\": null, \"name\": \"Gyoza\", \"category\": \"A\", \"price\": 180}\n\n{\"name\": 1}\n";
+        let (records, issues) = parse_menu(ndjson.as_bytes(), ExportFormat::Ndjson);
+        assert_eq!(records.len(), 1);
+        assert_eq!(issues.iter().map(|issue| issue.line).collect::<Vec<_>>(), vec![3]);
+    }
+
+    #[test]
+    fn test_validate_menu() {
+        let record = |id: Option<i32>, name: &str, category: &str, price: i64| MenuItemRecord {
+            id,
+            name: name.to_string(),
+            category: category.to_string(),
+            price,
+        };
+        let records = vec![
+            (2, record(Some(1), "Fried chicken", "A", 250)),
+            (3, record(Some(1), "Gyoza", "A", 180)),
+            (4, record(None, "", "LONGER", -1)),
+        ];
+        let lines = validate_menu(&records).iter().map(|issue| issue.line).collect::<Vec<_>>();
+        assert_eq!(lines, vec![3, 4, 4, 4]);
+    }
+
+    #[test]
+    fn test_repriced() {
+        let record = |id: Option<i32>, price: i64| (2, MenuItemRecord { id, name: "Gyoza".to_string(), category: "A".to_string(), price });
+        let records = vec![record(Some(1), 250), record(Some(2), 180), record(Some(3), 90), record(None, 120)];
+        assert_eq!(repriced(&records, &[(1, 250), (2, 160)]), vec![2]);
+    }
+}
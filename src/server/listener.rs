@@ -0,0 +1,90 @@
+//! Dedicated connection listening to notifications of triggers on `table` and `bill_item`, published to the event bus,
+//! so that clients of every replica are pushed the changes made through any of them
+
+use anyhow::{ensure, Error};
+use std::time::Duration;
+use log::{info, warn};
+use tokio_util::sync::CancellationToken;
+use crate::server::database::pool::connect_util;
+use crate::server::event::EventBus;
+use crate::server::model::config::{ListenerConfig, PoolConfig};
+use crate::server::model::event::Event;
+use crate::server::tls;
+
+/// Channels notified by the triggers, named after the kind of their events
+pub(crate) const CHANNELS: [&str; 2] = ["table_changed", "bill_item_changed"];
+
+/// Event of a notification, its payload is the event in JSON
+fn parse(channel: &str, payload: &str) -> Result<Event, Error> {
+    let event = serde_json::from_str::<Event>(payload)?;
+    ensure!(event.kind().to_string() == channel, "{} event notified on {}", event.kind(), channel);
+    Ok(event)
+}
+
+/// Listen with the settings of the pool until cancelled, reconnecting a lost connection after a while.
+/// Changes notified while disconnected are not published.
+pub(crate) async fn run(pool: PoolConfig, config: ListenerConfig, events: EventBus, cancel_token: CancellationToken) {
+    let retry = Duration::from_secs(config.retry_seconds);
+    loop {
+        match listen(&pool, &events, &cancel_token).await {
+            Ok(()) => return,
+            Err(e) => warn!("listener connection is lost, reconnecting in {:?}, {:#}", retry, e),
+        }
+        tokio::select! {
+            _ = cancel_token.cancelled() => return,
+            _ = tokio::time::sleep(retry) => {},
+        }
+    }
+}
+
+/// Publish notified events until cancelled or the connection is lost
+async fn listen(pool: &PoolConfig, events: &EventBus, cancel_token: &CancellationToken) -> Result<(), Error> {
+    let connector = pool.tls.as_ref().map(|tls| tls::postgres_connector(&tls.ca_cert)).transpose()?;
+    let (_client, mut notifications) = connect_util::listen(&pool.conn_str, connector, &CHANNELS).await?;
+    info!("listening to {}", CHANNELS.join(", "));
+    loop {
+        let notification = tokio::select! {
+            _ = cancel_token.cancelled() => return Ok(()),
+            notification = notifications.recv() => notification,
+        };
+        let Some(notification) = notification else {
+            return Err(Error::msg("connection closed"));
+        };
+        match parse(notification.channel(), notification.payload()) {
+            Ok(event) => events.publish(event),
+            Err(e) => warn!("dropped a notification on {}, {:#}", notification.channel(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use crate::server::model::event::{EventKind, RowChange};
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        // payloads as the triggers build them
+        let event = parse("table_changed", r#"{"kind" : "table_changed", "table_id" : 3, "bill_id" : null}"#).unwrap();
+        assert_eq!(event, Event::TableChanged { table_id: 3, bill_id: None });
+        let event = parse("bill_item_changed", r#"{"kind" : "bill_item_changed", "bill_id" : 1, "bill_item_id" : 7, "change" : "updated", "state" : "delivered"}"#).unwrap();
+        assert_eq!(event, Event::BillItemChanged { bill_id: 1, bill_item_id: 7, change: RowChange::Updated, state: Some("delivered".to_string()) });
+        assert_eq!(CHANNELS.map(|channel| EventKind::from_str(channel).unwrap()), [EventKind::TableChanged, EventKind::BillItemChanged]);
+
+        assert!(parse("bill_item_changed", r#"{"kind" : "table_changed", "table_id" : 3, "bill_id" : 1}"#).is_err());
+        assert!(parse("table_changed", "{}").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let cancel_token = CancellationToken::new();
+        let config = ListenerConfig { retry_seconds: 3600, ..ListenerConfig::default() };
+        let run = tokio::spawn(run(PoolConfig::default(), config, EventBus::new(), cancel_token.clone()));
+        // waits to reconnect as mock connections do not listen, until cancelled
+        tokio::task::yield_now().await;
+        assert!(!run.is_finished());
+        cancel_token.cancel();
+        tokio::time::timeout(Duration::from_secs(1), run).await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,175 @@
+//! Menu items with their modifiers, and validation of ordered items against them
+
+use std::collections::HashSet;
+use derive_more::{Display, Error};
+use crate::server::database::pool::{GenericRow, GenericTransaction, WrappedRow};
+use crate::server::model::bill::OrderItem;
+use crate::server::model::menu::{MenuItem, ModifierGroup, ModifierOption};
+
+/// Most units of a menu item in one order line
+pub(crate) const MAX_QUANTITY: i32 = 50;
+
+/// Longest special instruction accepted for an ordered item, in characters
+pub(crate) const MAX_NOTE_LEN: usize = 140;
+
+#[derive(Debug, Display, Error, PartialEq)]
+pub(crate) enum OrderItemError {
+    #[display("quantity must be between 1 and {}", MAX_QUANTITY)]
+    QuantityOutOfRange,
+    #[display("option does not belong to the menu item")]
+    UnknownOption,
+    #[display("option is chosen more than once")]
+    DuplicateOption,
+    #[display("too few options chosen in a modifier group")]
+    TooFewOptions,
+    #[display("too many options chosen in a modifier group")]
+    TooManyOptions,
+    #[display("note is longer than {} characters", MAX_NOTE_LEN)]
+    NoteTooLong,
+}
+
+impl MenuItem {
+    /// Resolve the options chosen for an ordered item, every group must get between min_select and max_select options
+    pub(crate) fn select(&self, item: &OrderItem) -> Result<Vec<&ModifierOption>, OrderItemError> {
+        if !(1..=MAX_QUANTITY).contains(&item.quantity) {
+            return Err(OrderItemError::QuantityOutOfRange);
+        }
+        if item.note.as_ref().is_some_and(|note| note.chars().count() > MAX_NOTE_LEN) {
+            return Err(OrderItemError::NoteTooLong);
+        }
+        let mut seen = HashSet::with_capacity(item.modifiers.len());
+        if !item.modifiers.iter().all(|id| seen.insert(*id)) {
+            return Err(OrderItemError::DuplicateOption);
+        }
+        let mut selected = Vec::with_capacity(item.modifiers.len());
+        for group in &self.modifier_groups {
+            let chosen = group.options.iter().filter(|option| seen.contains(&option.id)).collect::<Vec<_>>();
+            if (chosen.len() as i16) < group.min_select {
+                return Err(OrderItemError::TooFewOptions);
+            }
+            if chosen.len() as i16 > group.max_select {
+                return Err(OrderItemError::TooManyOptions);
+            }
+            selected.extend(chosen);
+        }
+        if selected.len() != seen.len() {
+            return Err(OrderItemError::UnknownOption);
+        }
+        Ok(selected)
+    }
+}
+
+/// Load menu items with their modifier groups and options, all items when ids are absent
+pub(crate) async fn load<R, T>(txn: &T, ids: Option<Vec<i32>>) -> Result<Vec<MenuItem>, tokio_postgres::Error>
+where
+    R: GenericRow,
+    WrappedRow<R>: GenericRow,
+    T: GenericTransaction<R>,
+{
+    let rows = txn.query(r#"
+        SELECT mi.id, mi.name, mi.category, mi.price,
+            g.id AS group_id, g.name AS group_name, g.min_select, g.max_select,
+            o.id AS option_id, o.name AS option_name, o.price_delta
+        FROM menu_item mi
+        LEFT JOIN modifier_group g
+        ON g.menu_item_id = mi.id
+        LEFT JOIN modifier_option o
+        ON o.group_id = g.id
+        WHERE $1::integer[] IS NULL OR mi.id = ANY($1)
+        ORDER BY mi.id, g.id, o.id
+    "#, &[&ids]).await?;
+    let mut items: Vec<MenuItem> = vec![];
+    for row in rows.iter() {
+        let id: i32 = row.get("id");
+        if items.last().is_none_or(|item| item.id != id) {
+            items.push(MenuItem {
+                id,
+                name: row.get("name"),
+                category: row.get("category"),
+                price: row.get("price"),
+                modifier_groups: vec![],
+            });
+        }
+        let item = items.last_mut().unwrap();
+        let Some(group_id) = row.get::<&str, Option<i32>>("group_id") else {
+            continue;
+        };
+        if item.modifier_groups.last().is_none_or(|group| group.id != group_id) {
+            item.modifier_groups.push(ModifierGroup {
+                id: group_id,
+                name: row.get("group_name"),
+                min_select: row.get("min_select"),
+                max_select: row.get("max_select"),
+                options: vec![],
+            });
+        }
+        if let Some(option_id) = row.get::<&str, Option<i32>>("option_id") {
+            item.modifier_groups.last_mut().unwrap().options.push(ModifierOption {
+                id: option_id,
+                name: row.get("option_name"),
+                price_delta: row.get("price_delta"),
+            });
+        }
+    }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn option(id: i32, name: &str, price_delta: i64) -> ModifierOption {
+        ModifierOption { id, name: name.to_string(), price_delta }
+    }
+
+    fn ramen() -> MenuItem {
+        MenuItem {
+            id: 4,
+            name: "Ramen".to_string(),
+            category: "C".to_string(),
+            price: 320,
+            modifier_groups: vec![
+                ModifierGroup {
+                    id: 2,
+                    name: "Size".to_string(),
+                    min_select: 1,
+                    max_select: 1,
+                    options: vec![option(3, "Regular", 0), option(4, "Large", 80)],
+                },
+                ModifierGroup {
+                    id: 4,
+                    name: "Toppings".to_string(),
+                    min_select: 0,
+                    max_select: 2,
+                    options: vec![option(7, "Egg", 50), option(8, "Chashu", 120), option(9, "Nori", 30)],
+                },
+            ],
+        }
+    }
+
+    fn order(modifiers: Vec<i32>, note: Option<&str>) -> OrderItem {
+        OrderItem { menu_item_id: 4, quantity: 1, modifiers, note: note.map(str::to_string) }
+    }
+
+    #[test]
+    fn test_select() {
+        let item = ramen();
+        let selected = item.select(&order(vec![8, 4], Some("no onions"))).unwrap();
+        assert_eq!(selected.iter().map(|option| option.id).collect::<Vec<_>>(), vec![4, 8]);
+        assert_eq!(selected.iter().map(|option| option.price_delta).sum::<i64>(), 200);
+    }
+
+    #[test]
+    fn test_select_rejects_invalid_choices() {
+        let item = ramen();
+        assert_eq!(item.select(&order(vec![], None)), Err(OrderItemError::TooFewOptions));
+        assert_eq!(item.select(&OrderItem { quantity: 0, ..order(vec![3], None) }), Err(OrderItemError::QuantityOutOfRange));
+        assert_eq!(item.select(&OrderItem { quantity: MAX_QUANTITY + 1, ..order(vec![3], None) }), Err(OrderItemError::QuantityOutOfRange));
+        assert_eq!(item.select(&order(vec![3, 4], None)), Err(OrderItemError::TooManyOptions));
+        assert_eq!(item.select(&order(vec![3, 7, 8, 9], None)), Err(OrderItemError::TooManyOptions));
+        assert_eq!(item.select(&order(vec![3, 3], None)), Err(OrderItemError::DuplicateOption));
+        assert_eq!(item.select(&order(vec![3, 1], None)), Err(OrderItemError::UnknownOption));
+        let note = "x".repeat(MAX_NOTE_LEN + 1);
+        assert_eq!(item.select(&order(vec![3], Some(&note))), Err(OrderItemError::NoteTooLong));
+    }
+}
@@ -0,0 +1,192 @@
+use std::str::FromStr;
+use chrono::{DateTime, Utc};
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GetLeasesResponse {
+    /// id of the instance answering
+    pub instance: String,
+    pub leases: Vec<Lease>,
+}
+
+/// Lease of a scheduled job, only its holder runs the job until it expires
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Lease {
+    /// job name
+    pub name: String,
+    /// id of the instance holding the lease
+    pub holder: String,
+    pub acquired_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// expired leases are taken over by the next instance running the job
+    pub expired: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GetJobsParams {
+    /// latest runs listed for each job, 10 when absent
+    pub runs: Option<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GetJobsResponse {
+    /// id of the instance answering
+    pub instance: String,
+    pub jobs: Vec<JobStatus>,
+}
+
+/// A scheduled job as seen by the instance answering
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct JobStatus {
+    pub name: String,
+    /// disabled jobs only run when triggered
+    pub enabled: bool,
+    /// interval or cron expression
+    pub schedule: String,
+    /// next scheduled run on this instance, skipped when another instance holds the lease
+    pub next_run_at: Option<DateTime<Utc>>,
+    /// whether a run is in progress on this instance
+    pub running: bool,
+    /// latest runs on every instance, newest first
+    pub runs: Vec<JobRun>,
+}
+
+/// A finished run of a scheduled job
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct JobRun {
+    pub name: String,
+    /// id of the instance running the job
+    pub instance: String,
+    pub trigger: JobTrigger,
+    pub outcome: JobOutcome,
+    /// rows changed by a succeeded run
+    pub rows_affected: Option<i64>,
+    /// why the run did not succeed
+    pub error: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Display, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobTrigger {
+    /// started by the scheduler
+    #[display("schedule")]
+    Schedule,
+    /// started on demand through the admin API
+    #[display("manual")]
+    Manual,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Display, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobOutcome {
+    #[display("succeeded")]
+    Succeeded,
+    #[display("failed")]
+    Failed,
+    #[display("panicked")]
+    Panicked,
+    /// aborted after the timeout of the job
+    #[display("timed_out")]
+    TimedOut,
+}
+
+impl FromStr for JobTrigger {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "schedule" => Ok(Self::Schedule),
+            "manual" => Ok(Self::Manual),
+            s => Err(format!("Invalid JobTrigger: {s}")),
+        }
+    }
+}
+
+impl FromStr for JobOutcome {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "succeeded" => Ok(Self::Succeeded),
+            "failed" => Ok(Self::Failed),
+            "panicked" => Ok(Self::Panicked),
+            "timed_out" => Ok(Self::TimedOut),
+            s => Err(format!("Invalid JobOutcome: {s}")),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GetRetentionResponse {
+    /// where rows go before they are purged, `archive` or `ndjson`
+    pub target: String,
+    /// whether the retention job only logs what is due
+    pub dry_run: bool,
+    pub entities: Vec<RetentionReport>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GetCachesResponse {
+    pub caches: Vec<CacheStats>,
+}
+
+/// Counters of an in-process cache of the instance answering, since it started
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct CacheStats {
+    pub name: String,
+    /// seconds an entry is served for, off when 0
+    pub ttl_seconds: u64,
+    /// reads served from the cache
+    pub hits: u64,
+    /// reads that went to the database, every read when off
+    pub misses: u64,
+    /// times the cache was invalidated by writes
+    pub invalidations: u64,
+    /// whether an entry is cached, it may be expired
+    pub cached: bool,
+}
+
+/// Rows of an entity due for retention now, what the next run of the retention job moves
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RetentionReport {
+    pub entity: RetentionEntity,
+    /// days rows are kept, forever when absent
+    pub days: Option<u32>,
+    /// rows older than that
+    pub due: i64,
+    /// time the oldest due row is aged from
+    pub oldest_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Display, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RetentionEntity {
+    /// closed bills with their items, payments, splits and comps, aged from checkout
+    #[display("bills")]
+    Bills,
+    /// bill items removed from bills, aged from ordering
+    #[display("deleted_items")]
+    DeletedItems,
+    /// history of job runs, aged from the end of the run
+    #[display("job_runs")]
+    JobRuns,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(JobTrigger::from_str(&JobTrigger::Manual.to_string()), Ok(JobTrigger::Manual));
+        assert!(JobTrigger::from_str("foo").is_err());
+        assert_eq!(JobOutcome::from_str("timed_out"), Ok(JobOutcome::TimedOut));
+        assert_eq!(JobOutcome::from_str(&JobOutcome::Panicked.to_string()), Ok(JobOutcome::Panicked));
+        assert!(JobOutcome::from_str("foo").is_err());
+    }
+}
@@ -0,0 +1,57 @@
+use std::str::FromStr;
+use chrono::{DateTime, Utc};
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GetAlertsResponse {
+    pub alerts: Vec<Alert>,
+}
+
+/// An active alert of a bill flagged by the stale bill detector
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Alert {
+    pub id: i64,
+    pub bill_id: i64,
+    pub table_id: i16,
+    pub reason: AlertReason,
+    pub detected_at: DateTime<Utc>,
+    /// the bill is marked for review by the detector, it stays on its table until checked out
+    pub needs_review: bool,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Display, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertReason {
+    /// the bill is open longer than configured
+    #[display("open_too_long")]
+    OpenTooLong,
+    /// every item of the bill is delivered and nothing happened since for a while
+    #[display("idle")]
+    Idle,
+}
+
+impl FromStr for AlertReason {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "open_too_long" => Ok(Self::OpenTooLong),
+            "idle" => Ok(Self::Idle),
+            s => Err(format!("Invalid AlertReason: {s}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(AlertReason::from_str("idle"), Ok(AlertReason::Idle));
+        assert_eq!(AlertReason::from_str(&AlertReason::OpenTooLong.to_string()), Ok(AlertReason::OpenTooLong));
+        assert!(AlertReason::from_str("foo").is_err());
+    }
+}
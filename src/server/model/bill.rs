@@ -1,21 +1,145 @@
 use serde::{Deserialize, Serialize};
-use crate::server::model::item::Item;
+use crate::server::model::item::{Item, ItemGroup};
+use crate::server::model::pricing::PricedBill;
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Debug, Serialize)]
-pub(crate) struct GetBillResponse {
+pub(crate) const BILL_STATE_OPEN: &str = "open";
+pub(crate) const BILL_STATE_CLOSED: &str = "closed";
+/// closed by the stale bill detector without checking out, for a manager to review
+pub(crate) const BILL_STATE_NEEDS_REVIEW: &str = "needs_review";
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GetBillResponse {
     pub bill: Option<Bill>,
 }
 
 /// A bill that binds to a table, and binds to zero to many bill items
-#[derive(Debug, Serialize)]
-pub(crate) struct Bill {
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Bill {
     pub id: i64,
-    pub items: Vec<Item>,
+    pub items: BillItems,
+    /// totals of the whole bill regardless of paging, with every adjustment line
+    pub totals: PricedBill,
+}
+
+/// Items of a bill, one entry per bill item, or one entry per group of identical orders
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum BillItems {
+    Detailed(Vec<Item>),
+    Aggregated(Vec<ItemGroup>),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GetBillParams {
+    /// detailed (default) or aggregated
+    pub view: Option<BillView>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BillView {
+    #[default]
+    Detailed,
+    Aggregated,
 }
 
-#[derive(Debug, Deserialize)]
-pub(crate) struct PostBillItemsRequest {
-    pub items: Vec<MenuItemId>,
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PostBillItemsRequest {
+    #[schema(value_type = Vec<OrderItemRepr>)]
+    pub items: Vec<OrderItem>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PostBillItemsResponse {
+    /// created bill items, in the order of the request
+    pub items: Vec<OrderedItem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct OrderedItem {
+    #[schema(value_type = i32)]
+    pub menu_item_id: MenuItemId,
+    /// one bill item per unit ordered
+    pub ids: Vec<i64>,
 }
 
 type MenuItemId = i32;
+
+/// An item to order, given as a bare menu item id or as an object with a quantity, modifier option ids and a note
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "OrderItemRepr")]
+pub struct OrderItem {
+    pub menu_item_id: MenuItemId,
+    /// units to order, every unit becomes a bill item sharing the same delivery time
+    pub quantity: i32,
+    pub modifiers: Vec<i32>,
+    /// special instructions for the kitchen
+    pub note: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(untagged)]
+#[schema(as = OrderItem)]
+enum OrderItemRepr {
+    Id(i32),
+    Detailed {
+        menu_item_id: i32,
+        #[serde(default = "default_quantity")]
+        quantity: i32,
+        #[serde(default)]
+        modifiers: Vec<i32>,
+        #[serde(default)]
+        note: Option<String>,
+    },
+}
+
+fn default_quantity() -> i32 {
+    1
+}
+
+impl From<OrderItemRepr> for OrderItem {
+    fn from(repr: OrderItemRepr) -> Self {
+        match repr {
+            OrderItemRepr::Id(menu_item_id) => OrderItem { menu_item_id, quantity: default_quantity(), modifiers: vec![], note: None },
+            OrderItemRepr::Detailed { menu_item_id, quantity, modifiers, note } => OrderItem { menu_item_id, quantity, modifiers, note },
+        }
+    }
+}
+
+impl From<MenuItemId> for OrderItem {
+    fn from(menu_item_id: MenuItemId) -> Self {
+        OrderItemRepr::Id(menu_item_id).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_order_items() {
+        let body = r#"{"items": [1, {"menu_item_id": 4, "modifiers": [4, 7], "note": "no onions"}, {"menu_item_id": 3, "quantity": 5}]}"#;
+        let request: PostBillItemsRequest = serde_json::from_str(body).unwrap();
+        assert_eq!(request.items, vec![
+            OrderItem { menu_item_id: 1, quantity: 1, modifiers: vec![], note: None },
+            OrderItem { menu_item_id: 4, quantity: 1, modifiers: vec![4, 7], note: Some("no onions".to_string()) },
+            OrderItem { menu_item_id: 3, quantity: 5, modifiers: vec![], note: None },
+        ]);
+        assert!(serde_json::from_str::<PostBillItemsRequest>(r#"{"items": ["1"]}"#).is_err());
+    }
+    #[test]
+    fn test_round_trip() {
+        let request = PostBillItemsRequest {
+            items: vec![OrderItem::from(1), OrderItem { menu_item_id: 4, quantity: 2, modifiers: vec![4], note: None }],
+        };
+        let body = serde_json::to_string(&request).unwrap();
+        assert_eq!(serde_json::from_str::<PostBillItemsRequest>(&body).unwrap().items, request.items);
+
+        let body = r#"{"bill": {"id": 7, "items": [{"menu_item_id": 1, "name": "Fried chicken", "modifiers": [], "note": null, "state": "cooking", "quantity": 2, "ids": [1, 2]}],
+            "totals": {"subtotal": 500, "adjustments": [], "total": 500}}}"#;
+        let response: GetBillResponse = serde_json::from_str(body).unwrap();
+        assert!(matches!(response.bill.unwrap().items, BillItems::Aggregated(groups) if groups[0].ids == vec![1, 2]));
+    }
+}
@@ -0,0 +1,164 @@
+use std::str::FromStr;
+use chrono::{DateTime, Utc};
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use crate::server::model::alert::AlertReason;
+use crate::server::model::bill::OrderedItem;
+
+/// Something that happened in the restaurant, pushed to clients of the event stream or delivered to integrations
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Event {
+    /// A bill item was marked delivered once its time to deliver passed
+    ItemDelivered {
+        bill_id: i64,
+        bill_item_id: i64,
+        delivered_at: DateTime<Utc>,
+    },
+    /// A bill was flagged by the stale bill detector
+    BillFlagged {
+        alert_id: i64,
+        bill_id: i64,
+        table_id: i16,
+        reason: AlertReason,
+        detected_at: DateTime<Utc>,
+    },
+    /// A stale bill was marked for review by the detector, it stays on its table
+    BillNeedsReview {
+        bill_id: i64,
+        table_id: i16,
+    },
+    /// A table was claimed with a new bill, for a reservation when given
+    BillOpened {
+        bill_id: i64,
+        table_id: i16,
+        reservation_id: Option<i64>,
+    },
+    /// Items were ordered on a bill, one id per unit
+    ItemsOrdered {
+        bill_id: i64,
+        items: Vec<OrderedItem>,
+        ordered_at: DateTime<Utc>,
+    },
+    /// A bill item was removed from its bill
+    ItemRemoved {
+        bill_id: i64,
+        bill_item_id: i64,
+    },
+    /// A bill was paid in full and checked out, and its table freed
+    BillClosed {
+        bill_id: i64,
+        table_id: i16,
+        closed_at: DateTime<Utc>,
+    },
+    /// A table was bound to a bill or freed, through any replica
+    TableChanged {
+        table_id: i16,
+        /// bill on the table, none once freed
+        bill_id: Option<i64>,
+    },
+    /// A bill item was added, changed state or removed, through any replica
+    BillItemChanged {
+        bill_id: i64,
+        bill_item_id: i64,
+        change: RowChange,
+        state: Option<String>,
+    },
+}
+
+impl Event {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Event::ItemDelivered { .. } => EventKind::ItemDelivered,
+            Event::BillFlagged { .. } => EventKind::BillFlagged,
+            Event::BillNeedsReview { .. } => EventKind::BillNeedsReview,
+            Event::BillOpened { .. } => EventKind::BillOpened,
+            Event::ItemsOrdered { .. } => EventKind::ItemsOrdered,
+            Event::ItemRemoved { .. } => EventKind::ItemRemoved,
+            Event::BillClosed { .. } => EventKind::BillClosed,
+            Event::TableChanged { .. } => EventKind::TableChanged,
+            Event::BillItemChanged { .. } => EventKind::BillItemChanged,
+        }
+    }
+}
+
+/// The `kind` of an event
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Display, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    #[display("item_delivered")]
+    ItemDelivered,
+    #[display("bill_flagged")]
+    BillFlagged,
+    #[display("bill_needs_review")]
+    BillNeedsReview,
+    #[display("bill_opened")]
+    BillOpened,
+    #[display("items_ordered")]
+    ItemsOrdered,
+    #[display("item_removed")]
+    ItemRemoved,
+    #[display("bill_closed")]
+    BillClosed,
+    #[display("table_changed")]
+    TableChanged,
+    #[display("bill_item_changed")]
+    BillItemChanged,
+}
+
+impl FromStr for EventKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "item_delivered" => Ok(Self::ItemDelivered),
+            "bill_flagged" => Ok(Self::BillFlagged),
+            "bill_needs_review" => Ok(Self::BillNeedsReview),
+            "bill_opened" => Ok(Self::BillOpened),
+            "items_ordered" => Ok(Self::ItemsOrdered),
+            "item_removed" => Ok(Self::ItemRemoved),
+            "bill_closed" => Ok(Self::BillClosed),
+            "table_changed" => Ok(Self::TableChanged),
+            "bill_item_changed" => Ok(Self::BillItemChanged),
+            s => Err(format!("Invalid EventKind: {s}")),
+        }
+    }
+}
+
+/// How a row changed, as told by a database trigger
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Display, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RowChange {
+    #[display("inserted")]
+    Inserted,
+    #[display("updated")]
+    Updated,
+    #[display("deleted")]
+    Deleted,
+}
+
+/// An event of the outbox as delivered to sinks, a sink may get it more than once and tells by the id
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct OutboxMessage {
+    pub id: i64,
+    /// time of the transaction that made the change
+    pub created_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kind() {
+        let event = Event::BillOpened { bill_id: 1, table_id: 2, reservation_id: None };
+        assert_eq!(event.kind(), EventKind::BillOpened);
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["kind"], event.kind().to_string());
+        assert_eq!(EventKind::from_str("bill_closed"), Ok(EventKind::BillClosed));
+        assert!(EventKind::from_str("foo").is_err());
+    }
+}
@@ -0,0 +1,115 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportKind {
+    Bills,
+    Items,
+    Payments,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportParams {
+    /// records created at or after this time, the beginning when absent
+    pub from: Option<DateTime<Utc>>,
+    /// records created before this time, now when absent
+    pub to: Option<DateTime<Utc>>,
+    /// csv (default) or ndjson
+    pub format: Option<ExportFormat>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Ndjson,
+}
+
+/// A record of any exported kind, serialized as the record itself
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub(crate) enum ExportRecord {
+    Bill(BillRecord),
+    Item(ItemRecord),
+    Payment(PaymentRecord),
+}
+
+/// A bill with its totals, amounts are in the smallest currency unit
+#[derive(Debug, Serialize)]
+pub(crate) struct BillRecord {
+    pub id: i64,
+    pub table_id: i16,
+    pub created_at: DateTime<Utc>,
+    pub checkout_at: Option<DateTime<Utc>>,
+    pub subtotal: i64,
+    pub total: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ItemRecord {
+    pub id: i64,
+    pub bill_id: i64,
+    pub menu_item_id: i32,
+    pub name: String,
+    pub state: Option<String>,
+    /// names of the chosen modifier options, separated by semicolons
+    pub modifiers: String,
+    pub note: Option<String>,
+    /// menu price with the deltas of chosen modifiers
+    pub price: i64,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct PaymentRecord {
+    pub id: i64,
+    pub bill_id: i64,
+    pub split_id: Option<i64>,
+    pub refund_of: Option<i64>,
+    pub kind: String,
+    pub provider: String,
+    pub amount: i64,
+    pub tip: i64,
+    pub reference: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportMenuParams {
+    /// validate and report without writing anything
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// A menu item to import, items with an id are created or updated in place, items without one are created
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct MenuItemRecord {
+    pub id: Option<i32>,
+    pub name: String,
+    pub category: String,
+    pub price: i64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct ImportMenuResponse {
+    pub dry_run: bool,
+    pub created: u64,
+    pub updated: u64,
+    /// ids of existing items whose price changes, items ordered before keep the price they were ordered at
+    pub repriced: Vec<i32>,
+    /// nothing is written when there is any
+    pub errors: Vec<ImportIssue>,
+}
+
+/// A problem found in the imported file, lines start from 1 and count the CSV header
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ImportIssue {
+    pub line: usize,
+    pub message: String,
+}
@@ -1,8 +1,9 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 /// A bill item that contains the order details
-#[derive(Debug, Serialize)]
-pub(crate) struct Item {
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Item {
     /// menu item id
     pub id: i64,
     /// menu item name
@@ -11,4 +12,23 @@ pub(crate) struct Item {
     pub time_to_deliver: i32,
     /// status of the order
     pub state: String,
+    /// names of the chosen modifier options
+    pub modifiers: Vec<String>,
+    /// special instructions for the kitchen
+    pub note: Option<String>,
+}
+
+/// Identical orders of a bill, grouped by menu item, modifiers, note and status
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ItemGroup {
+    pub menu_item_id: i32,
+    /// menu item name
+    pub name: String,
+    pub modifiers: Vec<String>,
+    pub note: Option<String>,
+    /// status of the orders
+    pub state: String,
+    pub quantity: i64,
+    /// bill item ids in the group
+    pub ids: Vec<i64>,
 }
@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GetMenuResponse {
+    pub items: Vec<MenuItem>,
+}
+
+/// A menu item with the modifiers that can be chosen when ordering it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct MenuItem {
+    pub id: i32,
+    pub name: String,
+    pub category: String,
+    /// in the smallest currency unit
+    pub price: i64,
+    pub modifier_groups: Vec<ModifierGroup>,
+}
+
+/// A group of options, e.g. size, of which between min_select and max_select are chosen
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ModifierGroup {
+    pub id: i32,
+    pub name: String,
+    pub min_select: i16,
+    pub max_select: i16,
+    pub options: Vec<ModifierOption>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ModifierOption {
+    pub id: i32,
+    pub name: String,
+    /// added to the menu item price when chosen
+    pub price_delta: i64,
+}
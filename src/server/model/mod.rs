@@ -1,12 +1,26 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use utoipa::IntoParams;
 
-pub(crate) mod bill;
-pub(crate) mod config;
-pub(crate) mod item;
-pub(crate) mod table;
+pub mod admin;
+pub mod alert;
+pub mod bill;
+pub mod config;
+pub mod event;
+pub mod export;
+pub mod item;
+pub mod menu;
+pub mod payment;
+pub mod pricing;
+pub mod receipt;
+pub mod report;
+pub mod reservation;
+pub mod split;
+pub mod table;
+pub mod webhook;
 
-#[derive(Debug, Deserialize)]
-pub(crate) struct CommonRequestParams {
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CommonRequestParams {
     pub page: Option<u8>,
     pub page_size: Option<u8>,
 }
\ No newline at end of file
@@ -0,0 +1,68 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PostPaymentRequest {
+    /// Pay amount plus an optional tip, optionally for one split of the bill
+    Payment {
+        provider: PaymentMethod,
+        amount: i64,
+        #[serde(default)]
+        tip: i64,
+        split_id: Option<i64>,
+    },
+    /// Give back part or all of a previous payment, through the provider of that payment
+    Refund {
+        payment_id: i64,
+        amount: i64,
+    },
+}
+
+/// Payment providers that can be chosen by the client
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentMethod {
+    Cash,
+    /// a stand-in card provider, only built for tests
+    #[cfg(test)]
+    FakeCard,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PostPaymentResponse {
+    pub payment: Payment,
+    pub balance: Balance,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GetPaymentsResponse {
+    pub balance: Balance,
+    pub payments: Vec<Payment>,
+}
+
+/// A payment or refund recorded for a bill
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Payment {
+    pub id: i64,
+    pub split_id: Option<i64>,
+    pub refund_of: Option<i64>,
+    /// payment or refund
+    pub kind: String,
+    pub provider: String,
+    pub amount: i64,
+    pub tip: i64,
+    pub reference: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Amounts of a bill, in the smallest currency unit
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct Balance {
+    pub total: i64,
+    /// net paid amount, refunds deducted and tips excluded
+    pub paid: i64,
+    pub tips: i64,
+    pub outstanding: i64,
+}
@@ -0,0 +1,154 @@
+use std::str::FromStr;
+use chrono::NaiveTime;
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Prices of a bill with every adjustment applied, amounts are in the smallest currency unit
+#[derive(Debug, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct PricedBill {
+    /// sum of item prices before adjustments
+    pub subtotal: i64,
+    pub adjustments: Vec<Adjustment>,
+    pub total: i64,
+    /// net amount of each item, bill level adjustments are allocated proportionally
+    #[serde(skip)]
+    pub items: Vec<PricedItem>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PricedItem {
+    pub bill_item_id: i64,
+    /// price before adjustments
+    pub gross: i64,
+    pub net: i64,
+}
+
+/// A line that changes the bill total
+#[derive(Debug, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct Adjustment {
+    pub kind: AdjustmentKind,
+    /// rule name for discounts, reason code for comps
+    pub label: String,
+    /// the adjusted bill item, none for bill level adjustments
+    pub bill_item_id: Option<i64>,
+    /// always negative
+    pub amount: i64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AdjustmentKind {
+    Discount,
+    Comp,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Display, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscountKind {
+    #[display("percentage")]
+    Percentage,
+    #[display("fixed")]
+    Fixed,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Display, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscountScope {
+    #[display("item")]
+    Item,
+    #[display("bill")]
+    Bill,
+}
+
+/// A discount rule, e.g. 20% off drinks from 17:00 to 19:00
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, ToSchema)]
+pub struct DiscountRule {
+    #[serde(default)]
+    pub id: i32,
+    pub name: String,
+    pub kind: DiscountKind,
+    pub scope: DiscountScope,
+    /// percentage points for percentage rules, amount for fixed rules
+    pub value: i64,
+    pub category: Option<String>,
+    pub menu_item_id: Option<i32>,
+    pub starts_at: Option<NaiveTime>,
+    pub ends_at: Option<NaiveTime>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GetDiscountRulesResponse {
+    pub rules: Vec<DiscountRule>,
+}
+
+/// Reasons accepted for manual comps
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Display, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReasonCode {
+    #[display("quality")]
+    Quality,
+    #[display("long_wait")]
+    LongWait,
+    #[display("wrong_order")]
+    WrongOrder,
+    #[display("staff_meal")]
+    StaffMeal,
+    #[display("goodwill")]
+    Goodwill,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PostCompRequest {
+    /// comp a bill item, or an amount of the whole bill when absent
+    pub bill_item_id: Option<i64>,
+    pub reason_code: ReasonCode,
+    /// required for bill comps, comps the whole item when absent for item comps
+    pub amount: Option<i64>,
+}
+
+/// A manual comp of a bill
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Comp {
+    pub bill_item_id: Option<i64>,
+    pub reason_code: String,
+    pub amount: Option<i64>,
+}
+
+impl FromStr for DiscountKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "percentage" => Ok(Self::Percentage),
+            "fixed" => Ok(Self::Fixed),
+            s => Err(format!("Invalid DiscountKind: {s}")),
+        }
+    }
+}
+
+impl FromStr for DiscountScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "item" => Ok(Self::Item),
+            "bill" => Ok(Self::Bill),
+            s => Err(format!("Invalid DiscountScope: {s}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(DiscountKind::from_str("percentage"), Ok(DiscountKind::Percentage));
+        assert_eq!(DiscountKind::from_str(&DiscountKind::Fixed.to_string()), Ok(DiscountKind::Fixed));
+        assert!(DiscountKind::from_str("foo").is_err());
+        assert_eq!(DiscountScope::from_str(&DiscountScope::Bill.to_string()), Ok(DiscountScope::Bill));
+        assert!(DiscountScope::from_str("foo").is_err());
+    }
+}
@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GetReceiptParams {
+    /// text (default) or html
+    pub format: Option<ReceiptFormat>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GetTicketParams {
+    /// only items ordered after this time, items not delivered yet when absent
+    pub since: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiptFormat {
+    #[default]
+    Text,
+    Html,
+}
+
+/// Everything printed on a receipt, amounts are in the smallest currency unit
+#[derive(Debug, Serialize)]
+pub(crate) struct Receipt {
+    pub restaurant: String,
+    pub bill_id: i64,
+    pub table_id: i16,
+    pub opened_at: String,
+    pub checkout_at: Option<String>,
+    pub printed_at: String,
+    pub items: Vec<ReceiptLine>,
+    pub subtotal: i64,
+    pub adjustments: Vec<ReceiptLine>,
+    pub total: i64,
+    pub tax_rate: u8,
+    /// tax included in the total
+    pub tax: i64,
+    pub payments: Vec<ReceiptLine>,
+    pub paid: i64,
+    pub tips: i64,
+    pub outstanding: i64,
+}
+
+/// A labelled amount on a receipt
+#[derive(Debug, Serialize)]
+pub(crate) struct ReceiptLine {
+    pub label: String,
+    pub amount: i64,
+}
+
+/// Items for the kitchen to prepare
+#[derive(Debug, Serialize)]
+pub(crate) struct KitchenTicket {
+    pub bill_id: i64,
+    pub table_id: i16,
+    pub printed_at: String,
+    pub items: Vec<TicketItem>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct TicketItem {
+    pub id: i64,
+    pub name: String,
+    pub modifiers: Vec<String>,
+    pub note: Option<String>,
+    pub ordered_at: String,
+}
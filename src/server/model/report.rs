@@ -0,0 +1,98 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+/// Parameters shared by reports, each report ignores what does not apply to it
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ReportParams {
+    /// start of the range, 7 days ago when absent
+    pub from: Option<DateTime<Utc>>,
+    /// end of the range, now when absent
+    pub to: Option<DateTime<Utc>>,
+    /// json (default) or csv
+    pub format: Option<ReportFormat>,
+    /// day (default) or hour, for revenue
+    pub granularity: Option<Granularity>,
+    /// rows to return, for top items and categories
+    pub limit: Option<u16>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Granularity {
+    #[default]
+    Day,
+    Hour,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ReportResponse<T> {
+    pub rows: Vec<T>,
+}
+
+/// Money collected in a period of the configured time zone, amounts are in the smallest currency unit
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RevenueRow {
+    pub period: NaiveDateTime,
+    pub payments: i64,
+    pub refunds: i64,
+    /// payments minus refunds, tips excluded
+    pub net: i64,
+    pub tips: i64,
+    /// bills with any payment in the period
+    pub bills: i64,
+}
+
+/// Units ordered and gross sales at the prices ordered at of a menu item
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ItemSalesRow {
+    pub menu_item_id: i32,
+    pub name: String,
+    pub category: String,
+    pub quantity: i64,
+    pub gross: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CategorySalesRow {
+    pub category: String,
+    pub quantity: i64,
+    pub gross: i64,
+}
+
+/// Time from claiming a table to checking it out, the row without a table is over all tables
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TurnoverRow {
+    pub table_id: Option<i16>,
+    pub bills: i64,
+    pub average_minutes: f64,
+}
+
+/// Estimated and actual delivery time of delivered items, the row without a menu item is over all items
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DeliveryRow {
+    pub menu_item_id: Option<i32>,
+    pub name: Option<String>,
+    pub delivered: i64,
+    pub average_estimated_minutes: f64,
+    pub average_actual_minutes: f64,
+}
+
+/// Ordered items that are removed later, the row without a menu item is over all items
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct VoidRow {
+    pub menu_item_id: Option<i32>,
+    pub name: Option<String>,
+    pub ordered: i64,
+    pub voided: i64,
+    pub void_rate: f64,
+}
@@ -0,0 +1,83 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+pub(crate) const RESERVATION_STATE_BOOKED: &str = "booked";
+pub(crate) const RESERVATION_STATE_SEATED: &str = "seated";
+pub(crate) const RESERVATION_STATE_CANCELLED: &str = "cancelled";
+
+pub(crate) const WAITLIST_STATE_WAITING: &str = "waiting";
+pub(crate) const WAITLIST_STATE_REMOVED: &str = "removed";
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PostReservationRequest {
+    pub party_size: i16,
+    pub starts_at: DateTime<Utc>,
+    #[serde(default = "default_duration_minutes")]
+    pub duration_minutes: i64,
+    /// contact name
+    pub name: String,
+    /// contact phone
+    pub phone: Option<String>,
+    /// a specific table, the smallest free table seating the party when absent
+    pub table_id: Option<i16>,
+}
+
+fn default_duration_minutes() -> i64 {
+    90
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GetReservationsParams {
+    /// only reservations ending after this time, now when absent
+    pub from: Option<DateTime<Utc>>,
+    /// only reservations starting before this time
+    pub to: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GetReservationsResponse {
+    pub reservations: Vec<Reservation>,
+}
+
+/// A booking of a table for a time slot
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Reservation {
+    pub id: i64,
+    pub table_id: i16,
+    pub party_size: i16,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub name: String,
+    pub phone: Option<String>,
+    /// booked, seated or cancelled
+    pub state: String,
+    /// bill of the party once seated
+    pub bill_id: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PostWaitlistRequest {
+    pub party_size: i16,
+    pub name: String,
+    pub phone: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GetWaitlistResponse {
+    /// waiting parties, first come first
+    pub parties: Vec<WaitlistEntry>,
+}
+
+/// A walk-in party waiting for a table
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct WaitlistEntry {
+    pub id: i64,
+    pub party_size: i16,
+    pub name: String,
+    pub phone: Option<String>,
+    pub created_at: DateTime<Utc>,
+    /// none when no table can seat the party
+    pub estimated_wait_minutes: Option<i64>,
+}
@@ -0,0 +1,114 @@
+use std::collections::HashSet;
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum PostBillSplitsRequest {
+    /// Partition bill items into groups, every live bill item must be in exactly one group
+    Items { items: Vec<Vec<i64>> },
+    /// Divide the bill total evenly into the given number of parts
+    Equal { parts: u8 },
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GetBillSplitsResponse {
+    pub splits: Vec<Split>,
+}
+
+/// A part of a bill that is settled separately
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Split {
+    pub id: i64,
+    /// amount to pay, in the smallest currency unit
+    pub amount: i64,
+    /// open or settled
+    pub state: String,
+    /// bill item ids, empty when split evenly
+    pub items: Vec<i64>,
+}
+
+pub(crate) const SPLIT_STATE_OPEN: &str = "open";
+pub(crate) const SPLIT_STATE_SETTLED: &str = "settled";
+
+#[derive(Debug, Display, PartialEq)]
+pub(crate) enum SplitError {
+    #[display("a bill can only be split into 1 to {} parts", MAX_PARTS)]
+    InvalidParts,
+    #[display("empty split group")]
+    EmptyGroup,
+    #[display("bill item {} is not in the bill", _0)]
+    UnknownItem(i64),
+    #[display("bill item {} appears more than once", _0)]
+    DuplicateItem(i64),
+    #[display("bill item {} is not assigned to any split", _0)]
+    MissingItem(i64),
+}
+
+const MAX_PARTS: usize = 32;
+
+/// Divide total into `parts` amounts, the remainder is spread one unit at a time over the first parts
+pub(crate) fn equal_shares(total: i64, parts: u8) -> Result<Vec<i64>, SplitError> {
+    let parts = parts as usize;
+    if parts == 0 || parts > MAX_PARTS {
+        return Err(SplitError::InvalidParts);
+    }
+    let (share, remainder) = (total / parts as i64, (total % parts as i64) as usize);
+    Ok((0..parts).map(|i| share + if i < remainder { 1 } else { 0 }).collect())
+}
+
+/// Validate that groups partition the bill items, and sum up the amount of each group.
+/// `bill_items` are pairs of (bill item id, amount).
+pub(crate) fn partition_by_items(bill_items: &[(i64, i64)], groups: &[Vec<i64>]) -> Result<Vec<i64>, SplitError> {
+    if groups.is_empty() || groups.len() > MAX_PARTS {
+        return Err(SplitError::InvalidParts);
+    }
+    let mut seen = HashSet::with_capacity(bill_items.len());
+    let mut amounts = Vec::with_capacity(groups.len());
+    for group in groups {
+        if group.is_empty() {
+            return Err(SplitError::EmptyGroup);
+        }
+        let mut amount = 0;
+        for item_id in group {
+            let Some((_, item_amount)) = bill_items.iter().find(|(id, _)| id == item_id) else {
+                return Err(SplitError::UnknownItem(*item_id));
+            };
+            if !seen.insert(*item_id) {
+                return Err(SplitError::DuplicateItem(*item_id));
+            }
+            amount += item_amount;
+        }
+        amounts.push(amount);
+    }
+    match bill_items.iter().find(|(id, _)| !seen.contains(id)) {
+        Some((id, _)) => Err(SplitError::MissingItem(*id)),
+        None => Ok(amounts),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_equal_shares() {
+        assert_eq!(equal_shares(100, 3), Ok(vec![34, 33, 33]));
+        assert_eq!(equal_shares(90, 3), Ok(vec![30, 30, 30]));
+        assert_eq!(equal_shares(0, 2), Ok(vec![0, 0]));
+        assert_eq!(equal_shares(100, 0), Err(SplitError::InvalidParts));
+        assert_eq!(equal_shares(100, 33), Err(SplitError::InvalidParts));
+    }
+
+    #[test]
+    fn test_partition_by_items() {
+        let items = [(1, 100), (2, 50), (3, 25)];
+        assert_eq!(partition_by_items(&items, &[vec![1, 3], vec![2]]), Ok(vec![125, 50]));
+        assert_eq!(partition_by_items(&items, &[]), Err(SplitError::InvalidParts));
+        assert_eq!(partition_by_items(&items, &[vec![1, 2, 3], vec![]]), Err(SplitError::EmptyGroup));
+        assert_eq!(partition_by_items(&items, &[vec![1, 2, 3, 4]]), Err(SplitError::UnknownItem(4)));
+        assert_eq!(partition_by_items(&items, &[vec![1, 2], vec![2, 3]]), Err(SplitError::DuplicateItem(2)));
+        assert_eq!(partition_by_items(&items, &[vec![1], vec![2]]), Err(SplitError::MissingItem(3)));
+    }
+}
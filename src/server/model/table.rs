@@ -1,24 +1,34 @@
-use serde::{Serialize};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Debug, Serialize)]
-pub(crate) struct PatchTablesResponse {
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PatchTableParams {
+    /// the reservation being seated, required when the table is held for it
+    pub reservation_id: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PatchTablesResponse {
     pub bill_id: i64,
 }
 
-#[derive(Debug, Serialize)]
-pub(crate) struct PostTablesResponse {
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PostTablesResponse {
     pub id: u8,
 }
 
 
-#[derive(Debug, Serialize)]
-pub(crate) struct GetTablesResponse {
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GetTablesResponse {
     pub tables: Option<Vec<Table>>, 
 }
 
 /// A table in the restaurant
-#[derive(Debug, Serialize)]
-pub(crate) struct Table {
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Table {
     pub id: u8,
+    /// seats of the table
+    pub capacity: u8,
     pub bill_id: Option<i64>, // only when table is occupied there will be associated bill
 }
\ No newline at end of file
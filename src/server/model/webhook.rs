@@ -0,0 +1,119 @@
+use std::str::FromStr;
+use chrono::{DateTime, Utc};
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use crate::server::model::event::EventKind;
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PostWebhookRequest {
+    /// HTTP or HTTPS URL events are posted to
+    pub url: String,
+    /// kinds of integration events delivered, every kind when empty
+    #[serde(default)]
+    pub events: Vec<EventKind>,
+    /// key of the HMAC signatures, generated when absent
+    pub secret: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PostWebhookResponse {
+    pub id: i64,
+    /// key of the HMAC signatures, only told here
+    pub secret: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GetWebhooksResponse {
+    pub webhooks: Vec<Webhook>,
+}
+
+/// A webhook subscription, its secret is left out
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Webhook {
+    pub id: i64,
+    pub url: String,
+    /// kinds of integration events delivered, every kind when empty
+    pub events: Vec<EventKind>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GetWebhookDeliveriesParams {
+    /// only deliveries in this state
+    pub state: Option<DeliveryState>,
+    /// latest deliveries listed, 50 when absent
+    pub limit: Option<u16>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GetWebhookDeliveriesResponse {
+    /// newest first
+    pub deliveries: Vec<WebhookDelivery>,
+}
+
+/// Delivery of an event to a webhook, with the outcome of its latest attempt
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    /// id of the event, the same for every webhook it is delivered to
+    pub event_id: i64,
+    pub kind: EventKind,
+    pub state: DeliveryState,
+    pub attempts: i32,
+    /// next attempt of a pending delivery
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    /// HTTP status of the latest response
+    pub response_status: Option<i16>,
+    /// error of the latest failed attempt
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Display, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryState {
+    /// not delivered yet, attempted again later
+    #[display("pending")]
+    Pending,
+    /// answered with a 2xx status
+    #[display("delivered")]
+    Delivered,
+    /// given up after the last attempt
+    #[display("failed")]
+    Failed,
+}
+
+impl FromStr for DeliveryState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(Self::Pending),
+            "delivered" => Ok(Self::Delivered),
+            "failed" => Ok(Self::Failed),
+            s => Err(format!("Invalid DeliveryState: {s}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(DeliveryState::from_str("failed"), Ok(DeliveryState::Failed));
+        assert_eq!(DeliveryState::from_str(&DeliveryState::Pending.to_string()), Ok(DeliveryState::Pending));
+        assert!(DeliveryState::from_str("foo").is_err());
+    }
+
+    #[test]
+    fn test_post_webhook_request() {
+        let request: PostWebhookRequest = serde_json::from_str(r#"{"url": "https://example.com", "events": ["bill_closed"]}"#).unwrap();
+        assert_eq!((request.events, request.secret), (vec![EventKind::BillClosed], None));
+        assert!(serde_json::from_str::<PostWebhookRequest>(r#"{"url": "https://example.com", "events": ["foo"]}"#).is_err());
+    }
+}
@@ -0,0 +1,270 @@
+//! Transactional outbox, integration events are written in the transaction of the change they tell about
+//! and relayed to the configured sinks afterwards, at least once
+
+pub(crate) mod sink;
+
+use crate::server::database::pool::GenericTransaction;
+use crate::server::database::pool::GenericRow;
+#[cfg(test)]
+use crate::server::database::pool::DbClient;
+use std::time::Duration;
+use anyhow::{anyhow, Context as _, Error};
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
+use tokio_postgres::types::ToSql;
+use crate::server::database::pool::WrappedRow;
+use crate::server::model::config::{OutboxConfig, SinkKind};
+use crate::server::model::event::{Event, OutboxMessage};
+use crate::server::outbox::sink::Sink;
+use crate::server::scheduler::Context;
+use crate::server::state::AppState;
+use crate::server::webhook;
+
+/// Write an event within the transaction of the change, it is relayed only once the transaction commits
+pub(crate) async fn enqueue<R, T>(txn: &T, event: &Event) -> Result<(), Error>
+where
+    R: GenericRow,
+    WrappedRow<R>: GenericRow,
+    T: GenericTransaction<R>,
+{
+    let payload = serde_json::to_value(event)?;
+    let kind = event.kind().to_string();
+    let params: &[&(dyn ToSql + Sync)] = &[&kind, &payload];
+    txn.execute(r#"
+        INSERT INTO outbox (kind, payload, created_at, next_attempt_at)
+        VALUES ($1, $2, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP)
+    "#, params).await.context("failed to write the outbox")?;
+    Ok(())
+}
+
+/// An event claimed for delivery
+struct Claimed {
+    id: i64,
+    created_at: DateTime<Utc>,
+    payload: serde_json::Value,
+    /// names of the sinks that have the event already
+    delivered_to: Vec<String>,
+    attempts: i32,
+}
+
+/// What becomes of a claimed event after a delivery
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    /// every sink has it, it is removed
+    Completed,
+    /// some sink failed, it is delivered again after the delay to the sinks without it
+    Retry { error: String, delay: Duration },
+}
+
+/// Delivers events of the outbox to every sink, retrying failed deliveries with exponential backoff
+pub(crate) struct Relay {
+    sinks: Vec<Box<dyn Sink>>,
+    retry_base: Duration,
+    retry_max: Duration,
+    /// how long an event is claimed for, twice the time delivering it to every sink may take
+    claim: Duration,
+}
+
+impl Relay {
+    pub fn new(config: &OutboxConfig) -> Self {
+        let client = reqwest::Client::new();
+        let deliver_timeout = config.sinks.iter()
+            .map(|sink| match sink.kind {
+                SinkKind::Webhook => Duration::from_secs(sink.timeout_seconds),
+                SinkKind::File | SinkKind::Stdout => Duration::from_secs(1),
+            })
+            .sum::<Duration>();
+        Self {
+            sinks: config.sinks.iter().map(|sink| sink::from_config(sink, &client)).collect(),
+            retry_base: Duration::from_secs(config.retry_base_seconds),
+            retry_max: Duration::from_secs(config.retry_max_seconds),
+            claim: deliver_timeout * 2,
+        }
+    }
+
+    /// Deliver due events a batch at a time, until none is left due or the shutdown starts.
+    /// Returns the number of events every sink has now, they are removed from the outbox.
+    pub async fn run(&self, state: &AppState, context: &Context) -> Result<u64, Error> {
+        let delivered = context.in_batches("events delivered", || self.relay_batch(state, context.batch_size)).await?;
+        if delivered > 0 {
+            info!("delivered {} events from the outbox", delivered);
+        } else {
+            debug!("no event delivered, continue to sleep");
+        }
+        Ok(delivered)
+    }
+
+    /// Claim up to a batch of due events, deliver them oldest first and record the outcomes, returning how many were due and how many completed.
+    /// Claimed events are not due for a while, so that other relays leave them, and ones not recorded are delivered again then.
+    /// An event failed on a sink is retried later on the sinks without it only.
+    async fn relay_batch(&self, state: &AppState, batch_size: u32) -> Result<(u64, u64), Error> {
+        let mut due = self.claim(state, batch_size).await.context("failed to claim due events")?;
+
+        let mut outcomes = Vec::with_capacity(due.len());
+        for claimed in due.iter_mut() {
+            let errors = self.deliver(claimed).await;
+            outcomes.push(self.outcome(claimed, errors));
+        }
+
+        let Some(mut conn) = state.get_db_write_pool().acquire(state.get_db_timeout_seconds()).await else {
+            return Err(anyhow!("no connection available"));
+        };
+        let client = conn.client.as_mut().ok_or(anyhow!("client is None"))?;
+        let txn = client.transaction().await?;
+        let mut completed = vec![];
+        for (claimed, outcome) in due.iter().zip(outcomes) {
+            let Outcome::Retry { error, delay } = outcome else {
+                completed.push(claimed.id);
+                continue;
+            };
+            warn!("failed to deliver event {}, retrying in {:?}, {}", claimed.id, delay, error);
+            let params: &[&(dyn ToSql + Sync)] = &[&claimed.id, &claimed.delivered_to, &error, &delay.as_secs_f64()];
+            txn.execute(r#"
+                UPDATE outbox
+                SET delivered_to = $2, attempts = attempts + 1, last_error = $3,
+                    next_attempt_at = CURRENT_TIMESTAMP + make_interval(secs => $4)
+                WHERE id = $1
+            "#, params).await.context("failed to schedule a retry")?;
+        }
+        if !completed.is_empty() {
+            let params: &[&(dyn ToSql + Sync)] = &[&completed];
+            txn.execute("DELETE FROM outbox WHERE id = ANY($1)", params).await.context("failed to remove delivered events")?;
+        }
+        txn.commit().await?;
+        Ok((due.len() as u64, completed.len() as u64))
+    }
+
+    /// Deliver an event to the sinks without it yet, adding the ones it succeeds on to `delivered_to`.
+    /// Returns the errors of the sinks it failed on, or of the payload when it is no event.
+    async fn deliver(&self, claimed: &mut Claimed) -> Vec<String> {
+        let event = match serde_json::from_value::<Event>(claimed.payload.clone()) {
+            Ok(event) => event,
+            Err(e) => return vec![format!("malformed payload, {}", e)],
+        };
+        let message = OutboxMessage { id: claimed.id, created_at: claimed.created_at, event };
+        let mut failed = vec![];
+        for sink in &self.sinks {
+            if claimed.delivered_to.iter().any(|name| name == sink.name()) {
+                continue;
+            }
+            match sink.deliver(&message).await {
+                Ok(()) => claimed.delivered_to.push(sink.name().to_string()),
+                Err(e) => failed.push(format!("{}: {:#}", sink.name(), e)),
+            }
+        }
+        failed
+    }
+
+    /// Complete an event delivered without errors, otherwise retry it after the backoff of its attempts
+    fn outcome(&self, claimed: &Claimed, errors: Vec<String>) -> Outcome {
+        if errors.is_empty() {
+            return Outcome::Completed;
+        }
+        let delay = backoff(self.retry_base, self.retry_max, u32::try_from(claimed.attempts).unwrap_or_default());
+        Outcome::Retry { error: errors.join("; "), delay }
+    }
+
+    /// Take due events for the time delivering the batch may take, oldest first, and queue their webhook deliveries
+    async fn claim(&self, state: &AppState, batch_size: u32) -> Result<Vec<Claimed>, Error> {
+        let Some(mut conn) = state.get_db_write_pool().acquire(state.get_db_timeout_seconds()).await else {
+            return Err(anyhow!("no connection available"));
+        };
+        let client = conn.client.as_mut().ok_or(anyhow!("client is None"))?;
+        let txn = client.transaction().await?;
+        let claim_seconds = self.claim.saturating_mul(batch_size).as_secs_f64();
+        let params: &[&(dyn ToSql + Sync)] = &[&i64::from(batch_size), &claim_seconds];
+        let mut due = txn.query(r#"
+            WITH due AS (
+                SELECT id
+                FROM outbox
+                WHERE next_attempt_at <= CURRENT_TIMESTAMP
+                ORDER BY id
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            UPDATE outbox o
+            SET next_attempt_at = CURRENT_TIMESTAMP + make_interval(secs => $2)
+            FROM due
+            WHERE o.id = due.id
+            RETURNING o.id, o.payload, o.created_at, o.delivered_to, o.attempts
+        "#, params).await?.iter().map(|row| Claimed {
+            id: row.get("id"),
+            created_at: row.get::<&str, DateTime<Utc>>("created_at"),
+            payload: row.get("payload"),
+            delivered_to: row.get("delivered_to"),
+            attempts: row.get("attempts"),
+        }).collect::<Vec<_>>();
+        due.sort_by_key(|claimed| claimed.id);
+        // webhook subscriptions get a delivery of their own, see [webhook::Dispatcher]
+        let ids = due.iter().map(|claimed| claimed.id).collect::<Vec<_>>();
+        if !ids.is_empty() {
+            webhook::fan_out(&txn, &ids).await.context("failed to queue webhook deliveries")?;
+        }
+        txn.commit().await?;
+        Ok(due)
+    }
+}
+
+/// Wait before the next attempt after the given number of failed ones, doubled each time up to the maximum
+pub(crate) fn backoff(base: Duration, max: Duration, attempts: u32) -> Duration {
+    base.saturating_mul(2u32.saturating_pow(attempts)).min(max)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use crate::server::model::config::SinkConfig;
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let (base, max) = (Duration::from_secs(5), Duration::from_secs(60));
+        let delays = (0..6).map(|attempts| backoff(base, max, attempts).as_secs()).collect::<Vec<_>>();
+        assert_eq!(delays, vec![5, 10, 20, 40, 60, 60]);
+        assert_eq!(backoff(base, max, u32::MAX), max);
+    }
+
+    #[tokio::test]
+    async fn test_deliver() {
+        let dir = std::env::temp_dir().join(format!("bookish-eureka-relay-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // a file sink whose directory is a file fails every delivery
+        std::fs::write(dir.join("blocked"), "").unwrap();
+        let file = |name: &str, path: PathBuf| SinkConfig { name: name.to_string(), kind: SinkKind::File, path: Some(path), ..SinkConfig::default() };
+        let config = OutboxConfig {
+            sinks: vec![file("audit", dir.join("events.ndjson")), file("broken", dir.join("blocked").join("events.ndjson"))],
+            retry_base_seconds: 5,
+            retry_max_seconds: 60,
+        };
+        let relay = Relay::new(&config);
+        let event = Event::ItemRemoved { bill_id: 1, bill_item_id: 2 };
+        let mut claimed = Claimed { id: 7, created_at: Utc::now(), payload: serde_json::to_value(&event).unwrap(), delivered_to: vec![], attempts: 2 };
+
+        let errors = relay.deliver(&mut claimed).await;
+        assert_eq!(claimed.delivered_to, vec!["audit"]);
+        assert!(errors.len() == 1 && errors[0].starts_with("broken: "), "{:?}", errors);
+        let Outcome::Retry { error, delay } = relay.outcome(&claimed, errors) else { panic!("completed with a failed sink") };
+        assert!(error.starts_with("broken: "));
+        assert_eq!(delay, Duration::from_secs(20));
+
+        // the retry leaves out the sink which has the event already
+        claimed.attempts += 1;
+        let errors = relay.deliver(&mut claimed).await;
+        assert_eq!(claimed.delivered_to, vec!["audit"]);
+        assert_eq!(std::fs::read_to_string(dir.join("events.ndjson")).unwrap().lines().count(), 1);
+        assert!(matches!(relay.outcome(&claimed, errors), Outcome::Retry { delay, .. } if delay == Duration::from_secs(40)));
+
+        // done once the broken sink recovers
+        std::fs::remove_file(dir.join("blocked")).unwrap();
+        let errors = relay.deliver(&mut claimed).await;
+        assert_eq!(claimed.delivered_to, vec!["audit", "broken"]);
+        assert_eq!(relay.outcome(&claimed, errors), Outcome::Completed);
+
+        // a payload which is no event fails on every sink
+        let mut malformed = Claimed { id: 8, created_at: Utc::now(), payload: serde_json::json!({"kind": "unknown"}), delivered_to: vec![], attempts: 0 };
+        let errors = relay.deliver(&mut malformed).await;
+        assert!(malformed.delivered_to.is_empty());
+        assert!(errors.len() == 1 && errors[0].starts_with("malformed payload, "), "{:?}", errors);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,119 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+use anyhow::Error;
+use futures_util::future::BoxFuture;
+use crate::server::model::config::{SinkConfig, SinkKind};
+use crate::server::model::event::OutboxMessage;
+use crate::server::util::file::append_ndjson;
+
+/// A destination of integration events
+pub(crate) trait Sink: Send + Sync {
+    /// Unique name, deliveries are tracked by it
+    fn name(&self) -> &str;
+
+    /// Deliver one event, it is delivered again later when this fails
+    fn deliver<'a>(&'a self, message: &'a OutboxMessage) -> BoxFuture<'a, Result<(), Error>>;
+}
+
+/// Build a sink, configs are validated before
+pub(crate) fn from_config(config: &SinkConfig, client: &reqwest::Client) -> Box<dyn Sink> {
+    let name = config.name.clone();
+    match config.kind {
+        SinkKind::Webhook => Box::new(Webhook {
+            name,
+            url: config.url.clone().unwrap_or_default(),
+            timeout: Duration::from_secs(config.timeout_seconds),
+            client: client.clone(),
+        }),
+        SinkKind::File => Box::new(File { name, path: config.path.clone().unwrap_or_default() }),
+        SinkKind::Stdout => Box::new(Stdout { name }),
+    }
+}
+
+/// Posts each event in JSON, with its id in `X-Event-Id` as well
+struct Webhook {
+    name: String,
+    url: String,
+    timeout: Duration,
+    client: reqwest::Client,
+}
+
+impl Sink for Webhook {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn deliver<'a>(&'a self, message: &'a OutboxMessage) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            self.client.post(&self.url)
+                .timeout(self.timeout)
+                .header("X-Event-Id", message.id)
+                .json(message)
+                .send().await?
+                .error_for_status()?;
+            Ok(())
+        })
+    }
+}
+
+/// Appends each event to an NDJSON file, flushed to the disk before it counts as delivered
+struct File {
+    name: String,
+    path: PathBuf,
+}
+
+impl Sink for File {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn deliver<'a>(&'a self, message: &'a OutboxMessage) -> BoxFuture<'a, Result<(), Error>> {
+        let (path, message) = (self.path.clone(), message.clone());
+        Box::pin(async move { tokio::task::spawn_blocking(move || append_ndjson(&path, &[message])).await? })
+    }
+}
+
+/// Prints each event as a line of JSON, e.g. for a log shipper
+struct Stdout {
+    name: String,
+}
+
+impl Sink for Stdout {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn deliver<'a>(&'a self, message: &'a OutboxMessage) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let mut stdout = std::io::stdout().lock();
+            serde_json::to_writer(&mut stdout, message)?;
+            writeln!(stdout)?;
+            stdout.flush()?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use crate::server::model::event::Event;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_file() {
+        let path = std::env::temp_dir().join(format!("bookish-eureka-outbox-{}", std::process::id())).join("events.ndjson");
+        let config = SinkConfig { name: "audit".to_string(), kind: SinkKind::File, path: Some(path.clone()), ..SinkConfig::default() };
+        let sink = from_config(&config, &reqwest::Client::new());
+        let message = OutboxMessage { id: 7, created_at: Utc::now(), event: Event::ItemRemoved { bill_id: 1, bill_item_id: 2 } };
+        sink.deliver(&message).await.unwrap();
+        sink.deliver(&message).await.unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines = content.lines().map(|line| serde_json::from_str::<OutboxMessage>(line).unwrap()).collect::<Vec<_>>();
+        assert_eq!(lines, vec![message.clone(), message]);
+        assert!(content.starts_with(r#"{"id":7,"created_at":"#) && content.contains(r#""kind":"item_removed""#));
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,69 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::server::payment::{PaymentError, PaymentProvider};
+
+/// A stand-in card provider for tests, it approves every charge unless created
+/// as declining, and issues sequential references.
+#[derive(Debug, Default)]
+pub(crate) struct FakeCardProvider {
+    decline: bool,
+    sequence: AtomicU64,
+}
+
+impl FakeCardProvider {
+    /// Create a provider that approves every charge and refund
+    pub const fn new() -> Self {
+        Self {
+            decline: false,
+            sequence: AtomicU64::new(0),
+        }
+    }
+
+    /// Create a provider that declines every charge and refund
+    #[allow(unused)]
+    pub fn declining() -> Self {
+        Self {
+            decline: true,
+            ..Self::new()
+        }
+    }
+
+    fn next_reference(&self) -> String {
+        format!("fake-card-{}", self.sequence.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+}
+
+impl PaymentProvider for FakeCardProvider {
+    fn name(&self) -> &'static str {
+        "fake_card"
+    }
+
+    async fn charge(&self, _amount: i64, _tip: i64) -> Result<Option<String>, PaymentError> {
+        if self.decline {
+            return Err(PaymentError::Declined);
+        }
+        Ok(Some(self.next_reference()))
+    }
+
+    async fn refund(&self, reference: Option<&str>, _amount: i64) -> Result<Option<String>, PaymentError> {
+        if self.decline || reference.is_none() {
+            return Err(PaymentError::Declined);
+        }
+        Ok(Some(self.next_reference()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_fake_card_provider() {
+        let provider = FakeCardProvider::new();
+        assert_eq!(provider.charge(100, 0).await, Ok(Some("fake-card-1".to_string())));
+        assert_eq!(provider.refund(Some("fake-card-1"), 100).await, Ok(Some("fake-card-2".to_string())));
+        assert_eq!(provider.refund(None, 100).await, Err(PaymentError::Declined));
+
+        let provider = FakeCardProvider::declining();
+        assert_eq!(provider.charge(100, 0).await, Err(PaymentError::Declined));
+    }
+}
@@ -0,0 +1,32 @@
+use crate::server::payment::{PaymentError, PaymentProvider};
+
+/// Cash or any other payment collected manually at the cashier, it never fails and issues no reference
+#[derive(Debug, Default)]
+pub(crate) struct CashProvider;
+
+impl PaymentProvider for CashProvider {
+    fn name(&self) -> &'static str {
+        "cash"
+    }
+
+    async fn charge(&self, _amount: i64, _tip: i64) -> Result<Option<String>, PaymentError> {
+        Ok(None)
+    }
+
+    async fn refund(&self, _reference: Option<&str>, _amount: i64) -> Result<Option<String>, PaymentError> {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cash_provider() {
+        let provider = CashProvider;
+        assert_eq!(provider.name(), "cash");
+        assert_eq!(provider.charge(100, 10).await, Ok(None));
+        assert_eq!(provider.refund(None, 100).await, Ok(None));
+    }
+}
@@ -0,0 +1,139 @@
+//! Payment providers and bill balance bookkeeping
+
+#[cfg(test)]
+pub(crate) mod card;
+pub(crate) mod cash;
+
+use chrono_tz::Tz;
+use derive_more::{Display, Error};
+use crate::server::database::pool::{GenericRow, GenericTransaction, WrappedRow};
+use crate::server::model::payment::{Balance, PaymentMethod};
+#[cfg(test)]
+use crate::server::payment::card::FakeCardProvider;
+use crate::server::payment::cash::CashProvider;
+use crate::server::pricing;
+
+/// A trait that abstracts out how money is actually collected, so that new providers can be plugged in
+pub(crate) trait PaymentProvider {
+    /// Name persisted along with the payments made through this provider
+    fn name(&self) -> &'static str;
+
+    /// Collect amount plus tip, returns a transaction reference if the provider issues one
+    async fn charge(&self, amount: i64, tip: i64) -> Result<Option<String>, PaymentError>;
+
+    /// Give back amount of a previous charge identified by its reference
+    async fn refund(&self, reference: Option<&str>, amount: i64) -> Result<Option<String>, PaymentError>;
+}
+
+#[derive(Debug, Display, Error, PartialEq)]
+pub(crate) enum PaymentError {
+    /// only card providers decline, and only the fake one is built for tests so far
+    #[allow(unused)]
+    #[display("payment declined")]
+    Declined,
+    #[display("unknown payment provider {}", _0)]
+    UnknownProvider(#[error(not(source))] String),
+}
+
+#[cfg(test)]
+static FAKE_CARD_PROVIDER: FakeCardProvider = FakeCardProvider::new();
+
+pub(crate) const PAYMENT_KIND_PAYMENT: &str = "payment";
+pub(crate) const PAYMENT_KIND_REFUND: &str = "refund";
+
+/// Charge through the provider of the chosen method, returns the provider name and the transaction reference
+pub(crate) async fn charge(method: PaymentMethod, amount: i64, tip: i64) -> Result<(&'static str, Option<String>), PaymentError> {
+    match method {
+        PaymentMethod::Cash => charge_with(&CashProvider, amount, tip).await,
+        #[cfg(test)]
+        PaymentMethod::FakeCard => charge_with(&FAKE_CARD_PROVIDER, amount, tip).await,
+    }
+}
+
+/// Refund through the provider that took the original payment
+pub(crate) async fn refund(provider: &str, reference: Option<&str>, amount: i64) -> Result<Option<String>, PaymentError> {
+    if provider == CashProvider.name() {
+        return CashProvider.refund(reference, amount).await;
+    }
+    #[cfg(test)]
+    if provider == FAKE_CARD_PROVIDER.name() {
+        return FAKE_CARD_PROVIDER.refund(reference, amount).await;
+    }
+    Err(PaymentError::UnknownProvider(provider.to_string()))
+}
+
+async fn charge_with<P: PaymentProvider>(provider: &P, amount: i64, tip: i64) -> Result<(&'static str, Option<String>), PaymentError> {
+    provider.charge(amount, tip).await.map(|reference| (provider.name(), reference))
+}
+
+/// Get total and paid amount of a bill within a transaction
+pub(crate) async fn balance<R, T>(txn: &T, bill_id: i64, time_zone: Tz) -> Result<Balance, tokio_postgres::Error>
+where
+    R: GenericRow,
+    WrappedRow<R>: GenericRow,
+    T: GenericTransaction<R>,
+{
+    let total = pricing::load(txn, bill_id, time_zone).await?.total;
+    balance_of(txn, bill_id, total).await
+}
+
+/// Get paid amount of a bill already priced at total within a transaction
+pub(crate) async fn balance_of<R, T>(txn: &T, bill_id: i64, total: i64) -> Result<Balance, tokio_postgres::Error>
+where
+    R: GenericRow,
+    WrappedRow<R>: GenericRow,
+    T: GenericTransaction<R>,
+{
+    let row = txn.query_one(r#"
+        SELECT
+            COALESCE(SUM(CASE WHEN kind = 'payment' THEN amount ELSE -amount END), 0)::bigint AS paid,
+            COALESCE(SUM(CASE WHEN kind = 'payment' THEN tip ELSE 0 END), 0)::bigint AS tips
+        FROM payment
+        WHERE bill_id = $1
+    "#, &[&bill_id]).await?;
+    Ok(Balance::new(total, row.get("paid"), row.get("tips")))
+}
+
+impl Balance {
+    pub fn new(total: i64, paid: i64, tips: i64) -> Self {
+        Self {
+            total,
+            paid,
+            tips,
+            outstanding: (total - paid).max(0),
+        }
+    }
+
+    /// Whether the paid amount covers the total, tips excluded
+    pub fn is_settled(&self) -> bool {
+        self.paid >= self.total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_balance() {
+        let balance = Balance::new(1000, 400, 50);
+        assert_eq!(balance.outstanding, 600);
+        assert!(!balance.is_settled());
+
+        let balance = Balance::new(1000, 1000, 0);
+        assert_eq!(balance.outstanding, 0);
+        assert!(balance.is_settled());
+
+        let balance = Balance::new(0, 0, 0);
+        assert!(balance.is_settled());
+    }
+
+    #[tokio::test]
+    async fn test_charge_and_refund() {
+        assert_eq!(charge(PaymentMethod::Cash, 100, 0).await, Ok(("cash", None)));
+        let (provider, reference) = charge(PaymentMethod::FakeCard, 100, 10).await.unwrap();
+        assert_eq!(provider, "fake_card");
+        assert!(refund(provider, reference.as_deref(), 100).await.unwrap().is_some());
+        assert_eq!(refund("cheque", None, 100).await, Err(PaymentError::UnknownProvider("cheque".to_string())));
+    }
+}
@@ -0,0 +1,517 @@
+//! Pricing of bills, shared by bill queries, splitting, payments and checkout so that they always agree on totals
+
+use std::collections::HashMap;
+use chrono::{DateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+use derive_more::{Display, Error};
+use log::warn;
+use tokio_postgres::types::ToSql;
+use crate::server::database::pool::{GenericRow, GenericTransaction, WrappedRow};
+use crate::server::model::pricing::{Adjustment, AdjustmentKind, Comp, DiscountKind, DiscountRule, DiscountScope, PricedBill, PricedItem};
+
+/// A bill item to be priced
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Line {
+    pub bill_item_id: i64,
+    pub menu_item_id: i32,
+    pub category: String,
+    /// menu price with the deltas of chosen modifiers
+    pub price: i64,
+    /// time of day in the restaurant when the item was ordered, time windowed rules are evaluated against it
+    pub ordered_at: NaiveTime,
+}
+
+#[derive(Debug, Display, Error, PartialEq)]
+pub(crate) enum PricingError {
+    #[display("discount value must be positive, and at most 100 for percentage")]
+    ValueOutOfRange,
+    #[display("bill level discounts cannot target a category or a menu item")]
+    TargetNotAllowed,
+    #[display("time window needs both start and end")]
+    IncompleteWindow,
+    #[display("comp amount must be positive, and is required for bill comps")]
+    BadCompAmount,
+}
+
+impl DiscountRule {
+    /// Check the rule is well-formed before persisting it
+    pub(crate) fn validate(&self) -> Result<(), PricingError> {
+        if self.value <= 0 || (self.kind == DiscountKind::Percentage && self.value > 100) {
+            return Err(PricingError::ValueOutOfRange);
+        }
+        if self.scope == DiscountScope::Bill && (self.category.is_some() || self.menu_item_id.is_some()) {
+            return Err(PricingError::TargetNotAllowed);
+        }
+        if self.starts_at.is_some() != self.ends_at.is_some() {
+            return Err(PricingError::IncompleteWindow);
+        }
+        Ok(())
+    }
+
+    /// Whether the time falls into the daily window of the rule, windows may wrap around midnight
+    fn is_active_at(&self, at: NaiveTime) -> bool {
+        match (self.starts_at, self.ends_at) {
+            (Some(starts_at), Some(ends_at)) if starts_at <= ends_at => starts_at <= at && at < ends_at,
+            (Some(starts_at), Some(ends_at)) => at >= starts_at || at < ends_at,
+            _ => true,
+        }
+    }
+
+    fn matches(&self, line: &Line) -> bool {
+        self.scope == DiscountScope::Item
+            && self.category.as_ref().is_none_or(|category| *category == line.category)
+            && self.menu_item_id.is_none_or(|id| id == line.menu_item_id)
+            && self.is_active_at(line.ordered_at)
+    }
+
+    /// Discount off the amount, never more than the amount itself
+    fn discount(&self, amount: i64) -> i64 {
+        match self.kind {
+            DiscountKind::Percentage => amount * self.value / 100,
+            DiscountKind::Fixed => self.value,
+        }.clamp(0, amount.max(0))
+    }
+}
+
+impl Comp {
+    /// Check the comp is well-formed before persisting it
+    pub fn validate(&self) -> Result<(), PricingError> {
+        match (self.bill_item_id, self.amount) {
+            (None, None) => Err(PricingError::BadCompAmount),
+            (_, Some(amount)) if amount <= 0 => Err(PricingError::BadCompAmount),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Price a bill. For each item the best matching item rule is applied before its comps,
+/// then the best bill rule and bill comps are applied to what is left.
+pub(crate) fn price(lines: &[Line], rules: &[DiscountRule], comps: &[Comp], opened_at: NaiveTime) -> PricedBill {
+    price_applying(lines, rules, comps, opened_at).0
+}
+
+/// Price a bill as [`price`] does, with the ids of the rules which took effect
+fn price_applying(lines: &[Line], rules: &[DiscountRule], comps: &[Comp], opened_at: NaiveTime) -> (PricedBill, Vec<i32>) {
+    let mut applied = vec![];
+    let mut adjustments = vec![];
+    let mut nets = Vec::with_capacity(lines.len());
+    for line in lines {
+        let mut net = line.price;
+        if let Some((rule, discount)) = best_discount(rules.iter().filter(|rule| rule.matches(line)), net) {
+            net -= discount;
+            applied.push(rule.id);
+            adjustments.push(Adjustment {
+                kind: AdjustmentKind::Discount,
+                label: rule.name.clone(),
+                bill_item_id: Some(line.bill_item_id),
+                amount: -discount,
+            });
+        }
+        for comp in comps.iter().filter(|comp| comp.bill_item_id == Some(line.bill_item_id)) {
+            let amount = comp.amount.unwrap_or(net).min(net);
+            if amount > 0 {
+                net -= amount;
+                adjustments.push(Adjustment {
+                    kind: AdjustmentKind::Comp,
+                    label: comp.reason_code.clone(),
+                    bill_item_id: Some(line.bill_item_id),
+                    amount: -amount,
+                });
+            }
+        }
+        nets.push(net);
+    }
+
+    let items_total: i64 = nets.iter().sum();
+    let mut total = items_total;
+    let bill_rules = rules.iter().filter(|rule| rule.scope == DiscountScope::Bill && rule.is_active_at(opened_at));
+    if let Some((rule, discount)) = best_discount(bill_rules, total) {
+        total -= discount;
+        applied.push(rule.id);
+        adjustments.push(Adjustment {
+            kind: AdjustmentKind::Discount,
+            label: rule.name.clone(),
+            bill_item_id: None,
+            amount: -discount,
+        });
+    }
+    for comp in comps.iter().filter(|comp| comp.bill_item_id.is_none()) {
+        let amount = comp.amount.unwrap_or(0).min(total);
+        if amount > 0 {
+            total -= amount;
+            adjustments.push(Adjustment {
+                kind: AdjustmentKind::Comp,
+                label: comp.reason_code.clone(),
+                bill_item_id: None,
+                amount: -amount,
+            });
+        }
+    }
+
+    let shares = allocate(items_total - total, &nets);
+    applied.sort_unstable();
+    applied.dedup();
+    let priced = PricedBill {
+        subtotal: lines.iter().map(|line| line.price).sum(),
+        adjustments,
+        total,
+        items: lines.iter().zip(nets).zip(shares)
+            .map(|((line, net), share)| PricedItem {
+                bill_item_id: line.bill_item_id,
+                gross: line.price,
+                net: net - share,
+            })
+            .collect(),
+    };
+    (priced, applied)
+}
+
+/// Pick the rule giving the largest discount off the amount, the earlier rule wins a tie
+fn best_discount<'a>(rules: impl Iterator<Item = &'a DiscountRule>, amount: i64) -> Option<(&'a DiscountRule, i64)> {
+    rules
+        .map(|rule| (rule, rule.discount(amount)))
+        .filter(|(_, discount)| *discount > 0)
+        .fold(None, |best, (rule, discount)| match best {
+            Some((_, best_discount)) if best_discount >= discount => best,
+            _ => Some((rule, discount)),
+        })
+}
+
+/// Split amount proportionally to weights, the remainder goes to the largest fractions
+fn allocate(amount: i64, weights: &[i64]) -> Vec<i64> {
+    let total: i64 = weights.iter().sum();
+    if total <= 0 {
+        return vec![0; weights.len()];
+    }
+    let exact = weights.iter().map(|w| *w as i128 * amount as i128).collect::<Vec<_>>();
+    let mut shares = exact.iter().map(|e| (e / total as i128) as i64).collect::<Vec<_>>();
+    let mut remainder = amount - shares.iter().sum::<i64>();
+    let mut order = (0..weights.len()).collect::<Vec<_>>();
+    order.sort_by_key(|i| std::cmp::Reverse(exact[*i] % total as i128));
+    for i in order {
+        if remainder == 0 {
+            break;
+        }
+        shares[i] += 1;
+        remainder -= 1;
+    }
+    shares
+}
+
+fn local_time(at: DateTime<Utc>, time_zone: Tz) -> NaiveTime {
+    at.with_timezone(&time_zone).time()
+}
+
+/// Active discount rules, in the order their ties are broken
+pub(crate) const ACTIVE_RULES_QUERY: &str = r#"
+    SELECT id, name, kind, scope, value, category, menu_item_id, starts_at, ends_at
+    FROM discount_rule
+    WHERE active
+    ORDER BY id
+"#;
+
+/// Discount rule of a row of [`ACTIVE_RULES_QUERY`], rules of unknown kinds or scopes are skipped
+pub(crate) fn rule_from_row<R: GenericRow>(row: &R) -> Option<DiscountRule> {
+    let (kind, scope): (String, String) = (row.get("kind"), row.get("scope"));
+    match (kind.parse(), scope.parse()) {
+        (Ok(kind), Ok(scope)) => Some(DiscountRule {
+            id: row.get("id"),
+            name: row.get("name"),
+            kind,
+            scope,
+            value: row.get("value"),
+            category: row.get("category"),
+            menu_item_id: row.get("menu_item_id"),
+            starts_at: row.get("starts_at"),
+            ends_at: row.get("ends_at"),
+        }),
+        _ => {
+            warn!("skip malformed discount rule {}", row.get::<&str, i32>("id"));
+            None
+        }
+    }
+}
+
+/// Load everything needed to price a bill within a transaction, and price it
+pub(crate) async fn load<R, T>(txn: &T, bill_id: i64, time_zone: Tz) -> Result<PricedBill, tokio_postgres::Error>
+where
+    R: GenericRow,
+    WrappedRow<R>: GenericRow,
+    T: GenericTransaction<R>,
+{
+    let priced = load_all(txn, &[bill_id], time_zone).await?.remove(&bill_id);
+    Ok(priced.unwrap_or_else(|| price(&[], &[], &[], NaiveTime::default())))
+}
+
+/// Load everything needed to price bills within a transaction with a query per table, and price each of them
+pub(crate) async fn load_all<R, T>(txn: &T, bill_ids: &[i64], time_zone: Tz) -> Result<HashMap<i64, PricedBill>, tokio_postgres::Error>
+where
+    R: GenericRow,
+    WrappedRow<R>: GenericRow,
+    T: GenericTransaction<R>,
+{
+    Ok(load_inputs(txn, bill_ids, time_zone).await?
+        .into_iter()
+        .map(|(bill_id, inputs)| (bill_id, price(&inputs.lines, &inputs.rules, &inputs.comps, inputs.opened_at)))
+        .collect())
+}
+
+/// Price an open bill and freeze the discounts applied to it, so that later rule changes do not reprice it.
+/// Bills are frozen once split or checked out, freezing a frozen bill again changes nothing.
+pub(crate) async fn freeze<R, T>(txn: &T, bill_id: i64, time_zone: Tz) -> Result<PricedBill, tokio_postgres::Error>
+where
+    R: GenericRow,
+    WrappedRow<R>: GenericRow,
+    T: GenericTransaction<R>,
+{
+    let Some(inputs) = load_inputs(txn, &[bill_id], time_zone).await?.remove(&bill_id) else {
+        return Ok(price(&[], &[], &[], NaiveTime::default()));
+    };
+    let (priced, applied) = price_applying(&inputs.lines, &inputs.rules, &inputs.comps, inputs.opened_at);
+    if inputs.frozen {
+        return Ok(priced);
+    }
+    for rule in inputs.rules.iter().filter(|rule| applied.contains(&rule.id)) {
+        txn.execute(r#"
+            INSERT INTO bill_discount(bill_id, rule_id, name, kind, scope, value, category, menu_item_id, starts_at, ends_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        "#, &[&bill_id as &(dyn ToSql + Sync), &rule.id, &rule.name, &rule.kind.to_string(), &rule.scope.to_string(), &rule.value,
+            &rule.category, &rule.menu_item_id, &rule.starts_at, &rule.ends_at]).await?;
+    }
+    txn.execute("UPDATE bill SET discounts_frozen_at = CURRENT_TIMESTAMP WHERE id = $1", &[&bill_id as &(dyn ToSql + Sync)]).await?;
+    Ok(priced)
+}
+
+/// What a bill is priced from, frozen bills keep the rules applied when they were frozen
+struct Inputs {
+    lines: Vec<Line>,
+    rules: Vec<DiscountRule>,
+    comps: Vec<Comp>,
+    opened_at: NaiveTime,
+    frozen: bool,
+}
+
+/// Load what bills are priced from with a query per table, bills which do not exist are left out
+async fn load_inputs<R, T>(txn: &T, bill_ids: &[i64], time_zone: Tz) -> Result<HashMap<i64, Inputs>, tokio_postgres::Error>
+where
+    R: GenericRow,
+    WrappedRow<R>: GenericRow,
+    T: GenericTransaction<R>,
+{
+    let params: &[&(dyn ToSql + Sync)] = &[&bill_ids];
+    let mut bills = txn.query("SELECT id, created_at, discounts_frozen_at FROM bill WHERE id = ANY($1)", params).await?
+        .iter()
+        .map(|row| (row.get::<&str, i64>("id"), Inputs {
+            lines: vec![],
+            rules: vec![],
+            comps: vec![],
+            opened_at: local_time(row.get("created_at"), time_zone),
+            frozen: row.get::<&str, Option<DateTime<Utc>>>("discounts_frozen_at").is_some(),
+        }))
+        .collect::<HashMap<_, _>>();
+    for row in txn.query(r#"
+        SELECT bi.id, bi.bill_id, bi.menu_item_id, mi.category, bi.created_at,
+            bi.unit_price + COALESCE((SELECT SUM(m.price_delta) FROM bill_item_modifier m WHERE m.bill_item_id = bi.id), 0)::bigint AS price
+        FROM bill_item bi
+        JOIN menu_item mi
+        ON bi.menu_item_id = mi.id
+        WHERE bi.bill_id = ANY($1) AND bi.state IS DISTINCT FROM 'deleted'
+        ORDER BY bi.id
+    "#, params).await?.iter() {
+        if let Some(bill) = bills.get_mut(&row.get("bill_id")) {
+            bill.lines.push(Line {
+                bill_item_id: row.get("id"),
+                menu_item_id: row.get("menu_item_id"),
+                category: row.get("category"),
+                price: row.get("price"),
+                ordered_at: local_time(row.get("created_at"), time_zone),
+            });
+        }
+    }
+    if bills.values().any(|bill| !bill.frozen) {
+        let rules = txn.query(ACTIVE_RULES_QUERY, &[]).await?
+            .iter()
+            .filter_map(rule_from_row)
+            .collect::<Vec<_>>();
+        for bill in bills.values_mut().filter(|bill| !bill.frozen) {
+            bill.rules = rules.clone();
+        }
+    }
+    for row in txn.query(r#"
+        SELECT bill_id, rule_id AS id, name, kind, scope, value, category, menu_item_id, starts_at, ends_at
+        FROM bill_discount
+        WHERE bill_id = ANY($1)
+        ORDER BY rule_id
+    "#, params).await?.iter() {
+        if let (Some(bill), Some(rule)) = (bills.get_mut(&row.get("bill_id")), rule_from_row(row)) {
+            bill.rules.push(rule);
+        }
+    }
+    for row in txn.query(r#"
+        SELECT bill_id, bill_item_id, reason_code, amount
+        FROM bill_comp
+        WHERE bill_id = ANY($1)
+        ORDER BY id
+    "#, params).await?.iter() {
+        if let Some(bill) = bills.get_mut(&row.get("bill_id")) {
+            bill.comps.push(Comp {
+                bill_item_id: row.get("bill_item_id"),
+                reason_code: row.get("reason_code"),
+                amount: row.get("amount"),
+            });
+        }
+    }
+    Ok(bills)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(h: u32, m: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, 0).unwrap()
+    }
+
+    fn line(bill_item_id: i64, category: &str, price: i64) -> Line {
+        Line {
+            bill_item_id,
+            menu_item_id: bill_item_id as i32,
+            category: category.to_string(),
+            price,
+            ordered_at: time(18, 0),
+        }
+    }
+
+    fn rule(name: &str, kind: DiscountKind, scope: DiscountScope, value: i64) -> DiscountRule {
+        DiscountRule {
+            id: 0,
+            name: name.to_string(),
+            kind,
+            scope,
+            value,
+            category: None,
+            menu_item_id: None,
+            starts_at: None,
+            ends_at: None,
+        }
+    }
+
+    #[test]
+    fn test_price_without_adjustments() {
+        let priced = price(&[line(1, "A", 250), line(2, "B", 80)], &[], &[], time(18, 0));
+        assert_eq!(priced.subtotal, 330);
+        assert_eq!(priced.total, 330);
+        assert!(priced.adjustments.is_empty());
+        assert_eq!(priced.items, vec![
+            PricedItem { bill_item_id: 1, gross: 250, net: 250 },
+            PricedItem { bill_item_id: 2, gross: 80, net: 80 },
+        ]);
+    }
+
+    #[test]
+    fn test_price_with_happy_hour_by_category() {
+        let happy_hour = DiscountRule {
+            category: Some("B".to_string()),
+            starts_at: Some(time(17, 0)),
+            ends_at: Some(time(19, 0)),
+            ..rule("happy hour", DiscountKind::Percentage, DiscountScope::Item, 50)
+        };
+        let late = Line { ordered_at: time(19, 30), ..line(3, "B", 80) };
+        let priced = price(&[line(1, "A", 250), line(2, "B", 80), late], &[happy_hour], &[], time(18, 0));
+        assert_eq!(priced.total, 370);
+        assert_eq!(priced.adjustments, vec![Adjustment {
+            kind: AdjustmentKind::Discount,
+            label: "happy hour".to_string(),
+            bill_item_id: Some(2),
+            amount: -40,
+        }]);
+    }
+
+    #[test]
+    fn test_price_picks_best_rule() {
+        let rules = [
+            rule("10 off", DiscountKind::Fixed, DiscountScope::Item, 10),
+            rule("20%", DiscountKind::Percentage, DiscountScope::Item, 20),
+        ];
+        let priced = price(&[line(1, "A", 250), line(2, "B", 30)], &rules, &[], time(18, 0));
+        assert_eq!(priced.total, 200 + 20);
+        assert_eq!(priced.adjustments.iter().map(|a| a.label.as_str()).collect::<Vec<_>>(), vec!["20%", "10 off"]);
+    }
+
+    #[test]
+    fn test_price_applying_lists_rules_in_effect() {
+        let rules = [
+            DiscountRule { id: 1, ..rule("10 off", DiscountKind::Fixed, DiscountScope::Item, 10) },
+            DiscountRule { id: 2, ..rule("20%", DiscountKind::Percentage, DiscountScope::Item, 20) },
+            DiscountRule { id: 3, ..rule("5%", DiscountKind::Percentage, DiscountScope::Item, 5) },
+            DiscountRule { id: 4, ..rule("welcome", DiscountKind::Fixed, DiscountScope::Bill, 15) },
+        ];
+        let lines = [line(1, "A", 250), line(2, "B", 30), line(3, "B", 40)];
+        let (priced, applied) = price_applying(&lines, &rules, &[], time(18, 0));
+        assert_eq!(applied, vec![1, 2, 4]);
+        // the rules in effect alone price the bill the same
+        let frozen = rules.iter().filter(|rule| applied.contains(&rule.id)).cloned().collect::<Vec<_>>();
+        assert_eq!(price(&lines, &frozen, &[], time(18, 0)), priced);
+    }
+
+    #[test]
+    fn test_price_with_comps_and_bill_discount() {
+        let rules = [rule("welcome", DiscountKind::Percentage, DiscountScope::Bill, 10)];
+        let comps = [
+            Comp { bill_item_id: Some(2), reason_code: "long_wait".to_string(), amount: None },
+            Comp { bill_item_id: None, reason_code: "goodwill".to_string(), amount: Some(5) },
+        ];
+        let priced = price(&[line(1, "A", 250), line(2, "B", 80), line(3, "C", 100)], &rules, &comps, time(12, 0));
+        // 350 after the item comp, 315 after 10% off, 310 after the bill comp
+        assert_eq!(priced.subtotal, 430);
+        assert_eq!(priced.total, 310);
+        assert_eq!(priced.adjustments.len(), 3);
+        assert_eq!(priced.adjustments.iter().map(|a| a.amount).sum::<i64>(), -120);
+        assert_eq!(priced.items.iter().map(|i| i.net).sum::<i64>(), 310);
+        assert_eq!(priced.items[1].net, 0);
+    }
+
+    #[test]
+    fn test_window_wraps_midnight() {
+        let late_night = DiscountRule {
+            starts_at: Some(time(22, 0)),
+            ends_at: Some(time(2, 0)),
+            ..rule("late night", DiscountKind::Fixed, DiscountScope::Bill, 10)
+        };
+        assert!(late_night.is_active_at(time(23, 0)));
+        assert!(late_night.is_active_at(time(1, 0)));
+        assert!(!late_night.is_active_at(time(2, 0)));
+        assert!(!late_night.is_active_at(time(12, 0)));
+    }
+
+    #[test]
+    fn test_local_time() {
+        // 10:30 UTC is happy hour in Taipei, not on a host in UTC
+        let at = "2024-05-01T10:30:00Z".parse::<DateTime<Utc>>().unwrap();
+        assert_eq!(local_time(at, Tz::UTC), time(10, 30));
+        assert_eq!(local_time(at, chrono_tz::Asia::Taipei), time(18, 30));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(rule("ok", DiscountKind::Percentage, DiscountScope::Item, 100).validate().is_ok());
+        assert_eq!(rule("x", DiscountKind::Percentage, DiscountScope::Item, 101).validate(), Err(PricingError::ValueOutOfRange));
+        assert_eq!(rule("x", DiscountKind::Fixed, DiscountScope::Bill, 0).validate(), Err(PricingError::ValueOutOfRange));
+        let targeted = DiscountRule { category: Some("A".to_string()), ..rule("x", DiscountKind::Fixed, DiscountScope::Bill, 1) };
+        assert_eq!(targeted.validate(), Err(PricingError::TargetNotAllowed));
+        let half_window = DiscountRule { starts_at: Some(time(1, 0)), ..rule("x", DiscountKind::Fixed, DiscountScope::Item, 1) };
+        assert_eq!(half_window.validate(), Err(PricingError::IncompleteWindow));
+
+        assert!(Comp { bill_item_id: Some(1), reason_code: "quality".to_string(), amount: None }.validate().is_ok());
+        assert!(Comp { bill_item_id: None, reason_code: "quality".to_string(), amount: None }.validate().is_err());
+        assert!(Comp { bill_item_id: None, reason_code: "quality".to_string(), amount: Some(0) }.validate().is_err());
+    }
+
+    #[test]
+    fn test_allocate() {
+        assert_eq!(allocate(10, &[50, 50]), vec![5, 5]);
+        assert_eq!(allocate(10, &[1, 1, 1]).iter().sum::<i64>(), 10);
+        assert_eq!(allocate(0, &[0, 0]), vec![0, 0]);
+        assert_eq!(allocate(7, &[100, 0]), vec![7, 0]);
+    }
+}
@@ -0,0 +1,191 @@
+//! Rendering of receipts and kitchen tickets from templates, built-in templates can be overridden per restaurant
+
+use std::fs;
+use anyhow::{Context, Error};
+use log::info;
+use minijinja::Environment;
+use serde::Serialize;
+use crate::server::model::config::ReceiptConfig;
+
+/// Templates known to the renderer, each can be overridden by a file of the same name in the template directory
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Template {
+    ReceiptText,
+    ReceiptHtml,
+    KitchenTicket,
+}
+
+impl Template {
+    const ALL: [Template; 3] = [Template::ReceiptText, Template::ReceiptHtml, Template::KitchenTicket];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Template::ReceiptText => "receipt.txt",
+            Template::ReceiptHtml => "receipt.html",
+            Template::KitchenTicket => "ticket.txt",
+        }
+    }
+
+    fn builtin(&self) -> &'static str {
+        match self {
+            Template::ReceiptText => include_str!("templates/receipt.txt"),
+            Template::ReceiptHtml => include_str!("templates/receipt.html"),
+            Template::KitchenTicket => include_str!("templates/ticket.txt"),
+        }
+    }
+}
+
+/// Template renderer, text templates get `row`, `center` and `rule` helpers for fixed width layout,
+/// and every template gets a `money` filter
+pub(crate) struct Renderer {
+    env: Environment<'static>,
+    config: ReceiptConfig,
+}
+
+impl Renderer {
+    /// Create a renderer, templates found in the configured directory replace the built-in ones
+    pub fn new(config: &ReceiptConfig) -> Result<Self, Error> {
+        let width = config.width;
+        let mut env = Environment::new();
+        env.add_filter("money", money);
+        env.add_function("row", move |left: String, right: String| row(&left, &right, width));
+        env.add_function("center", move |text: String| format!("{:^width$}", truncate(&text, width)).trim_end().to_string());
+        env.add_function("rule", move || "-".repeat(width));
+        for template in Template::ALL {
+            let source = match &config.template_dir {
+                Some(dir) if dir.join(template.name()).is_file() => {
+                    info!("loading template {} from {}", template.name(), dir.display());
+                    fs::read_to_string(dir.join(template.name()))
+                        .with_context(|| format!("failed to read template {}", template.name()))?
+                },
+                _ => template.builtin().to_string(),
+            };
+            env.add_template_owned(template.name(), source)
+                .with_context(|| format!("failed to parse template {}", template.name()))?;
+        }
+        Ok(Self { env, config: config.clone() })
+    }
+
+    /// Get the configs the renderer is created with
+    pub fn config(&self) -> &ReceiptConfig {
+        &self.config
+    }
+
+    /// Render a template with the given context
+    pub fn render<S: Serialize>(&self, template: Template, context: S) -> Result<String, Error> {
+        self.env
+            .get_template(template.name())?
+            .render(context)
+            .with_context(|| format!("failed to render template {}", template.name()))
+    }
+}
+
+/// Format an amount in the smallest currency unit with two decimals
+fn money(amount: i64) -> String {
+    let sign = if amount < 0 { "-" } else { "" };
+    format!("{}{}.{:02}", sign, amount.abs() / 100, amount.abs() % 100)
+}
+
+/// Keep at most width characters
+fn truncate(text: &str, width: usize) -> String {
+    text.chars().take(width).collect()
+}
+
+/// Left and right aligned texts in one line, the left text is truncated when they do not fit
+fn row(left: &str, right: &str, width: usize) -> String {
+    let right = truncate(right, width);
+    let room = width.saturating_sub(right.chars().count() + 1);
+    let left = truncate(left, room);
+    let padding = width - left.chars().count() - right.chars().count();
+    format!("{}{}{}", left, " ".repeat(padding), right)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use crate::server::model::receipt::{KitchenTicket, Receipt, ReceiptLine, TicketItem};
+    use super::*;
+
+    fn receipt() -> Receipt {
+        Receipt {
+            restaurant: "Eureka <Diner>".to_string(),
+            bill_id: 7,
+            table_id: 3,
+            opened_at: "2024-11-20 18:01".to_string(),
+            checkout_at: None,
+            printed_at: "2024-11-20 19:30".to_string(),
+            items: vec![
+                ReceiptLine { label: "Fried chicken with an extremely long name".to_string(), amount: 250 },
+                ReceiptLine { label: "Juice".to_string(), amount: 80 },
+            ],
+            subtotal: 330,
+            adjustments: vec![ReceiptLine { label: "happy hour (Juice)".to_string(), amount: -40 }],
+            total: 290,
+            tax_rate: 5,
+            tax: 14,
+            payments: vec![ReceiptLine { label: "cash".to_string(), amount: 290 }],
+            paid: 290,
+            tips: 0,
+            outstanding: 0,
+        }
+    }
+
+    #[test]
+    fn test_helpers() {
+        assert_eq!(money(12345), "123.45");
+        assert_eq!(money(-40), "-0.40");
+        assert_eq!(money(0), "0.00");
+        assert_eq!(row("Juice", "0.80", 12), "Juice   0.80");
+        assert_eq!(row("Fried chicken", "2.50", 12), "Fried c 2.50");
+    }
+
+    #[test]
+    fn test_render_text_receipt() {
+        let renderer = Renderer::new(&ReceiptConfig::default()).unwrap();
+        let text = renderer.render(Template::ReceiptText, receipt()).unwrap();
+        assert!(text.lines().all(|line| line.chars().count() <= ReceiptConfig::default().width));
+        assert!(text.contains("Eureka <Diner>"));
+        assert!(text.contains("happy hour (Juice)"));
+        assert!(text.contains("incl. tax 5%"));
+        assert!(!text.contains("Closed"));
+    }
+
+    #[test]
+    fn test_render_html_receipt() {
+        let renderer = Renderer::new(&ReceiptConfig::default()).unwrap();
+        let html = renderer.render(Template::ReceiptHtml, receipt()).unwrap();
+        assert!(html.contains("Eureka &lt;Diner&gt;"));
+        assert!(html.contains("-0.40"));
+    }
+
+    #[test]
+    fn test_render_ticket() {
+        let renderer = Renderer::new(&ReceiptConfig { width: 32, ..ReceiptConfig::default() }).unwrap();
+        let ticket = KitchenTicket {
+            bill_id: 7,
+            table_id: 3,
+            printed_at: "18:05".to_string(),
+            items: vec![TicketItem {
+                id: 1,
+                name: "Ramen".to_string(),
+                modifiers: vec!["Large".to_string(), "Egg".to_string()],
+                note: Some("no onions".to_string()),
+                ordered_at: "18:04".to_string(),
+            }],
+        };
+        let text = renderer.render(Template::KitchenTicket, ticket).unwrap();
+        assert!(text.lines().all(|line| line.chars().count() <= 32));
+        assert!(text.contains("[ ] Ramen"));
+        assert!(text.contains("    + Large"));
+        assert!(text.contains("    ! no onions"));
+    }
+
+    #[test]
+    fn test_missing_template_dir_falls_back_to_builtin() {
+        let config = ReceiptConfig {
+            template_dir: Some(PathBuf::from("/nonexistent")),
+            ..ReceiptConfig::default()
+        };
+        assert!(Renderer::new(&config).is_ok());
+    }
+}
@@ -0,0 +1,63 @@
+//! Shaping of report rows into JSON or CSV responses
+
+use actix_web::HttpResponse;
+use anyhow::Error;
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Serialize;
+use crate::server::model::report::{ReportFormat, ReportParams, ReportResponse};
+
+/// Range reported when the request does not give one
+const DEFAULT_RANGE_DAYS: i64 = 7;
+/// Rows of top lists when the request does not give a limit
+pub(crate) const DEFAULT_LIMIT: u16 = 10;
+
+impl ReportParams {
+    /// The requested range, defaulting to the last days until now
+    pub fn range(&self, now: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+        let to = self.to.unwrap_or(now);
+        (self.from.unwrap_or(to - TimeDelta::days(DEFAULT_RANGE_DAYS)), to)
+    }
+}
+
+/// Write rows as CSV with a header line
+pub(crate) fn to_csv<T: Serialize>(rows: &[T]) -> Result<String, Error> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+/// Respond with rows in the requested format
+pub(crate) fn respond<T: Serialize>(rows: Vec<T>, format: ReportFormat) -> Result<HttpResponse, Error> {
+    Ok(match format {
+        ReportFormat::Json => HttpResponse::Ok().json(ReportResponse { rows }),
+        ReportFormat::Csv => HttpResponse::Ok().content_type("text/csv; charset=utf-8").body(to_csv(&rows)?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use crate::server::model::report::{ReportParams, TurnoverRow};
+    use super::*;
+
+    #[test]
+    fn test_to_csv() {
+        let rows = vec![
+            TurnoverRow { table_id: Some(1), bills: 2, average_minutes: 45.5 },
+            TurnoverRow { table_id: None, bills: 2, average_minutes: 45.5 },
+        ];
+        assert_eq!(to_csv(&rows).unwrap(), "table_id,bills,average_minutes\n1,2,45.5\n,2,45.5\n");
+    }
+
+    #[test]
+    fn test_range() {
+        let now = Utc.with_ymd_and_hms(2024, 11, 20, 12, 0, 0).unwrap();
+        let params = ReportParams { from: None, to: None, format: None, granularity: None, limit: None };
+        assert_eq!(params.range(now), (now - TimeDelta::days(DEFAULT_RANGE_DAYS), now));
+        let from = Utc.with_ymd_and_hms(2024, 11, 1, 0, 0, 0).unwrap();
+        let params = ReportParams { from: Some(from), ..params };
+        assert_eq!(params.range(now), (from, now));
+    }
+}
@@ -0,0 +1,246 @@
+//! Table assignment for reservations and wait time estimates for walk-in parties
+
+use chrono::{DateTime, TimeDelta, Utc};
+use derive_more::{Display, Error};
+use crate::server::database::pool::{GenericRow, GenericTransaction, WrappedRow};
+use crate::server::model::reservation::{PostReservationRequest, PostWaitlistRequest, RESERVATION_STATE_BOOKED};
+
+/// A reserved table is held for this long before the slot, walk-ins cannot claim it meanwhile
+pub(crate) const HOLD_MINUTES: i64 = 30;
+/// How long a walk-in party typically stays, used for wait time estimates
+pub(crate) const DINING_MINUTES: i64 = 60;
+
+const MIN_DURATION_MINUTES: i64 = 15;
+const MAX_DURATION_MINUTES: i64 = 8 * 60;
+const MAX_NAME_LEN: usize = 64;
+const MAX_PHONE_LEN: usize = 32;
+
+#[derive(Debug, Display, Error, PartialEq)]
+pub(crate) enum ReservationError {
+    #[display("party size must be positive")]
+    BadPartySize,
+    #[display("duration must be between {} and {} minutes", MIN_DURATION_MINUTES, MAX_DURATION_MINUTES)]
+    BadDuration,
+    #[display("contact name must be 1 to {} characters", MAX_NAME_LEN)]
+    BadName,
+    #[display("contact phone must be at most {} characters", MAX_PHONE_LEN)]
+    BadPhone,
+    #[display("reservations must start in the future")]
+    InThePast,
+    #[display("no table can seat the party at that time")]
+    NoTableAvailable,
+}
+
+/// A time range, the end is exclusive
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Slot {
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+}
+
+impl Slot {
+    pub fn new(starts_at: DateTime<Utc>, minutes: i64) -> Self {
+        Self { starts_at, ends_at: starts_at + TimeDelta::minutes(minutes) }
+    }
+
+    fn overlaps(&self, other: &Slot) -> bool {
+        self.starts_at < other.ends_at && other.starts_at < self.ends_at
+    }
+
+    /// The slot extended by the hold before it
+    fn held(&self) -> Slot {
+        Slot { starts_at: self.starts_at - TimeDelta::minutes(HOLD_MINUTES), ends_at: self.ends_at }
+    }
+}
+
+/// A table with its seats, when it is expected to be free, and its upcoming bookings
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TableState {
+    pub id: i16,
+    pub capacity: i16,
+    pub free_at: DateTime<Utc>,
+    pub booked: Vec<Slot>,
+}
+
+impl PostReservationRequest {
+    /// Check the request is well-formed before looking for a table
+    pub(crate) fn validate(&self, now: DateTime<Utc>) -> Result<Slot, ReservationError> {
+        if self.party_size <= 0 {
+            return Err(ReservationError::BadPartySize);
+        }
+        if !(MIN_DURATION_MINUTES..=MAX_DURATION_MINUTES).contains(&self.duration_minutes) {
+            return Err(ReservationError::BadDuration);
+        }
+        check_contact(&self.name, self.phone.as_deref())?;
+        if self.starts_at <= now {
+            return Err(ReservationError::InThePast);
+        }
+        Ok(Slot::new(self.starts_at, self.duration_minutes))
+    }
+}
+
+impl PostWaitlistRequest {
+    /// Check the party is well-formed before adding it
+    pub(crate) fn validate(&self) -> Result<(), ReservationError> {
+        if self.party_size <= 0 {
+            return Err(ReservationError::BadPartySize);
+        }
+        check_contact(&self.name, self.phone.as_deref())
+    }
+}
+
+/// Check the contact fits the columns it is stored in
+fn check_contact(name: &str, phone: Option<&str>) -> Result<(), ReservationError> {
+    if name.trim().is_empty() || name.chars().count() > MAX_NAME_LEN {
+        return Err(ReservationError::BadName);
+    }
+    if phone.is_some_and(|phone| phone.chars().count() > MAX_PHONE_LEN) {
+        return Err(ReservationError::BadPhone);
+    }
+    Ok(())
+}
+
+/// Pick the smallest table seating the party without overlapping other bookings, the lower id wins a tie
+pub(crate) fn pick_table(tables: &[TableState], party_size: i16, slot: &Slot) -> Result<i16, ReservationError> {
+    tables.iter()
+        .filter(|table| table.capacity >= party_size)
+        .filter(|table| table.booked.iter().all(|booked| !booked.overlaps(slot)))
+        .min_by_key(|table| (table.capacity, table.id))
+        .map(|table| table.id)
+        .ok_or(ReservationError::NoTableAvailable)
+}
+
+/// Estimate in minutes how long each waiting party, first come first, waits for a table.
+/// Each party takes the table free the earliest, avoiding the hold of upcoming bookings.
+pub(crate) fn estimate_waits(tables: &[TableState], party_sizes: &[i16], now: DateTime<Utc>) -> Vec<Option<i64>> {
+    let mut tables = tables.to_vec();
+    party_sizes.iter().map(|party_size| {
+        let (table, seated_at) = tables.iter_mut()
+            .filter(|table| table.capacity >= *party_size)
+            .map(|table| {
+                let seated_at = earliest_seating(table, now);
+                (table, seated_at)
+            })
+            .min_by_key(|(table, seated_at)| (*seated_at, table.capacity, table.id))?;
+        table.free_at = seated_at + TimeDelta::minutes(DINING_MINUTES);
+        Some((seated_at - now).num_minutes())
+    }).collect()
+}
+
+/// Earliest time a walk-in party can be seated at the table for a typical stay
+fn earliest_seating(table: &TableState, now: DateTime<Utc>) -> DateTime<Utc> {
+    let mut booked = table.booked.iter().map(Slot::held).collect::<Vec<_>>();
+    booked.sort_by_key(|slot| slot.starts_at);
+    let mut at = table.free_at.max(now);
+    for slot in booked {
+        if Slot::new(at, DINING_MINUTES).overlaps(&slot) {
+            at = at.max(slot.ends_at);
+        }
+    }
+    at
+}
+
+/// Load tables seating at least the party size with their bookings ending after a point of time
+pub(crate) async fn load_tables<R, T>(txn: &T, party_size: i16, after: DateTime<Utc>, now: DateTime<Utc>) -> Result<Vec<TableState>, tokio_postgres::Error>
+where
+    R: GenericRow,
+    WrappedRow<R>: GenericRow,
+    T: GenericTransaction<R>,
+{
+    let rows = txn.query(r#"
+        SELECT t.id, t.capacity, b.created_at, r.starts_at, r.ends_at
+        FROM "table" t
+        LEFT JOIN bill b
+        ON t.bill_id = b.id
+        LEFT JOIN reservation r
+        ON r.table_id = t.id AND r.state = $2 AND r.ends_at > $3
+        WHERE t.capacity >= $1
+        ORDER BY t.id, r.starts_at
+    "#, &[&party_size, &RESERVATION_STATE_BOOKED, &after]).await?;
+    let mut tables: Vec<TableState> = vec![];
+    for row in rows.iter() {
+        let id: i16 = row.get("id");
+        if tables.last().is_none_or(|table| table.id != id) {
+            // an occupied table is expected to be free after a typical stay, or soon when overstayed
+            let free_at = row.get::<&str, Option<DateTime<Utc>>>("created_at")
+                .map(|created_at| (created_at + TimeDelta::minutes(DINING_MINUTES)).max(now))
+                .unwrap_or(now);
+            tables.push(TableState { id, capacity: row.get("capacity"), free_at, booked: vec![] });
+        }
+        if let (Some(starts_at), Some(ends_at)) = (row.get::<&str, Option<DateTime<Utc>>>("starts_at"), row.get::<&str, Option<DateTime<Utc>>>("ends_at")) {
+            tables.last_mut().unwrap().booked.push(Slot { starts_at, ends_at });
+        }
+    }
+    Ok(tables)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use super::*;
+
+    fn at(h: u32, m: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 11, 20, h, m, 0).unwrap()
+    }
+
+    fn table(id: i16, capacity: i16, free_at: DateTime<Utc>, booked: Vec<Slot>) -> TableState {
+        TableState { id, capacity, free_at, booked }
+    }
+
+    fn request(party_size: i16, starts_at: DateTime<Utc>, duration_minutes: i64) -> PostReservationRequest {
+        PostReservationRequest {
+            party_size,
+            starts_at,
+            duration_minutes,
+            name: "Ada".to_string(),
+            phone: None,
+            table_id: None,
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let now = at(12, 0);
+        assert_eq!(request(2, at(19, 0), 90).validate(now), Ok(Slot::new(at(19, 0), 90)));
+        assert_eq!(request(0, at(19, 0), 90).validate(now), Err(ReservationError::BadPartySize));
+        assert_eq!(request(2, at(19, 0), 5).validate(now), Err(ReservationError::BadDuration));
+        assert_eq!(request(2, at(11, 0), 90).validate(now), Err(ReservationError::InThePast));
+        let nameless = PostReservationRequest { name: " ".to_string(), ..request(2, at(19, 0), 90) };
+        assert_eq!(nameless.validate(now), Err(ReservationError::BadName));
+        let long_phone = PostReservationRequest { phone: Some("0".repeat(33)), ..request(2, at(19, 0), 90) };
+        assert_eq!(long_phone.validate(now), Err(ReservationError::BadPhone));
+
+        let party = |name: &str, phone: Option<&str>| PostWaitlistRequest { party_size: 2, name: name.to_string(), phone: phone.map(str::to_string) };
+        assert_eq!(party("Ada", Some("0912345678")).validate(), Ok(()));
+        assert_eq!(PostWaitlistRequest { party_size: 0, ..party("Ada", None) }.validate(), Err(ReservationError::BadPartySize));
+        assert_eq!(party(&"A".repeat(65), None).validate(), Err(ReservationError::BadName));
+        assert_eq!(party("Ada", Some(&"0".repeat(33))).validate(), Err(ReservationError::BadPhone));
+    }
+
+    #[test]
+    fn test_pick_table() {
+        let now = at(12, 0);
+        let tables = vec![
+            table(1, 2, now, vec![Slot::new(at(19, 0), 90)]),
+            table(2, 2, now, vec![]),
+            table(5, 4, now, vec![]),
+        ];
+        assert_eq!(pick_table(&tables, 2, &Slot::new(at(19, 30), 60)), Ok(2));
+        assert_eq!(pick_table(&tables, 2, &Slot::new(at(20, 30), 60)), Ok(1));
+        assert_eq!(pick_table(&tables, 3, &Slot::new(at(19, 0), 60)), Ok(5));
+        assert_eq!(pick_table(&tables, 5, &Slot::new(at(19, 0), 60)), Err(ReservationError::NoTableAvailable));
+    }
+
+    #[test]
+    fn test_estimate_waits() {
+        let now = at(18, 0);
+        let tables = vec![
+            table(1, 2, now, vec![]),
+            table(2, 4, at(18, 20), vec![]),
+            // held from 18:30, a walk-in staying an hour only fits after the booking
+            table(3, 4, now, vec![Slot::new(at(19, 0), 90)]),
+        ];
+        let waits = estimate_waits(&tables, &[2, 4, 4, 2, 8], now);
+        assert_eq!(waits, vec![Some(0), Some(20), Some(80), Some(60), None]);
+    }
+}
@@ -0,0 +1,190 @@
+//! Retention of old rows, moved into the archive table or NDJSON files and then purged from the hot tables
+
+#[cfg(test)]
+use crate::server::database::pool::{DbClient, GenericRow, GenericTransaction};
+use anyhow::{anyhow, Context as _, Error};
+use chrono::{DateTime, Utc};
+use log::info;
+use tokio_postgres::types::ToSql;
+use crate::server::model::admin::{RetentionEntity, RetentionReport};
+use crate::server::model::config::{RetentionConfig, RetentionTarget};
+use crate::server::scheduler::Context;
+use crate::server::state::AppState;
+use crate::server::util::file::append_ndjson;
+
+impl RetentionEntity {
+    /// Rows of the entity older than the days given as $1, as `x`
+    fn due(self) -> &'static str {
+        match self {
+            RetentionEntity::Bills => "FROM bill x WHERE x.state = 'closed' AND x.checkout_at < CURRENT_TIMESTAMP - make_interval(days => $1)",
+            RetentionEntity::DeletedItems => "FROM bill_item x WHERE x.state = 'deleted' AND x.created_at < CURRENT_TIMESTAMP - make_interval(days => $1)",
+            RetentionEntity::JobRuns => "FROM job_run x WHERE x.finished_at < CURRENT_TIMESTAMP - make_interval(days => $1)",
+        }
+    }
+
+    /// Column a row of the entity is aged from
+    fn aged_at(self) -> &'static str {
+        match self {
+            RetentionEntity::Bills => "x.checkout_at",
+            RetentionEntity::DeletedItems => "x.created_at",
+            RetentionEntity::JobRuns => "x.finished_at",
+        }
+    }
+
+    /// JSON document of a row with every row depending on it, which the purge deletes by cascade
+    fn document(self) -> &'static str {
+        match self {
+            RetentionEntity::Bills => r#"
+                to_jsonb(x) || jsonb_build_object(
+                    'items', (SELECT COALESCE(jsonb_agg(to_jsonb(i) || jsonb_build_object(
+                        'modifiers', (SELECT COALESCE(jsonb_agg(to_jsonb(m)), '[]'::jsonb) FROM bill_item_modifier m WHERE m.bill_item_id = i.id)
+                    ) ORDER BY i.id), '[]'::jsonb) FROM bill_item i WHERE i.bill_id = x.id),
+                    'splits', (SELECT COALESCE(jsonb_agg(to_jsonb(s) || jsonb_build_object(
+                        'items', (SELECT COALESCE(jsonb_agg(si.bill_item_id), '[]'::jsonb) FROM bill_split_item si WHERE si.split_id = s.id)
+                    ) ORDER BY s.id), '[]'::jsonb) FROM bill_split s WHERE s.bill_id = x.id),
+                    'payments', (SELECT COALESCE(jsonb_agg(to_jsonb(p) ORDER BY p.id), '[]'::jsonb) FROM payment p WHERE p.bill_id = x.id),
+                    'comps', (SELECT COALESCE(jsonb_agg(to_jsonb(c) ORDER BY c.id), '[]'::jsonb) FROM bill_comp c WHERE c.bill_id = x.id),
+                    'discounts', (SELECT COALESCE(jsonb_agg(to_jsonb(d) ORDER BY d.rule_id), '[]'::jsonb) FROM bill_discount d WHERE d.bill_id = x.id),
+                    'alerts', (SELECT COALESCE(jsonb_agg(to_jsonb(a) ORDER BY a.id), '[]'::jsonb) FROM bill_alert a WHERE a.bill_id = x.id)
+                )
+            "#,
+            RetentionEntity::DeletedItems => r#"
+                to_jsonb(x) || jsonb_build_object(
+                    'modifiers', (SELECT COALESCE(jsonb_agg(to_jsonb(m)), '[]'::jsonb) FROM bill_item_modifier m WHERE m.bill_item_id = x.id)
+                )
+            "#,
+            RetentionEntity::JobRuns => "to_jsonb(x)",
+        }
+    }
+
+    fn table(self) -> &'static str {
+        match self {
+            RetentionEntity::Bills => "bill",
+            RetentionEntity::DeletedItems => "bill_item",
+            RetentionEntity::JobRuns => "job_run",
+        }
+    }
+}
+
+/// Entities with the days they are kept, forever when absent
+fn policies(config: &RetentionConfig) -> [(RetentionEntity, Option<u32>); 3] {
+    [
+        (RetentionEntity::Bills, config.bill_days),
+        (RetentionEntity::DeletedItems, config.deleted_item_days),
+        (RetentionEntity::JobRuns, config.job_run_days),
+    ]
+}
+
+/// Rows of each entity due for retention now, nothing is changed
+pub(crate) async fn report(state: &AppState, config: &RetentionConfig) -> Result<Vec<RetentionReport>, Error> {
+    let Some(conn) = state.get_db_read_pool().acquire(state.get_db_timeout_seconds()).await else {
+        return Err(anyhow!("no connection available"));
+    };
+    let client = conn.client.as_ref().ok_or(anyhow!("client is None"))?;
+    let mut reports = vec![];
+    for (entity, days) in policies(config) {
+        let mut report = RetentionReport { entity, days, due: 0, oldest_at: None };
+        if let Some(days) = days {
+            let params: &[&(dyn ToSql + Sync)] = &[&i32::try_from(days)?];
+            let query = format!("SELECT COUNT(*) AS due, MIN({}) AS oldest_at {}", entity.aged_at(), entity.due());
+            let rows = client.query(&query, params).await
+                .with_context(|| format!("failed to count {} due for retention", entity))?;
+            if let Some(row) = rows.first() {
+                report.due = row.get("due");
+                report.oldest_at = row.get::<&str, Option<DateTime<Utc>>>("oldest_at");
+            }
+        }
+        reports.push(report);
+    }
+    Ok(reports)
+}
+
+/// Move rows due for retention to the target and purge them, a batch at a time until none is left due or the shutdown starts.
+/// Returns the number of rows purged, none in a dry run which logs what is due instead.
+pub(crate) async fn purge(state: &AppState, config: &RetentionConfig, context: &Context) -> Result<u64, Error> {
+    if config.dry_run {
+        for report in report(state, config).await? {
+            if let Some(days) = report.days {
+                info!("dry run, {} {} are older than {} days, the oldest aged from {:?}", report.due, report.entity, days, report.oldest_at);
+            }
+        }
+        return Ok(0);
+    }
+    let mut purged = 0;
+    for (entity, days) in policies(config) {
+        let Some(days) = days else {
+            continue;
+        };
+        loop {
+            let batch = purge_batch(state, config, entity, days, context.batch_size).await
+                .with_context(|| format!("failed after purging {} rows", purged))?;
+            purged += batch;
+            if batch > 0 {
+                info!("moved {} {} to {} and purged them", batch, entity, config.target);
+            }
+            if batch < u64::from(context.batch_size) || context.cancel_token.is_cancelled() {
+                break;
+            }
+        }
+    }
+    Ok(purged)
+}
+
+/// Move up to a batch of due rows and purge them in one transaction.
+/// Rows written to a file are written again when the purge fails afterwards, readers tell them apart by id.
+async fn purge_batch(state: &AppState, config: &RetentionConfig, entity: RetentionEntity, days: u32, batch_size: u32) -> Result<u64, Error> {
+    let Some(mut conn) = state.get_db_write_pool().acquire(state.get_db_timeout_seconds()).await else {
+        return Err(anyhow!("no connection available"));
+    };
+    let client = conn.client.as_mut().ok_or(anyhow!("client is None"))?;
+    let txn = client.transaction().await?;
+    let params: &[&(dyn ToSql + Sync)] = &[&i32::try_from(days)?, &i64::from(batch_size)];
+    let query = format!("SELECT x.id, {} AS document {} ORDER BY x.id LIMIT $2 FOR UPDATE OF x SKIP LOCKED", entity.document(), entity.due());
+    let rows = txn.query(&query, params).await.with_context(|| format!("failed to query {} due for retention", entity))?;
+    if rows.is_empty() {
+        return Ok(0);
+    }
+    let ids = rows.iter().map(|row| row.get::<&str, i64>("id")).collect::<Vec<_>>();
+    let documents = rows.iter().map(|row| row.get::<&str, serde_json::Value>("document")).collect::<Vec<_>>();
+
+    match config.target {
+        RetentionTarget::Archive => {
+            let entity = entity.to_string();
+            let params: &[&(dyn ToSql + Sync)] = &[&entity, &ids, &documents];
+            txn.execute(r#"
+                INSERT INTO archive (entity, entity_id, document, archived_at)
+                SELECT $1, id, document, CURRENT_TIMESTAMP
+                FROM unnest($2::bigint[], $3::jsonb[]) AS moved(id, document)
+            "#, params).await.context("failed to archive")?;
+        },
+        RetentionTarget::Ndjson => {
+            let (dir, date) = (config.dir.clone(), Utc::now().date_naive());
+            let path = dir.join(format!("{}-{}.ndjson", entity, date));
+            tokio::task::spawn_blocking(move || append_ndjson(&path, &documents)).await??;
+        },
+    }
+    let params: &[&(dyn ToSql + Sync)] = &[&ids];
+    let purged = txn.execute(&format!("DELETE FROM {} WHERE id = ANY($1)", entity.table()), params).await
+        .with_context(|| format!("failed to purge {}", entity))?;
+    txn.commit().await?;
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_purge() {
+        let state = AppState::mock().await;
+        let config = RetentionConfig { bill_days: Some(365), job_run_days: Some(30), ..RetentionConfig::default() };
+        let context = Context { batch_size: 100, cancel_token: Default::default() };
+        assert_eq!(purge(&state, &config, &context).await.unwrap(), 0);
+        assert_eq!(purge(&state, &RetentionConfig { dry_run: true, ..config.clone() }, &context).await.unwrap(), 0);
+
+        let reports = report(&state, &config).await.unwrap();
+        assert_eq!(reports.iter().map(|report| (report.entity, report.days)).collect::<Vec<_>>(), vec![
+            (RetentionEntity::Bills, Some(365)), (RetentionEntity::DeletedItems, None), (RetentionEntity::JobRuns, Some(30)),
+        ]);
+    }
+}
@@ -0,0 +1,77 @@
+use std::str::FromStr;
+use anyhow::{anyhow, Context, Error};
+use chrono::{DateTime, Utc};
+use tokio_postgres::types::ToSql;
+#[cfg(test)]
+use crate::server::database::pool::{DbClient, GenericRow};
+use crate::server::model::admin::{JobOutcome, JobRun, JobTrigger};
+use crate::server::state::AppState;
+
+/// Keep a finished run in the `job_run` table
+pub async fn record(state: &AppState, run: &JobRun) -> Result<(), Error> {
+    let Some(conn) = state.get_db_write_pool().acquire(state.get_db_timeout_seconds()).await else {
+        return Err(anyhow!("no connection available"));
+    };
+    let client = conn.client.as_ref().unwrap();
+    let (trigger, outcome) = (run.trigger.to_string(), run.outcome.to_string());
+    let params: &[&(dyn ToSql + Sync)] = &[&run.name, &run.instance, &trigger, &outcome, &run.rows_affected, &run.error, &run.started_at, &run.finished_at];
+    client.execute(r#"
+            INSERT INTO job_run (name, instance, trigger, outcome, rows_affected, error, started_at, finished_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        "#, params).await.context("failed to record job run")?;
+    Ok(())
+}
+
+/// Latest runs of each given job on every instance, newest first
+pub async fn recent(state: &AppState, names: &[&str], limit: i64) -> Result<Vec<JobRun>, Error> {
+    let Some(conn) = state.get_db_read_pool().acquire(state.get_db_timeout_seconds()).await else {
+        return Err(anyhow!("no connection available"));
+    };
+    let client = conn.client.as_ref().unwrap();
+    let params: &[&(dyn ToSql + Sync)] = &[&names, &limit];
+    let rows = client.query(r#"
+            SELECT run.name, run.instance, run.trigger, run.outcome, run.rows_affected, run.error, run.started_at, run.finished_at
+            FROM unnest($1::varchar[]) AS job(name)
+            CROSS JOIN LATERAL (
+                SELECT *
+                FROM job_run
+                WHERE job_run.name = job.name
+                ORDER BY started_at DESC
+                LIMIT $2
+            ) run
+            ORDER BY run.name, run.started_at DESC
+        "#, params).await.context("failed to list job runs")?;
+    rows.iter().map(|row| Ok(JobRun {
+        name: row.get("name"),
+        instance: row.get("instance"),
+        trigger: JobTrigger::from_str(row.get("trigger")).map_err(Error::msg)?,
+        outcome: JobOutcome::from_str(row.get("outcome")).map_err(Error::msg)?,
+        rows_affected: row.get("rows_affected"),
+        error: row.get("error"),
+        started_at: row.get::<&str, DateTime<Utc>>("started_at"),
+        finished_at: row.get::<&str, DateTime<Utc>>("finished_at"),
+    })).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_record() {
+        let state = AppState::mock().await;
+        let now = Utc::now();
+        let run = JobRun {
+            name: "bill_item_sweeper".to_string(),
+            instance: "test".to_string(),
+            trigger: JobTrigger::Manual,
+            outcome: JobOutcome::Succeeded,
+            rows_affected: Some(3),
+            error: None,
+            started_at: now,
+            finished_at: now,
+        };
+        assert!(record(&state, &run).await.is_ok());
+        assert!(recent(&state, &["bill_item_sweeper"], 10).await.unwrap().is_empty());
+    }
+}
@@ -1,121 +1,171 @@
-use std::env;
+use std::str::FromStr;
+use std::time::Duration;
 use crate::server::database::pool::GenericRow;
 use crate::server::database::pool::DbClient;
-use log::{error, info};
-use tokio::{pin, select, time};
-use tokio_util::sync::CancellationToken;
-use tokio_util::task::task_tracker;
-use crate::DEFAULT_DB_WRITE_POOL_CONN_STR;
-use crate::server::DB_TIMEOUT_SECONDS;
-use crate::server::database::pool::{Init, Pool};
-
-/// Worker for the job scheduler, it takes a CancellationToken to be able to be gracefully cancelled when needed.
-async fn worker(cancel_token: CancellationToken) {
-    let mut write_pool = Pool::new().await.unwrap();
-    let conn_str = env::var("DB_WRITE_POOL_CONN_STR").unwrap_or(DEFAULT_DB_WRITE_POOL_CONN_STR.to_string());
-    write_pool.init(conn_str).await.ok();
-    let interval = time::interval(time::Duration::new(60, 0)); // run once every minute
-    pin!(interval);
-    loop {
-        select! {
-            _ = interval.tick() => {},
-            _ = cancel_token.cancelled() => {
-                info!("received cancel signal, returning");
-                return;
-            }
-        }
-
-        let local_conn = write_pool.acquire(DB_TIMEOUT_SECONDS).await.unwrap();
-        let client = local_conn.client.as_ref().unwrap();
-        let ids = match client.query(r#"
-                SELECT id
-                FROM bill_item
-                WHERE state = 'created'
-                AND date_add(created_at, make_interval(mins := time_to_deliver)) <= CURRENT_TIMESTAMP
-                LIMIT 10
-            "#, &[]).await {
-            Ok(rows) => rows.iter().map(|row| row.get("id")).collect::<Vec<i64>>(),
-            Err(e) => {
-                error!("failed to query delivered items, {:?}", e.code().unwrap());
-                vec![]
-            }
-        };
-        
-        if ids.is_empty() {
-            info!("nothing to update, continue to sleep");
-            continue;
-        }
-        
-        let stmt = format!(r#"
-                UPDATE "bill_item"
-                SET state = 'delivered'
-                WHERE state = 'created' AND id IN ({})
-                RETURNING id
-            "#, ids.iter().map(|id| id.to_string()).collect::<Vec<String>>().join(","));
-        
-        match client.query(&stmt, &[]).await {
-            Ok(rows) => {
-                info!("marked bill items {:?} as delivered", rows.iter().map(|row| row.get::<&str, i64>("id")).collect::<Vec<_>>());
-            },
-            Err(e) => {
-                error!("failed to mark some bill items as delivered, {:?}", e.code().unwrap());
-            }
-        };
+use anyhow::{anyhow, Context as _, Error};
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+use log::{debug, info};
+use tokio_postgres::types::ToSql;
+use crate::server::{alert, retention};
+use crate::server::outbox::Relay;
+use crate::server::webhook::Dispatcher;
+use crate::server::model::config::{AlertConfig, RetentionConfig};
+use crate::server::model::event::Event;
+use crate::server::scheduler::{Context, Job, Schedule};
+use crate::server::state::AppState;
+
+/// A scheduled job that updates bill items if it is delivered already,
+/// according to the original designated time_to_deliver
+pub(crate) struct BillItemSweeper;
+
+impl Job for BillItemSweeper {
+    fn name(&self) -> &'static str {
+        "bill_item_sweeper"
+    }
+
+    fn schedule(&self) -> Schedule {
+        Schedule::Interval(Duration::from_secs(60)) // run once every minute
+    }
+
+    fn run<'a>(&'a self, state: &'static AppState, context: &'a Context) -> BoxFuture<'a, Result<u64, Error>> {
+        Box::pin(sweep(state, context))
     }
 }
 
-/// a scheduled job that updates bill items if it is delivered already,
-/// according to the original designated time_to_deliver
-pub async fn bill_item_sweeper(cancel_token: CancellationToken) {
-    loop {
-        let tracker = task_tracker::TaskTracker::new();
-        tracker.spawn(worker(cancel_token.clone()));
-        if tracker.close() {
-            tracker.wait().await;
-            if cancel_token.is_cancelled() { // stop sweeper only when app is being shutting down 
-                break;   
-            }
-        }
+/// A scheduled job that flags bills left open on a table, and closes them for review when configured
+pub(crate) struct StaleBillDetector {
+    pub config: AlertConfig,
+}
+
+impl Job for StaleBillDetector {
+    fn name(&self) -> &'static str {
+        "stale_bill_detector"
+    }
+
+    fn schedule(&self) -> Schedule {
+        Schedule::Interval(Duration::from_secs(60))
+    }
+
+    fn run<'a>(&'a self, state: &'static AppState, _: &'a Context) -> BoxFuture<'a, Result<u64, Error>> {
+        Box::pin(alert::detect(state, &self.config))
     }
 }
 
+/// A scheduled job that moves old closed bills, removed items and job runs out of the hot tables
+pub(crate) struct Retention {
+    pub config: RetentionConfig,
+}
+
+impl Job for Retention {
+    fn name(&self) -> &'static str {
+        "retention"
+    }
+
+    fn schedule(&self) -> Schedule {
+        // daily at 03:00 UTC, out of business hours
+        Schedule::Cron(Box::new(cron::Schedule::from_str("0 0 3 * * *").expect("valid cron expression")))
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(600)
+    }
 
-#[cfg(test)]
-mod test {
-    use std::time::Duration;
-    use super::*;
-
-    #[tokio::test]
-    async fn test_worker() {
-        let cancel_token = CancellationToken::new();
-        let worker_cancel_token = cancel_token.clone();
-        let sleeper_cancel_token = cancel_token.clone();
-        let actual = select! {
-            _ = worker(worker_cancel_token) => {
-                "unexpected"    
-            },
-            _ = time::sleep(Duration::new(2, 0)) => {
-                sleeper_cancel_token.cancel();
-                "expected"
-            }
-        };
-        assert_eq!(actual, "expected");
-    }
-    
-    #[tokio::test]
-    async fn test_bill_item_sweeper() {
-        let cancel_token = CancellationToken::new();
-        let sweeper_cancel_token = cancel_token.clone();
-        let sleeper_cancel_token = cancel_token.clone();
-        let actual = select! {
-            _ = bill_item_sweeper(sweeper_cancel_token) => {
-                "unexpected"
-            },
-            _ = time::sleep(Duration::new(2, 0)) => {
-                sleeper_cancel_token.cancel();
-                "expected"
-            }
-        };
-        assert_eq!(actual, "expected");
-    }
-}
\ No newline at end of file
+    fn run<'a>(&'a self, state: &'static AppState, context: &'a Context) -> BoxFuture<'a, Result<u64, Error>> {
+        Box::pin(retention::purge(state, &self.config, context))
+    }
+}
+
+/// A scheduled job that delivers integration events from the outbox to the configured sinks
+pub(crate) struct OutboxRelay {
+    pub relay: Relay,
+}
+
+impl Job for OutboxRelay {
+    fn name(&self) -> &'static str {
+        "outbox_relay"
+    }
+
+    fn schedule(&self) -> Schedule {
+        Schedule::Interval(Duration::from_secs(5))
+    }
+
+    fn run<'a>(&'a self, state: &'static AppState, context: &'a Context) -> BoxFuture<'a, Result<u64, Error>> {
+        Box::pin(self.relay.run(state, context))
+    }
+}
+
+/// A scheduled job that posts due webhook deliveries, signed, to the subscribed URLs
+pub(crate) struct WebhookDispatcher {
+    pub dispatcher: Dispatcher,
+}
+
+impl Job for WebhookDispatcher {
+    fn name(&self) -> &'static str {
+        "webhook_dispatcher"
+    }
+
+    fn schedule(&self) -> Schedule {
+        Schedule::Interval(Duration::from_secs(5))
+    }
+
+    fn run<'a>(&'a self, state: &'static AppState, context: &'a Context) -> BoxFuture<'a, Result<u64, Error>> {
+        Box::pin(self.dispatcher.run(state, context))
+    }
+}
+
+/// Mark due bill items as delivered a batch at a time, until none is left due or the shutdown starts,
+/// returning how many were marked
+async fn sweep(state: &AppState, context: &Context) -> Result<u64, Error> {
+    // more may be due than a batch during rushes
+    let delivered = context.in_batches("bill items marked as delivered", || async {
+        let marked = sweep_batch(state, context.batch_size).await?;
+        Ok((marked, marked))
+    }).await?;
+    if delivered == 0 {
+        debug!("nothing to update, continue to sleep");
+    }
+    Ok(delivered)
+}
+
+/// Mark up to a batch of due bill items as delivered in one statement, publishing an event for each.
+/// Items locked by another sweeper are left to it.
+async fn sweep_batch(state: &AppState, batch_size: u32) -> Result<u64, Error> {
+    let Some(local_conn) = state.get_db_write_pool().acquire(state.get_db_timeout_seconds()).await else {
+        return Err(anyhow!("no connection available"));
+    };
+    let client = local_conn.client.as_ref().unwrap();
+    let params: &[&(dyn ToSql + Sync)] = &[&i64::from(batch_size)];
+    let rows = client.query(r#"
+            WITH due AS (
+                SELECT id
+                FROM bill_item
+                WHERE state = 'created'
+                AND date_add(created_at, make_interval(mins := time_to_deliver)) <= CURRENT_TIMESTAMP
+                ORDER BY id
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            UPDATE bill_item
+            SET state = 'delivered', delivered_at = CURRENT_TIMESTAMP
+            FROM due
+            WHERE bill_item.id = due.id
+            RETURNING bill_item.id, bill_item.bill_id, bill_item.delivered_at
+        "#, params).await
+        .context("failed to mark due bill items as delivered")?;
+
+    let mut ids = Vec::with_capacity(rows.len());
+    for row in &rows {
+        let bill_item_id = row.get("id");
+        ids.push(bill_item_id);
+        state.get_events().publish(Event::ItemDelivered {
+            bill_id: row.get("bill_id"),
+            bill_item_id,
+            delivered_at: row.get::<&str, DateTime<Utc>>("delivered_at"),
+        });
+    }
+    if !ids.is_empty() {
+        info!("marked bill items {:?} as delivered", ids);
+    }
+    Ok(rows.len() as u64)
+}
@@ -0,0 +1,87 @@
+use std::time::Duration;
+use anyhow::{anyhow, Context, Error};
+use chrono::{DateTime, Utc};
+use tokio_postgres::types::ToSql;
+#[cfg(test)]
+use crate::server::database::pool::{DbClient, GenericRow};
+use crate::server::model::admin::Lease;
+use crate::server::state::AppState;
+
+/// Leases of scheduled jobs in the `job_lease` table, so that one instance runs each job at a time.
+/// The holder renews its lease on every run, another instance takes the job over once the lease expires.
+#[derive(Debug, Clone)]
+pub(crate) struct Leases {
+    holder: String,
+}
+
+impl Leases {
+    pub fn new(holder: String) -> Self {
+        Self { holder }
+    }
+
+    /// Take or renew the lease of a job for the given time, telling whether this instance holds it now
+    pub async fn acquire(&self, state: &AppState, name: &str, ttl: Duration) -> Result<bool, Error> {
+        let Some(conn) = state.get_db_write_pool().acquire(state.get_db_timeout_seconds()).await else {
+            return Err(anyhow!("no connection available"));
+        };
+        let client = conn.client.as_ref().unwrap();
+        let ttl = ttl.as_secs_f64();
+        let params: &[&(dyn ToSql + Sync)] = &[&name, &self.holder, &ttl];
+        let rows = client.query(r#"
+                INSERT INTO job_lease (name, holder, acquired_at, expires_at)
+                VALUES ($1, $2, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP + make_interval(secs => $3))
+                ON CONFLICT (name) DO UPDATE
+                SET holder = EXCLUDED.holder,
+                    acquired_at = CASE WHEN job_lease.holder = EXCLUDED.holder THEN job_lease.acquired_at ELSE EXCLUDED.acquired_at END,
+                    expires_at = EXCLUDED.expires_at
+                WHERE job_lease.holder = EXCLUDED.holder OR job_lease.expires_at < CURRENT_TIMESTAMP
+                RETURNING holder
+            "#, params).await.context("failed to acquire lease")?;
+        Ok(!rows.is_empty())
+    }
+
+    /// Give up every lease of this instance, for others to take the jobs over without waiting for expiry
+    pub async fn release(&self, state: &AppState) -> Result<u64, Error> {
+        let Some(conn) = state.get_db_write_pool().acquire(state.get_db_timeout_seconds()).await else {
+            return Err(anyhow!("no connection available"));
+        };
+        let client = conn.client.as_ref().unwrap();
+        let params: &[&(dyn ToSql + Sync)] = &[&self.holder];
+        client.execute("DELETE FROM job_lease WHERE holder = $1", params).await.context("failed to release leases")
+    }
+
+    /// Leases of every job, expired ones included
+    pub async fn list(state: &AppState) -> Result<Vec<Lease>, Error> {
+        let Some(conn) = state.get_db_read_pool().acquire(state.get_db_timeout_seconds()).await else {
+            return Err(anyhow!("no connection available"));
+        };
+        let client = conn.client.as_ref().unwrap();
+        let rows = client.query(r#"
+                SELECT name, holder, acquired_at, expires_at, expires_at < CURRENT_TIMESTAMP AS expired
+                FROM job_lease
+                ORDER BY name
+            "#, &[]).await.context("failed to list leases")?;
+        Ok(rows.iter().map(|row| Lease {
+            name: row.get("name"),
+            holder: row.get("holder"),
+            acquired_at: row.get::<&str, DateTime<Utc>>("acquired_at"),
+            expires_at: row.get::<&str, DateTime<Utc>>("expires_at"),
+            expired: row.get("expired"),
+        }).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_acquire() {
+        let state = AppState::mock().await;
+        let leases = Leases::new("test".to_string());
+        // the mock database returns no row, as if another instance held the lease
+        assert!(!leases.acquire(&state, "bill_item_sweeper", Duration::from_secs(60)).await.unwrap());
+        assert_eq!(leases.release(&state).await.unwrap(), 0);
+        assert!(Leases::list(&state).await.unwrap().is_empty());
+    }
+}
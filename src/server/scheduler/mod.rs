@@ -1 +1,469 @@
-pub mod job;
\ No newline at end of file
+//! Scheduler of background jobs, each job runs on its own interval or cron schedule
+
+pub mod history;
+pub mod job;
+pub mod lease;
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use anyhow::{anyhow, Context as _, Error};
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+use log::{debug, error, info, warn};
+use rand::Rng;
+use tokio::{select, time};
+use tokio_util::sync::CancellationToken;
+use crate::server::model::admin::{JobOutcome, JobRun, JobTrigger};
+use crate::server::model::config::{JobConfig, ServerConfig};
+use crate::server::outbox::Relay;
+use crate::server::webhook::Dispatcher;
+use crate::server::scheduler::job::{BillItemSweeper, OutboxRelay, Retention, StaleBillDetector, WebhookDispatcher};
+use crate::server::scheduler::lease::Leases;
+use crate::server::shutdown::Coordinator;
+use crate::server::state::AppState;
+
+/// Time a lease outlasts the longest run, for the holder to record the run and renew it before it expires
+const LEASE_MARGIN: Duration = Duration::from_secs(10);
+
+/// When a job runs
+#[derive(Debug, Clone)]
+pub(crate) enum Schedule {
+    /// a fixed delay between the end of a run and the start of the next one
+    Interval(Duration),
+    /// times of a cron expression with seconds, in UTC
+    Cron(Box<cron::Schedule>),
+}
+
+impl Schedule {
+    /// Delay from now until the next run, none when a cron expression has no upcoming time
+    fn next_delay(&self) -> Option<Duration> {
+        match self {
+            Schedule::Interval(interval) => Some(*interval),
+            Schedule::Cron(schedule) => schedule.upcoming(Utc).next()
+                .map(|next| (next - Utc::now()).to_std().unwrap_or_default()),
+        }
+    }
+}
+
+impl Display for Schedule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Schedule::Interval(interval) => write!(f, "every {:?}", interval),
+            Schedule::Cron(schedule) => write!(f, "{}", schedule),
+        }
+    }
+}
+
+/// A background job, run repeatedly by the scheduler
+pub(crate) trait Job: Send + Sync + 'static {
+    /// Unique name, also the key of its configs
+    fn name(&self) -> &'static str;
+
+    /// Schedule unless configured otherwise
+    fn schedule(&self) -> Schedule;
+
+    /// Longest time a run may take unless configured otherwise, the run is aborted then
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(60)
+    }
+
+    /// Rows handled per statement by jobs working in batches, unless configured otherwise
+    fn batch_size(&self) -> u32 {
+        100
+    }
+
+    /// Run once with the shared pools of the app, returning the number of rows affected
+    fn run<'a>(&'a self, state: &'static AppState, context: &'a Context) -> BoxFuture<'a, Result<u64, Error>>;
+}
+
+/// What a run is given besides the app state
+#[derive(Debug, Clone)]
+pub(crate) struct Context {
+    pub batch_size: u32,
+    /// cancelled once the shutdown starts, for jobs working in batches to stop after the current one
+    pub cancel_token: CancellationToken,
+}
+
+impl Context {
+    /// Run batches until one is short of the batch size or the shutdown starts, a full batch means more may be due.
+    /// Each batch returns how many rows were due and how many of them were done, the total done is returned.
+    pub async fn in_batches<F, Fut>(&self, done: &str, mut batch: F) -> Result<u64, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<(u64, u64), Error>>,
+    {
+        let mut total = 0;
+        loop {
+            let (due, finished) = batch().await.with_context(|| format!("failed after {} {}", total, done))?;
+            total += finished;
+            if due < u64::from(self.batch_size) || self.cancel_token.is_cancelled() {
+                return Ok(total);
+            }
+        }
+    }
+}
+
+/// Jobs known to the scheduler, each with a unique name
+pub(crate) struct Registry {
+    jobs: Vec<Arc<dyn Job>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self { jobs: vec![] }
+    }
+
+    /// Registry with every job of the server, given the configs of jobs with their own section
+    pub fn with_default_jobs(config: &ServerConfig) -> Self {
+        let mut registry = Self::new();
+        registry.register(BillItemSweeper);
+        registry.register(StaleBillDetector { config: config.alerts.clone() });
+        registry.register(Retention { config: config.retention.clone() });
+        registry.register(OutboxRelay { relay: Relay::new(&config.outbox) });
+        registry.register(WebhookDispatcher { dispatcher: Dispatcher::new(&config.webhooks) });
+        registry
+    }
+
+    /// Add a job, panics when the name is taken
+    pub fn register(&mut self, job: impl Job) {
+        assert!(!self.names().contains(&job.name()), "job {} is registered twice", job.name());
+        self.jobs.push(Arc::new(job));
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.jobs.iter().map(|job| job.name()).collect()
+    }
+
+    /// Spawn enabled jobs with their configs, they return once the shutdown starts.
+    /// With leases, a job runs only while this instance holds its lease.
+    /// Every job, disabled ones included, is returned for listing and running on demand.
+    pub fn start(&self, configs: &BTreeMap<String, JobConfig>, state: &'static AppState, leases: Option<Leases>, coordinator: &Coordinator) -> Result<Scheduler, Error> {
+        let mut scheduled = vec![];
+        for job in &self.jobs {
+            let config = configs.get(job.name()).cloned().unwrap_or_default();
+            let schedule = match (config.interval_seconds, &config.cron) {
+                (Some(seconds), None) => Schedule::Interval(Duration::from_secs(seconds)),
+                (None, Some(cron)) => Schedule::Cron(Box::new(cron::Schedule::from_str(cron).map_err(|e| anyhow!("job {}: {}", job.name(), e))?)),
+                (None, None) => job.schedule(),
+                (Some(_), Some(_)) => return Err(anyhow!("job {}: both interval_seconds and cron are set", job.name())),
+            };
+            let timeout = config.timeout_seconds.map_or_else(|| job.timeout(), Duration::from_secs);
+            let job = Arc::new(Scheduled {
+                job: job.clone(),
+                enabled: config.enabled,
+                schedule,
+                jitter: Duration::from_secs(config.jitter_seconds),
+                timeout,
+                context: Context {
+                    batch_size: config.batch_size.unwrap_or_else(|| job.batch_size()),
+                    cancel_token: coordinator.cancel_token(),
+                },
+                next_run_at: Mutex::new(None),
+                running: tokio::sync::Mutex::new(()),
+            });
+            if job.enabled {
+                info!("job {} is scheduled {}", job.name(), job.schedule);
+                coordinator.spawn(run_job(job.clone(), state, leases.clone(), coordinator.cancel_token()));
+            } else {
+                info!("job {} is disabled", job.name());
+            }
+            scheduled.push(job);
+        }
+        Ok(Scheduler { jobs: scheduled })
+    }
+}
+
+/// Jobs started by the registry, shared with the admin API
+pub(crate) struct Scheduler {
+    jobs: Vec<Arc<Scheduled>>,
+}
+
+impl Scheduler {
+    pub fn jobs(&self) -> &[Arc<Scheduled>] {
+        &self.jobs
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<Scheduled>> {
+        self.jobs.iter().find(|job| job.name() == name)
+    }
+}
+
+/// A job with its effective configs and the state of its runs on this instance
+pub(crate) struct Scheduled {
+    job: Arc<dyn Job>,
+    enabled: bool,
+    schedule: Schedule,
+    jitter: Duration,
+    timeout: Duration,
+    context: Context,
+    next_run_at: Mutex<Option<DateTime<Utc>>>,
+    /// held through a run, so that runs of a job never overlap on an instance
+    running: tokio::sync::Mutex<()>,
+}
+
+impl Scheduled {
+    pub fn name(&self) -> &'static str {
+        self.job.name()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn schedule(&self) -> &Schedule {
+        &self.schedule
+    }
+
+    /// Time a lease is taken for before a scheduled run, as long as the longest run and no longer,
+    /// so that another instance takes a job over at its next run once the holder dies
+    fn lease_ttl(&self) -> Duration {
+        self.timeout + LEASE_MARGIN
+    }
+
+    /// Time of the next scheduled run, none while running or when disabled
+    pub fn next_run_at(&self) -> Option<DateTime<Utc>> {
+        *self.next_run_at.lock().unwrap()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.try_lock().is_err()
+    }
+
+    /// Run once now and record the run in the history, after a run in progress on this instance finishes.
+    /// A panicking run is reported as the run failing, a run taking longer than the timeout is aborted.
+    pub async fn run(&self, state: &'static AppState, trigger: JobTrigger) -> JobRun {
+        let _running = self.running.lock().await;
+        let started_at = Utc::now();
+        let run = tokio::spawn({
+            let (job, context) = (self.job.clone(), self.context.clone());
+            async move { job.run(state, &context).await }
+        });
+        let abort_handle = run.abort_handle();
+        let (outcome, rows_affected, error) = match time::timeout(self.timeout, run).await {
+            Ok(Ok(Ok(rows))) => (JobOutcome::Succeeded, Some(i64::try_from(rows).unwrap_or(i64::MAX)), None),
+            Ok(Ok(Err(e))) => (JobOutcome::Failed, None, Some(format!("{:#}", e))),
+            Ok(Err(e)) => (JobOutcome::Panicked, None, Some(e.to_string())),
+            Err(_) => {
+                abort_handle.abort();
+                (JobOutcome::TimedOut, None, Some(format!("did not finish in {:?}, aborted", self.timeout)))
+            },
+        };
+        let run = JobRun {
+            name: self.name().to_string(),
+            instance: state.get_instance_id().to_string(),
+            trigger,
+            outcome,
+            rows_affected,
+            error,
+            started_at,
+            finished_at: Utc::now(),
+        };
+        match &run.error {
+            None => info!("job {} succeeded, {} rows affected", run.name, run.rows_affected.unwrap_or_default()),
+            Some(e) => error!("job {} {}, {}", run.name, run.outcome, e),
+        }
+        if let Err(e) = history::record(state, &run).await {
+            error!("failed to record the run of job {}, {:#}", run.name, e);
+        }
+        run
+    }
+}
+
+/// Run a job on its schedule until cancelled, a run in progress is let finish or time out
+async fn run_job(job: Arc<Scheduled>, state: &'static AppState, leases: Option<Leases>, cancel_token: CancellationToken) {
+    loop {
+        let Some(delay) = job.schedule.next_delay() else {
+            warn!("job {} has no upcoming run, stopping it", job.name());
+            return;
+        };
+        // spread the runs of instances started together
+        let delay = delay + job.jitter.mul_f64(rand::thread_rng().gen_range(0.0..=1.0));
+        *job.next_run_at.lock().unwrap() = chrono::Duration::from_std(delay).ok().map(|delay| Utc::now() + delay);
+        select! {
+            _ = time::sleep(delay) => {},
+            _ = cancel_token.cancelled() => {
+                info!("job {} received cancel signal, returning", job.name());
+                return;
+            }
+        }
+
+        if let Some(leases) = &leases {
+            // the holder keeps jobs run more often than the lease lasts, others are taken by whoever is due first
+            match leases.acquire(state, job.name(), job.lease_ttl()).await {
+                Ok(true) => {},
+                Ok(false) => {
+                    debug!("job {} is run by another instance, skipping", job.name());
+                    continue;
+                },
+                Err(e) => {
+                    error!("job {} skipped, {:#}", job.name(), e);
+                    continue;
+                },
+            }
+        }
+
+        *job.next_run_at.lock().unwrap() = None;
+        job.run(state, JobTrigger::Schedule).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use super::*;
+
+    struct Counter(Arc<AtomicUsize>);
+
+    impl Job for Counter {
+        fn name(&self) -> &'static str {
+            "counter"
+        }
+
+        fn schedule(&self) -> Schedule {
+            Schedule::Interval(Duration::from_millis(10))
+        }
+
+        fn run<'a>(&'a self, _: &'static AppState, _: &'a Context) -> BoxFuture<'a, Result<u64, Error>> {
+            Box::pin(async move {
+                if self.0.fetch_add(1, Ordering::SeqCst) == 0 {
+                    panic!("the first run fails");
+                }
+                Ok(1)
+            })
+        }
+    }
+
+    #[test]
+    fn test_schedule() {
+        assert_eq!(Schedule::Interval(Duration::from_secs(5)).next_delay(), Some(Duration::from_secs(5)));
+        let cron = Schedule::Cron(Box::new(cron::Schedule::from_str("0 * * * * *").unwrap()));
+        assert!(cron.next_delay().unwrap() <= Duration::from_secs(60));
+        let past = Schedule::Cron(Box::new(cron::Schedule::from_str("0 0 0 1 1 * 2000").unwrap()));
+        assert_eq!(past.next_delay(), None);
+        assert_eq!(Schedule::Interval(Duration::from_secs(5)).to_string(), "every 5s");
+        assert_eq!(cron.to_string(), "0 * * * * *");
+    }
+
+    #[test]
+    #[should_panic(expected = "registered twice")]
+    fn test_register_twice() {
+        let mut registry = Registry::with_default_jobs(&ServerConfig::default());
+        registry.register(BillItemSweeper);
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let state: &'static AppState = Box::leak(Box::new(AppState::mock().await));
+        let mut registry = Registry::new();
+        registry.register(Counter(Arc::new(AtomicUsize::new(0))));
+        let configs = BTreeMap::from([
+            ("counter".to_string(), JobConfig { enabled: false, ..JobConfig::default() }),
+        ]);
+        let scheduler = registry.start(&configs, state, None, &Coordinator::new(Duration::from_secs(1))).unwrap();
+        let counter = scheduler.get("counter").unwrap();
+        let run = counter.run(state, JobTrigger::Manual).await;
+        assert_eq!((run.outcome, run.rows_affected), (JobOutcome::Panicked, None));
+        assert!(run.error.unwrap().contains("panicked"));
+        let run = counter.run(state, JobTrigger::Manual).await;
+        assert_eq!((run.outcome, run.rows_affected, run.error), (JobOutcome::Succeeded, Some(1), None));
+    }
+
+    #[tokio::test]
+    async fn test_start() {
+        let state: &'static AppState = Box::leak(Box::new(AppState::mock().await));
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut registry = Registry::new();
+        registry.register(Counter(runs.clone()));
+        registry.register(BillItemSweeper);
+
+        let coordinator = Coordinator::new(Duration::from_secs(1));
+        let configs = BTreeMap::from([
+            ("bill_item_sweeper".to_string(), JobConfig { enabled: false, ..JobConfig::default() }),
+        ]);
+        let scheduler = registry.start(&configs, state, None, &coordinator).unwrap();
+        time::sleep(Duration::from_millis(100)).await;
+        assert!(scheduler.get("counter").unwrap().next_run_at().is_some());
+        coordinator.stop_jobs(coordinator.deadline()).await.unwrap();
+        assert!(runs.load(Ordering::SeqCst) > 1, "runs continue after a panic");
+
+        // disabled jobs are listed and still run on demand
+        let sweeper = scheduler.get("bill_item_sweeper").unwrap();
+        assert!(!sweeper.is_enabled());
+        assert_eq!(sweeper.next_run_at(), None);
+        let run = sweeper.run(state, JobTrigger::Manual).await;
+        assert_eq!((run.outcome, run.rows_affected, run.trigger), (JobOutcome::Succeeded, Some(0), JobTrigger::Manual));
+        assert_eq!(run.instance, "test");
+        assert!(!sweeper.is_running());
+        assert_eq!(scheduler.jobs().len(), 2);
+        assert!(scheduler.get("vacuum").is_none());
+
+        // the mock database returns no lease, as if another instance held them
+        let runs_before = runs.load(Ordering::SeqCst);
+        let coordinator = Coordinator::new(Duration::from_secs(1));
+        registry.start(&configs, state, Some(Leases::new("test".to_string())), &coordinator).unwrap();
+        time::sleep(Duration::from_millis(100)).await;
+        coordinator.stop_jobs(coordinator.deadline()).await.unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), runs_before);
+
+        let configs = BTreeMap::from([
+            ("counter".to_string(), JobConfig { interval_seconds: Some(1), cron: Some("* * * * * *".to_string()), ..JobConfig::default() }),
+        ]);
+        assert!(registry.start(&configs, state, None, &Coordinator::new(Duration::from_secs(1))).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_in_batches() {
+        let batches = |sizes: Vec<u64>| {
+            let calls = Arc::new(AtomicUsize::new(0));
+            let batch = {
+                let calls = calls.clone();
+                move || {
+                    let call = calls.fetch_add(1, Ordering::SeqCst);
+                    let size = sizes.get(call).copied();
+                    async move { size.map(|size| (size, size / 2)).ok_or(anyhow!("no more rows")) }
+                }
+            };
+            (calls, batch)
+        };
+        let context = Context { batch_size: 100, cancel_token: Default::default() };
+
+        // full batches go on, the short one ends the run
+        let (calls, batch) = batches(vec![100, 100, 30, 100]);
+        assert_eq!(context.in_batches("rows done", batch).await.unwrap(), 50 + 50 + 15);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        // an empty batch after a full one too
+        let (calls, batch) = batches(vec![100, 0]);
+        assert_eq!(context.in_batches("rows done", batch).await.unwrap(), 50);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        // a failed batch tells how much was done before it
+        let (_, batch) = batches(vec![100]);
+        let e = context.in_batches("rows done", batch).await.unwrap_err();
+        assert_eq!(format!("{:#}", e), "failed after 50 rows done: no more rows");
+
+        // the shutdown stops the run after the current batch
+        let context = Context { batch_size: 100, cancel_token: CancellationToken::new() };
+        context.cancel_token.cancel();
+        let (calls, batch) = batches(vec![100, 100]);
+        assert_eq!(context.in_batches("rows done", batch).await.unwrap(), 50);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_lease_ttl() {
+        let state: &'static AppState = Box::leak(Box::new(AppState::mock().await));
+        let registry = Registry::with_default_jobs(&ServerConfig::default());
+        let configs = registry.names().into_iter()
+            .map(|name| (name.to_string(), JobConfig { enabled: false, timeout_seconds: Some(600), ..JobConfig::default() }))
+            .collect();
+        let scheduler = registry.start(&configs, state, None, &Coordinator::new(Duration::from_secs(1))).unwrap();
+        // a daily job is taken over at its next run when the holder dies, not a run later
+        let retention = scheduler.get("retention").unwrap();
+        assert_eq!(retention.schedule().to_string(), "0 0 3 * * *");
+        assert_eq!(retention.lease_ttl(), Duration::from_secs(600) + LEASE_MARGIN);
+        assert!(retention.lease_ttl() < Duration::from_secs(24 * 60 * 60));
+    }
+}
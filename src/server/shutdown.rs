@@ -0,0 +1,97 @@
+//! Coordinates graceful shutdown of the HTTP server, background jobs and connection pools
+
+use std::future::Future;
+use std::io::ErrorKind;
+use std::time::Duration;
+use log::{info, warn};
+use tokio::time::{self, Instant};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+/// Wait for SIGTERM or SIGINT, returning the name of the received one
+pub(crate) async fn signal() -> std::io::Result<&'static str> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        tokio::select! {
+            _ = terminate.recv() => Ok("SIGTERM"),
+            _ = interrupt.recv() => Ok("SIGINT"),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await.map(|_| "ctrl-c")
+    }
+}
+
+/// Tracks background jobs and stops them, all within a deadline from the start of the shutdown
+pub(crate) struct Coordinator {
+    cancel_token: CancellationToken,
+    tracker: TaskTracker,
+    timeout: Duration,
+}
+
+impl Coordinator {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            cancel_token: CancellationToken::new(),
+            tracker: TaskTracker::new(),
+            timeout,
+        }
+    }
+
+    /// Token cancelled once the shutdown starts, for jobs to return on
+    pub fn cancel_token(&self) -> CancellationToken {
+        self.cancel_token.clone()
+    }
+
+    /// Spawn a background job to be waited for on shutdown
+    pub fn spawn<F>(&self, job: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.tracker.spawn(job);
+    }
+
+    /// Deadline of a shutdown starting now
+    pub fn deadline(&self) -> Instant {
+        Instant::now() + self.timeout
+    }
+
+    /// Cancel background jobs and wait for them until the deadline, jobs still running then are left behind
+    pub async fn stop_jobs(&self, deadline: Instant) -> std::io::Result<()> {
+        self.cancel_token.cancel();
+        self.tracker.close();
+        info!("waiting for {} background jobs", self.tracker.len());
+        match time::timeout_at(deadline, self.tracker.wait()).await {
+            Ok(()) => Ok(()),
+            Err(_) => {
+                warn!("{} background jobs are still running after the shutdown deadline", self.tracker.len());
+                Err(std::io::Error::new(ErrorKind::TimedOut, "background jobs did not stop before the shutdown deadline"))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_stop_jobs() {
+        let coordinator = Coordinator::new(Duration::from_secs(1));
+        let cancel_token = coordinator.cancel_token();
+        coordinator.spawn(async move { cancel_token.cancelled().await });
+        assert!(coordinator.stop_jobs(coordinator.deadline()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_stop_jobs_after_deadline() {
+        let coordinator = Coordinator::new(Duration::from_millis(100));
+        coordinator.spawn(time::sleep(Duration::from_secs(60)));
+        let e = coordinator.stop_jobs(coordinator.deadline()).await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::TimedOut);
+    }
+}
@@ -1,7 +1,16 @@
+use std::sync::Arc;
+use chrono_tz::Tz;
 use tokio_postgres::Client;
 #[cfg(test)]
 use crate::server::database::connection::MockClient;
 use crate::server::database::pool::{DbClient, Pool};
+use crate::server::cache::Caches;
+use crate::server::event::EventBus;
+use crate::server::receipt::Renderer;
+#[cfg(test)]
+use crate::server::database::pool::Init;
+#[cfg(test)]
+use crate::server::model::config::{CacheConfig, PoolConfig, ReceiptConfig};
 
 /// Application states
 #[derive(Clone)]
@@ -9,6 +18,12 @@ use crate::server::database::pool::{DbClient, Pool};
 pub(crate) struct AppState {
     db_read_pool: Pool<Client>,
     db_write_pool: Pool<Client>,
+    db_timeout_seconds: u64,
+    renderer: Arc<Renderer>,
+    time_zone: Tz,
+    instance_id: String,
+    events: EventBus,
+    caches: Arc<Caches>,
 }
 
 #[derive(Clone)]
@@ -16,15 +31,27 @@ pub(crate) struct AppState {
 pub(crate) struct AppState {
     db_read_pool: Pool<MockClient>,
     db_write_pool: Pool<MockClient>,
+    db_timeout_seconds: u64,
+    renderer: Arc<Renderer>,
+    time_zone: Tz,
+    instance_id: String,
+    events: EventBus,
+    caches: Arc<Caches>,
 }
 
 impl AppState {
     /// Create a new AppState instance
     #[cfg(not(test))]
-    pub fn new(db_read_pool: Pool<Client>, db_write_pool: Pool<Client>) -> Self {
+    pub fn new(db_read_pool: Pool<Client>, db_write_pool: Pool<Client>, db_timeout_seconds: u64, renderer: Renderer, time_zone: Tz, instance_id: String, caches: Caches) -> Self {
         Self {
             db_read_pool,
             db_write_pool,
+            db_timeout_seconds,
+            renderer: Arc::new(renderer),
+            time_zone,
+            instance_id,
+            events: EventBus::new(),
+            caches: Arc::new(caches),
         }
     }
 
@@ -41,13 +68,28 @@ impl AppState {
     }
 
     #[cfg(test)]
-    pub fn new(db_read_pool: Pool<MockClient>, db_write_pool: Pool<MockClient>) -> Self {
+    pub fn new(db_read_pool: Pool<MockClient>, db_write_pool: Pool<MockClient>, db_timeout_seconds: u64, renderer: Renderer, time_zone: Tz, instance_id: String, caches: Caches) -> Self {
         Self {
             db_read_pool,
             db_write_pool,
+            db_timeout_seconds,
+            renderer: Arc::new(renderer),
+            time_zone,
+            instance_id,
+            events: EventBus::new(),
+            caches: Arc::new(caches),
         }
     }
 
+    /// State with one mock connection in each pool
+    #[cfg(test)]
+    pub async fn mock() -> Self {
+        let (mut read_pool, mut write_pool) = (Pool::<MockClient>::new().await.unwrap(), Pool::<MockClient>::new().await.unwrap());
+        read_pool.init(&PoolConfig::default()).await.unwrap();
+        write_pool.init(&PoolConfig::default()).await.unwrap();
+        Self::new(read_pool, write_pool, 1, Renderer::new(&ReceiptConfig::default()).unwrap(), Tz::UTC, "test".to_string(), Caches::new(&CacheConfig::default()))
+    }
+
     #[cfg(test)]
     pub fn get_db_read_pool(&self) -> Pool<MockClient> {
         self.db_read_pool.clone()
@@ -57,6 +99,36 @@ impl AppState {
     pub fn get_db_write_pool(&self) -> Pool<MockClient> {
         self.db_write_pool.clone()
     }
+
+    /// Get seconds to wait for a pooled connection
+    pub fn get_db_timeout_seconds(&self) -> u64 {
+        self.db_timeout_seconds
+    }
+
+    /// Get the id of this instance among replicas
+    pub fn get_instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    /// Get the bus of events pushed to clients
+    pub fn get_events(&self) -> &EventBus {
+        &self.events
+    }
+
+    /// Get the in-process caches of reads
+    pub fn get_caches(&self) -> &Caches {
+        &self.caches
+    }
+
+    /// Get the time zone of the restaurant
+    pub fn get_time_zone(&self) -> Tz {
+        self.time_zone
+    }
+
+    /// Get the receipt and kitchen ticket renderer
+    pub fn get_renderer(&self) -> &Renderer {
+        &self.renderer
+    }
 }
 
 #[cfg(test)]
@@ -69,7 +141,8 @@ mod test {
     async fn app_state() {
         async {
             let (read_pool, write_pool) = (Pool::<MockClient>::new().await, Pool::<MockClient>::new().await);
-            let state = AppState::new(read_pool.unwrap(), write_pool.unwrap());
+            let renderer = Renderer::new(&ReceiptConfig::default()).unwrap();
+            let state = AppState::new(read_pool.unwrap(), write_pool.unwrap(), 1, renderer, Tz::UTC, "test".to_string(), Caches::new(&CacheConfig::default()));
             assert_eq!(state.get_db_read_pool().type_id(), TypeId::of::<Pool<MockClient>>());
             assert_eq!(state.get_db_write_pool().type_id(), TypeId::of::<Pool<MockClient>>());
         }.await;
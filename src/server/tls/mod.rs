@@ -0,0 +1,157 @@
+//! TLS for the HTTP server, with certificates reloaded when their files change, and for Postgres connections
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use anyhow::{anyhow, Context, Error};
+use log::{error, info};
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio::time;
+use tokio_postgres_rustls::MakeRustlsConnect;
+
+/// Interval of checking certificate files for changes
+const RELOAD_INTERVAL_SECONDS: u64 = 60;
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+/// TLS connector for Postgres that verifies servers against the CA certificates in a PEM file
+pub fn postgres_connector(ca_cert: &Path) -> Result<MakeRustlsConnect, Error> {
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(ca_cert).with_context(|| format!("failed to read {}", ca_cert.display()))? {
+        roots.add(cert.with_context(|| format!("failed to parse {}", ca_cert.display()))?)?;
+    }
+    if roots.is_empty() {
+        return Err(anyhow!("no CA certificate in {}", ca_cert.display()));
+    }
+    let config = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(MakeRustlsConnect::new(config))
+}
+
+/// TLS configs of the HTTP server, certificates are served by the given resolver
+pub(crate) fn server_config(resolver: Arc<CertResolver>) -> Result<ServerConfig, Error> {
+    Ok(ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver))
+}
+
+/// Load a certificate chain and its private key from PEM files
+fn load_certified_key(cert: &Path, key: &Path) -> Result<CertifiedKey, Error> {
+    let chain = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("failed to read {}", cert.display()))?;
+    if chain.is_empty() {
+        return Err(anyhow!("no certificate in {}", cert.display()));
+    }
+    let key = PrivateKeyDer::from_pem_file(key).with_context(|| format!("failed to read {}", key.display()))?;
+    let signing_key = provider().key_provider.load_private_key(key)?;
+    Ok(CertifiedKey::new(chain, signing_key))
+}
+
+/// Latest modification time of the given files
+fn modified(paths: &[&Path]) -> Option<SystemTime> {
+    paths.iter().filter_map(|path| path.metadata().and_then(|metadata| metadata.modified()).ok()).max()
+}
+
+/// Serves the certificate loaded last, a newer one replaces it once its files change
+#[derive(Debug)]
+pub(crate) struct CertResolver {
+    cert: PathBuf,
+    key: PathBuf,
+    current: RwLock<(Arc<CertifiedKey>, Option<SystemTime>)>,
+}
+
+impl CertResolver {
+    pub fn new(cert: PathBuf, key: PathBuf) -> Result<Self, Error> {
+        let modified = modified(&[&cert, &key]);
+        let certified_key = load_certified_key(&cert, &key)?;
+        Ok(Self { cert, key, current: RwLock::new((Arc::new(certified_key), modified)) })
+    }
+
+    /// Reload the certificate if its files were modified since the last load, and tell whether it was replaced.
+    /// The current certificate is kept when the new files are broken, e.g. half written.
+    pub fn reload_if_changed(&self) -> Result<bool, Error> {
+        let modified = modified(&[&self.cert, &self.key]);
+        if modified == self.current.read().unwrap().1 {
+            return Ok(false);
+        }
+        let certified_key = load_certified_key(&self.cert, &self.key)?;
+        *self.current.write().unwrap() = (Arc::new(certified_key), modified);
+        Ok(true)
+    }
+
+    /// Check certificate files for changes periodically until the process exits
+    pub async fn watch(self: Arc<Self>) {
+        let mut interval = time::interval(Duration::from_secs(RELOAD_INTERVAL_SECONDS));
+        interval.tick().await; // the first tick completes immediately
+        loop {
+            interval.tick().await;
+            match self.reload_if_changed() {
+                Ok(true) => info!("reloaded TLS certificate from {}", self.cert.display()),
+                Ok(false) => {},
+                Err(e) => error!("failed to reload TLS certificate, keeping the current one, {:#}", e),
+            }
+        }
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().0.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::*;
+
+    fn testdata(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("src/server/tls/testdata").join(name)
+    }
+
+    #[test]
+    fn test_postgres_connector() {
+        assert!(postgres_connector(&testdata("cert.pem")).is_ok());
+        assert!(postgres_connector(&testdata("key.pem")).is_err());
+        assert!(postgres_connector(&testdata("missing.pem")).is_err());
+    }
+
+    #[test]
+    fn test_cert_resolver() {
+        let dir = std::env::temp_dir().join(format!("bookish-eureka-tls-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+        fs::copy(testdata("cert.pem"), &cert).unwrap();
+        fs::copy(testdata("key.pem"), &key).unwrap();
+
+        let resolver = Arc::new(CertResolver::new(cert.clone(), key.clone()).unwrap());
+        assert!(server_config(resolver.clone()).is_ok());
+        assert!(!resolver.reload_if_changed().unwrap());
+
+        // a broken certificate keeps the current one
+        fs::write(&cert, "not a certificate").unwrap();
+        fs::File::options().write(true).open(&cert).unwrap().set_modified(SystemTime::now() + Duration::from_secs(1)).unwrap();
+        let before = resolver.current.read().unwrap().0.clone();
+        assert!(resolver.reload_if_changed().is_err());
+        assert!(Arc::ptr_eq(&before, &resolver.current.read().unwrap().0));
+
+        fs::copy(testdata("cert.pem"), &cert).unwrap();
+        fs::File::options().write(true).open(&cert).unwrap().set_modified(SystemTime::now() + Duration::from_secs(2)).unwrap();
+        assert!(resolver.reload_if_changed().unwrap());
+        assert!(!Arc::ptr_eq(&before, &resolver.current.read().unwrap().0));
+
+        assert!(CertResolver::new(dir.join("missing.pem"), key).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
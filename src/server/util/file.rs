@@ -0,0 +1,36 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use anyhow::{Context, Error};
+use serde::Serialize;
+
+/// Append documents to a file as NDJSON, creating its directory, and flush them to the disk
+pub(crate) fn append_ndjson<T: Serialize>(path: &Path, documents: &[T]) -> Result<(), Error> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    let mut lines = Vec::new();
+    for document in documents {
+        serde_json::to_writer(&mut lines, document)?;
+        lines.push(b'\n');
+    }
+    let mut file = fs::File::options().create(true).append(true).open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    file.write_all(&lines).with_context(|| format!("failed to write {}", path.display()))?;
+    file.sync_all().with_context(|| format!("failed to flush {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_ndjson() {
+        let dir = std::env::temp_dir().join(format!("bookish-eureka-ndjson-{}", std::process::id()));
+        let path = dir.join("bills-2024-11-20.ndjson");
+        append_ndjson(&path, &[serde_json::json!({"id": 1}), serde_json::json!({"id": 2})]).unwrap();
+        append_ndjson(&path, &[serde_json::json!({"id": 3, "items": []})]).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"id\":1}\n{\"id\":2}\n{\"id\":3,\"items\":[]}\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1 +1,2 @@
+pub(crate) mod file;
 pub mod time;
@@ -0,0 +1,392 @@
+//! Webhook subscriptions, each gets the integration events it filters on in a POST signed with its secret
+
+use crate::server::database::pool::GenericTransaction;
+use crate::server::database::pool::GenericRow;
+#[cfg(test)]
+use crate::server::database::pool::DbClient;
+use std::str::FromStr;
+use std::time::Duration;
+use anyhow::{anyhow, Context as _, Error};
+use chrono::{DateTime, Utc};
+use futures_util::future::join_all;
+use hmac::{Hmac, Mac};
+use log::{debug, info, warn};
+use rand::Rng;
+use sha2::Sha256;
+use tokio_postgres::types::ToSql;
+use crate::server::database::pool::WrappedRow;
+use crate::server::model::config::WebhookConfig;
+use crate::server::model::event::{Event, EventKind, OutboxMessage};
+use crate::server::model::webhook::{DeliveryState, Webhook, WebhookDelivery};
+use crate::server::outbox::backoff;
+use crate::server::scheduler::Context;
+use crate::server::state::AppState;
+
+/// Header with the signature of a delivery, `t=<unix seconds>,v1=<hex HMAC-SHA256 of "<t>.<body>">`
+pub(crate) const SIGNATURE_HEADER: &str = "X-Signature";
+
+/// A new secret for signing, 32 random bytes in hex
+pub(crate) fn generate_secret() -> String {
+    to_hex(&rand::thread_rng().gen::<[u8; 32]>())
+}
+
+/// Subscribe a URL to integration events of the given kinds, every kind when empty, returning its id
+pub(crate) async fn create(state: &AppState, url: &str, events: &[EventKind], secret: &str) -> Result<i64, Error> {
+    let Some(conn) = state.get_db_write_pool().acquire(state.get_db_timeout_seconds()).await else {
+        return Err(anyhow!("no connection available"));
+    };
+    let client = conn.client.as_ref().ok_or(anyhow!("client is None"))?;
+    let mut kinds = events.iter().map(ToString::to_string).collect::<Vec<_>>();
+    kinds.sort();
+    kinds.dedup();
+    let params: &[&(dyn ToSql + Sync)] = &[&url, &kinds, &secret];
+    let rows = client.query(r#"
+        INSERT INTO webhook_subscription (url, events, secret, created_at)
+        VALUES ($1, $2, $3, CURRENT_TIMESTAMP)
+        RETURNING id
+    "#, params).await?;
+    rows.first().map(|row| row.get("id")).ok_or(anyhow!("no subscription created"))
+}
+
+/// Subscriptions, oldest first
+pub(crate) async fn list(state: &AppState) -> Result<Vec<Webhook>, Error> {
+    let Some(conn) = state.get_db_read_pool().acquire(state.get_db_timeout_seconds()).await else {
+        return Err(anyhow!("no connection available"));
+    };
+    let client = conn.client.as_ref().ok_or(anyhow!("client is None"))?;
+    let rows = client.query("SELECT id, url, events, created_at FROM webhook_subscription ORDER BY id", &[]).await?;
+    rows.iter().map(|row| Ok(Webhook {
+        id: row.get("id"),
+        url: row.get("url"),
+        events: row.get::<&str, Vec<String>>("events").iter().map(|kind| EventKind::from_str(kind)).collect::<Result<_, _>>().map_err(Error::msg)?,
+        created_at: row.get::<&str, DateTime<Utc>>("created_at"),
+    })).collect()
+}
+
+/// Unsubscribe, with the deliveries of the subscription, telling whether there was one
+pub(crate) async fn remove(state: &AppState, id: i64) -> Result<bool, Error> {
+    let Some(conn) = state.get_db_write_pool().acquire(state.get_db_timeout_seconds()).await else {
+        return Err(anyhow!("no connection available"));
+    };
+    let client = conn.client.as_ref().ok_or(anyhow!("client is None"))?;
+    let params: &[&(dyn ToSql + Sync)] = &[&id];
+    Ok(client.execute("DELETE FROM webhook_subscription WHERE id = $1", params).await? > 0)
+}
+
+/// Latest deliveries of a subscription, newest first, none when there is no such subscription
+pub(crate) async fn deliveries(state: &AppState, id: i64, delivery_state: Option<DeliveryState>, limit: i64) -> Result<Option<Vec<WebhookDelivery>>, Error> {
+    let Some(conn) = state.get_db_read_pool().acquire(state.get_db_timeout_seconds()).await else {
+        return Err(anyhow!("no connection available"));
+    };
+    let client = conn.client.as_ref().ok_or(anyhow!("client is None"))?;
+    let params: &[&(dyn ToSql + Sync)] = &[&id];
+    if client.query("SELECT id FROM webhook_subscription WHERE id = $1", params).await?.is_empty() {
+        return Ok(None);
+    }
+    let params: &[&(dyn ToSql + Sync)] = &[&id, &delivery_state.map(|state| state.to_string()), &limit];
+    let rows = client.query(r#"
+        SELECT id, event_id, kind, state, attempts, next_attempt_at, response_status, last_error, created_at, delivered_at
+        FROM webhook_delivery
+        WHERE subscription_id = $1 AND ($2::varchar IS NULL OR state = $2)
+        ORDER BY id DESC
+        LIMIT $3
+    "#, params).await?;
+    rows.iter().map(|row| Ok(WebhookDelivery {
+        id: row.get("id"),
+        event_id: row.get("event_id"),
+        kind: EventKind::from_str(row.get("kind")).map_err(Error::msg)?,
+        state: DeliveryState::from_str(row.get("state")).map_err(Error::msg)?,
+        attempts: row.get("attempts"),
+        next_attempt_at: row.get::<&str, Option<DateTime<Utc>>>("next_attempt_at"),
+        response_status: row.get("response_status"),
+        error: row.get("last_error"),
+        created_at: row.get::<&str, DateTime<Utc>>("created_at"),
+        delivered_at: row.get::<&str, Option<DateTime<Utc>>>("delivered_at"),
+    })).collect::<Result<_, Error>>().map(Some)
+}
+
+/// Queue a delivery of outbox events for every subscription filtering on their kind, within the transaction relaying them.
+/// An event relayed again is not queued twice for a subscription.
+pub(crate) async fn fan_out<R, T>(txn: &T, event_ids: &[i64]) -> Result<u64, tokio_postgres::Error>
+where
+    R: GenericRow,
+    WrappedRow<R>: GenericRow,
+    T: GenericTransaction<R>,
+{
+    let pending = DeliveryState::Pending.to_string();
+    let params: &[&(dyn ToSql + Sync)] = &[&event_ids, &pending];
+    txn.execute(r#"
+        INSERT INTO webhook_delivery (subscription_id, event_id, kind, payload, event_created_at, state, created_at, next_attempt_at)
+        SELECT s.id, o.id, o.kind, o.payload, o.created_at, $2, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP
+        FROM outbox o
+        JOIN webhook_subscription s ON cardinality(s.events) = 0 OR o.kind = ANY(s.events)
+        WHERE o.id = ANY($1)
+        ON CONFLICT (subscription_id, event_id) DO NOTHING
+    "#, params).await
+}
+
+/// Signature of a body at a time, see [SIGNATURE_HEADER]
+pub(crate) fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut signed = format!("{}.", timestamp).into_bytes();
+    signed.extend_from_slice(body);
+    format!("t={},v1={}", timestamp, hmac_sha256(secret.as_bytes(), &signed))
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(message);
+    to_hex(&mac.finalize().into_bytes())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// A delivery claimed for an attempt, with the error of its payload when it no longer reads as an event
+struct Due {
+    id: i64,
+    event_id: i64,
+    attempts: i32,
+    url: String,
+    secret: String,
+    message: Result<OutboxMessage, String>,
+}
+
+/// Outcome of an attempt, the status when the webhook answered and the error unless it answered with a 2xx status
+struct Attempt {
+    status: Option<u16>,
+    error: Option<String>,
+}
+
+/// Posts due deliveries to their webhooks, retrying failed ones with exponential backoff until the last attempt
+pub(crate) struct Dispatcher {
+    client: reqwest::Client,
+    timeout: Duration,
+    max_attempts: u32,
+    retry_base: Duration,
+    retry_max: Duration,
+}
+
+impl Dispatcher {
+    pub fn new(config: &WebhookConfig) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            timeout: Duration::from_secs(config.timeout_seconds),
+            max_attempts: config.max_attempts,
+            retry_base: Duration::from_secs(config.retry_base_seconds),
+            retry_max: Duration::from_secs(config.retry_max_seconds),
+        }
+    }
+
+    /// Attempt due deliveries a batch at a time, until none is left due or the shutdown starts, returning how many were delivered
+    pub async fn run(&self, state: &AppState, context: &Context) -> Result<u64, Error> {
+        let delivered = context.in_batches("webhooks delivered", || self.dispatch_batch(state, context.batch_size)).await?;
+        if delivered > 0 {
+            info!("delivered {} webhooks", delivered);
+        } else {
+            debug!("no webhook delivered, continue to sleep");
+        }
+        Ok(delivered)
+    }
+
+    /// Claim up to a batch of due deliveries, post them at once and record the outcomes, returning how many were due and delivered.
+    /// Claimed deliveries are not due for a while, so that other instances leave them, and ones not recorded are attempted again then.
+    async fn dispatch_batch(&self, state: &AppState, batch_size: u32) -> Result<(u64, u64), Error> {
+        let due = self.claim(state, batch_size).await.context("failed to claim due deliveries")?;
+        let attempts = join_all(due.iter().map(|due| self.attempt(due))).await;
+
+        let Some(conn) = state.get_db_write_pool().acquire(state.get_db_timeout_seconds()).await else {
+            return Err(anyhow!("no connection available"));
+        };
+        let client = conn.client.as_ref().ok_or(anyhow!("client is None"))?;
+        let mut delivered = 0;
+        for (due, attempt) in due.iter().zip(attempts) {
+            let attempts = u32::try_from(due.attempts).unwrap_or_default() + 1;
+            let now = Utc::now();
+            let (delivery_state, next_attempt_at, delivered_at) = match &attempt.error {
+                None => (DeliveryState::Delivered, None, Some(now)),
+                Some(_) if attempts >= self.max_attempts => (DeliveryState::Failed, None, None),
+                Some(_) => (DeliveryState::Pending, Some(now + backoff(self.retry_base, self.retry_max, attempts - 1)), None),
+            };
+            match (&attempt.error, delivery_state) {
+                (None, _) => delivered += 1,
+                (Some(e), DeliveryState::Failed) => warn!("gave up delivering event {} to {} after {} attempts, {}", due.event_id, due.url, attempts, e),
+                (Some(e), _) => warn!("failed to deliver event {} to {}, retrying at {:?}, {}", due.event_id, due.url, next_attempt_at, e),
+            }
+            let params: &[&(dyn ToSql + Sync)] = &[
+                &due.id, &delivery_state.to_string(), &attempt.status.map(|status| status as i16), &attempt.error, &next_attempt_at, &delivered_at,
+            ];
+            client.execute(r#"
+                UPDATE webhook_delivery
+                SET state = $2, attempts = attempts + 1, response_status = $3, last_error = $4, next_attempt_at = $5, delivered_at = $6
+                WHERE id = $1
+            "#, params).await.context("failed to record a delivery")?;
+        }
+        Ok((due.len() as u64, delivered))
+    }
+
+    /// Take due deliveries for the time their attempts may take, oldest first.
+    /// A payload which no longer reads as an event fails the attempt of its delivery only.
+    async fn claim(&self, state: &AppState, batch_size: u32) -> Result<Vec<Due>, Error> {
+        let Some(conn) = state.get_db_write_pool().acquire(state.get_db_timeout_seconds()).await else {
+            return Err(anyhow!("no connection available"));
+        };
+        let client = conn.client.as_ref().ok_or(anyhow!("client is None"))?;
+        let pending = DeliveryState::Pending.to_string();
+        let claim_seconds = (self.timeout * 2).as_secs_f64();
+        let params: &[&(dyn ToSql + Sync)] = &[&pending, &i64::from(batch_size), &claim_seconds];
+        let rows = client.query(r#"
+            WITH due AS (
+                SELECT id
+                FROM webhook_delivery
+                WHERE state = $1 AND next_attempt_at <= CURRENT_TIMESTAMP
+                ORDER BY id
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            )
+            UPDATE webhook_delivery d
+            SET next_attempt_at = CURRENT_TIMESTAMP + make_interval(secs => $3)
+            FROM due, webhook_subscription s
+            WHERE d.id = due.id AND s.id = d.subscription_id
+            RETURNING d.id, d.event_id, d.payload, d.event_created_at, d.attempts, s.url, s.secret
+        "#, params).await?;
+        Ok(rows.iter().map(|row| Due {
+            id: row.get("id"),
+            event_id: row.get("event_id"),
+            attempts: row.get("attempts"),
+            url: row.get("url"),
+            secret: row.get("secret"),
+            message: serde_json::from_value::<Event>(row.get("payload"))
+                .map(|event| OutboxMessage {
+                    id: row.get("event_id"),
+                    created_at: row.get::<&str, DateTime<Utc>>("event_created_at"),
+                    event,
+                })
+                .map_err(|e| format!("malformed payload, {}", e)),
+        }).collect())
+    }
+
+    /// Post a delivery signed with the secret of its subscription, the event id goes in `X-Event-Id` and the delivery id in `X-Delivery-Id`
+    async fn attempt(&self, due: &Due) -> Attempt {
+        let message = match &due.message {
+            Ok(message) => message,
+            Err(e) => return Attempt { status: None, error: Some(e.clone()) },
+        };
+        let body = match serde_json::to_vec(message) {
+            Ok(body) => body,
+            Err(e) => return Attempt { status: None, error: Some(e.to_string()) },
+        };
+        let signature = sign(&due.secret, Utc::now().timestamp(), &body);
+        let response = self.client.post(&due.url)
+            .timeout(self.timeout)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("X-Event-Id", due.event_id)
+            .header("X-Delivery-Id", due.id)
+            .header(SIGNATURE_HEADER, signature)
+            .body(body)
+            .send().await;
+        match response {
+            Ok(response) if response.status().is_success() => Attempt { status: Some(response.status().as_u16()), error: None },
+            Ok(response) => Attempt { status: Some(response.status().as_u16()), error: Some(format!("responded {}", response.status())) },
+            Err(e) => Attempt { status: None, error: Some(format!("{:#}", Error::from(e))) },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::oneshot;
+    use super::*;
+
+    /// A local HTTP server answering one request with the status, handing the request over
+    async fn stand_in(status: u16) -> (String, oneshot::Receiver<(String, Vec<u8>)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/events", listener.local_addr().unwrap());
+        let (sender, receiver) = oneshot::channel();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![];
+            let mut buffer = [0; 4096];
+            let (head, body) = loop {
+                let read = stream.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") else {
+                    continue;
+                };
+                let head = String::from_utf8_lossy(&request[..end]).to_lowercase();
+                let length = head.lines()
+                    .find_map(|line| line.strip_prefix("content-length:"))
+                    .map_or(0, |length| length.trim().parse::<usize>().unwrap());
+                if request.len() >= end + 4 + length {
+                    break (head, request[end + 4..end + 4 + length].to_vec());
+                }
+            };
+            stream.write_all(format!("HTTP/1.1 {} Stand-in\r\ncontent-length: 0\r\nconnection: close\r\n\r\n", status).as_bytes()).await.unwrap();
+            let _ = sender.send((head, body));
+        });
+        (url, receiver)
+    }
+
+    fn due(url: String) -> Due {
+        Due {
+            id: 3,
+            event_id: 42,
+            attempts: 0,
+            url,
+            secret: "secret".to_string(),
+            message: Ok(OutboxMessage { id: 42, created_at: Utc::now(), event: Event::BillClosed { bill_id: 1, table_id: 2, closed_at: Utc::now() } }),
+        }
+    }
+
+    #[test]
+    fn test_sign() {
+        // RFC 4231 test case 2
+        assert_eq!(hmac_sha256(b"Jefe", b"what do ya want for nothing?"), "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+        assert_eq!(sign("Jefe", 1700000000, b"{}"), format!("t=1700000000,v1={}", hmac_sha256(b"Jefe", b"1700000000.{}")));
+        assert_eq!(generate_secret().len(), 64);
+        assert_ne!(generate_secret(), generate_secret());
+    }
+
+    #[tokio::test]
+    async fn test_attempt() {
+        let dispatcher = Dispatcher::new(&WebhookConfig::default());
+        let (url, request) = stand_in(204).await;
+        let attempt = dispatcher.attempt(&due(url)).await;
+        assert_eq!((attempt.status, attempt.error), (Some(204), None));
+
+        let (head, body) = request.await.unwrap();
+        assert!(head.starts_with("post /events http/1.1"));
+        assert!(head.contains("x-event-id: 42") && head.contains("x-delivery-id: 3"));
+        let message = serde_json::from_slice::<OutboxMessage>(&body).unwrap();
+        assert_eq!((message.id, message.event.kind()), (42, EventKind::BillClosed));
+        // the receiver checks the signature with the shared secret
+        let signature = head.lines().find_map(|line| line.strip_prefix("x-signature: ")).unwrap();
+        let timestamp = signature.split(',').next().unwrap().strip_prefix("t=").unwrap().parse::<i64>().unwrap();
+        assert_eq!(signature, sign("secret", timestamp, &body));
+
+        let (url, _request) = stand_in(500).await;
+        let attempt = dispatcher.attempt(&due(url)).await;
+        assert_eq!(attempt.status, Some(500));
+        assert!(attempt.error.unwrap().contains("500"));
+
+        // a payload which no longer reads fails its own attempt without posting it
+        let (url, mut request) = stand_in(204).await;
+        let attempt = dispatcher.attempt(&Due { message: Err("malformed payload, unknown variant".to_string()), ..due(url) }).await;
+        assert_eq!((attempt.status, attempt.error.as_deref()), (None, Some("malformed payload, unknown variant")));
+        assert!(request.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_dispatch() {
+        let state = AppState::mock().await;
+        let context = Context { batch_size: 100, cancel_token: Default::default() };
+        assert_eq!(Dispatcher::new(&WebhookConfig::default()).run(&state, &context).await.unwrap(), 0);
+        assert!(list(&state).await.unwrap().is_empty());
+        assert!(deliveries(&state, 1, None, 50).await.unwrap().is_none());
+        assert!(!remove(&state, 1).await.unwrap());
+        // the connection is back to the pool
+        assert!(state.get_db_write_pool().acquire(1).await.is_some());
+    }
+}